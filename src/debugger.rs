@@ -0,0 +1,627 @@
+use std::io::Write;
+use crate::processor::cache_hierarchy::CacheLevel;
+use crate::processor::cache_view::CacheView;
+use crate::processor::console::{Console, StdConsole};
+use crate::processor::instruction::Instruction;
+use crate::processor::memory::Size;
+use crate::processor::register_dump::RegisterView;
+use crate::processor::registers::Register;
+use crate::processor::watchpoint::WatchKind;
+use crate::processor::{Processor, Status};
+
+/// Upper bound on cycles spent waiting for a single instruction to commit
+const MAX_CYCLES_PER_INSTRUCTION: u32 = 1000;
+
+/// Number of cycles recorded for reverse execution
+const HISTORY_LIMIT: usize = 100_000;
+
+/// Recent accesses shown under each cache by `info cache`
+const CACHE_LOG_LINES: usize = 10;
+
+/// Most bytes one `examine` shows, longer ranges are cut short
+const EXAMINE_LIMIT: u32 = 0x1000;
+
+/// Interactive front end that drives a [`Processor`] one command at a time
+pub struct Debugger {
+    processor: Processor,
+    breakpoints: Vec<u32>,
+    last_command: String
+}
+
+enum Format {
+    /// Hex dump grouped into bytes, halfwords or words
+    Hex(Size),
+    Ascii,
+    Disassembly
+}
+
+impl Debugger {
+    pub fn new(mut processor: Processor) -> Self {
+        processor.enable_history(HISTORY_LIMIT);
+        Self {
+            processor,
+            breakpoints: Vec::new(),
+            last_command: String::new()
+        }
+    }
+
+    pub fn get_processor(&self) -> &Processor {
+        &self.processor
+    }
+
+    pub fn get_processor_mut(&mut self) -> &mut Processor {
+        &mut self.processor
+    }
+
+    pub fn get_breakpoints(&self) -> &[u32] {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, address: u32) -> usize {
+        self.breakpoints.push(address);
+        self.breakpoints.len()
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u32) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints.retain(|breakpoint| *breakpoint != address);
+        self.breakpoints.len() != count
+    }
+
+    /// Reads commands from stdin until `quit` or end of input. Lines come
+    /// through [`StdConsole`] so a mapped keyboard device cannot swallow them.
+    pub fn run(&mut self) {
+        let mut stdin = StdConsole;
+        loop {
+            print!("(mips) ");
+            let _ = std::io::stdout().flush();
+            let line = match stdin.read_line() {
+                Some(line) => line,
+                None => break
+            };
+            let line = line.trim();
+            if matches!(line, "q" | "quit" | "exit") {
+                break;
+            }
+            match self.execute(line) {
+                Ok(output) => print!("{}", output),
+                Err(message) => println!("Error: {}", message)
+            }
+        }
+    }
+
+    /// Runs a single command and returns what it printed. An empty line repeats
+    /// the previous command.
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let line = if line.trim().is_empty() {
+            self.last_command.clone()
+        } else {
+            line.trim().to_string()
+        };
+        self.last_command = line.clone();
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(String::new())
+        };
+        let arguments: Vec<&str> = words.collect();
+        match command {
+            "s" | "step" => {
+                let count = self.parse_count(arguments.first())?;
+                let mut status = Status::Running;
+                for _ in 0..count {
+                    status = self.processor.cycle();
+                    if status != Status::Running {
+                        break;
+                    }
+                }
+                Ok(self.location(status))
+            }
+            "si" | "stepi" => {
+                let count = self.parse_count(arguments.first())?;
+                let mut status = Status::Running;
+                for _ in 0..count {
+                    status = self.step_instruction();
+                    if status != Status::Running {
+                        break;
+                    }
+                }
+                Ok(self.location(status))
+            }
+            "c" | "continue" => Ok(self.continue_execution()),
+            "rs" | "reverse-step" => {
+                let count = self.parse_count(arguments.first())?;
+                for _ in 0..count {
+                    if self.processor.step_back().is_none() {
+                        let location = self.location(Status::Running);
+                        return Ok(format!("{}{}", self.history_end(), location));
+                    }
+                }
+                Ok(self.location(self.processor.get_status()))
+            }
+            "rsi" | "reverse-stepi" => {
+                let count = self.parse_count(arguments.first())?;
+                for _ in 0..count {
+                    if !self.step_back_instruction() {
+                        let location = self.location(Status::Running);
+                        return Ok(format!("{}{}", self.history_end(), location));
+                    }
+                }
+                Ok(self.location(self.processor.get_status()))
+            }
+            "rc" | "reverse-continue" => Ok(self.reverse_continue()),
+            "b" | "break" => {
+                let target = arguments.first().ok_or("break needs an address or label")?;
+                let address = self.parse_value(target)?;
+                let number = self.add_breakpoint(address);
+                Ok(format!("Breakpoint {} at {:#010x}\n", number, address))
+            }
+            "d" | "delete" => {
+                let number = self.parse_count(arguments.first())?;
+                if number == 0 || number as usize > self.breakpoints.len() {
+                    return Err(format!("No breakpoint number {}", number));
+                }
+                let address = self.breakpoints.remove(number as usize - 1);
+                Ok(format!("Deleted breakpoint at {:#010x}\n", address))
+            }
+            "watch" | "rwatch" | "awatch" => {
+                let kind = match command {
+                    "watch" => WatchKind::Write,
+                    "rwatch" => WatchKind::Read,
+                    _ => WatchKind::Access
+                };
+                let target = arguments.first().ok_or("watch needs an address or register")?;
+                if target.starts_with('$') {
+                    let register = parse_register(target)?;
+                    self.processor
+                        .get_registers_mut()
+                        .add_watchpoint(register, kind);
+                    return Ok(format!("Watching ${} for {}\n", register.name(), kind));
+                }
+                let address = self.parse_value(target)?;
+                let length = match arguments.get(1) {
+                    Some(length) => self.parse_value(length)?,
+                    None => 4
+                };
+                self.processor
+                    .get_memory_mut()
+                    .add_watchpoint(address, length, kind);
+                Ok(format!(
+                    "Watching {} bytes at {:#010x} for {}\n",
+                    length, address, kind
+                ))
+            }
+            "unwatch" => {
+                let target = arguments.first().ok_or("unwatch needs an address or register")?;
+                let removed = if target.starts_with('$') {
+                    let register = parse_register(target)?;
+                    self.processor
+                        .get_registers_mut()
+                        .remove_watchpoint(register)
+                } else {
+                    let address = self.parse_value(target)?;
+                    self.processor
+                        .get_memory_mut()
+                        .remove_watchpoint(address)
+                };
+                if !removed {
+                    return Err(format!("No watchpoint on {}", target));
+                }
+                Ok(format!("Removed watchpoint on {}\n", target))
+            }
+            "i" | "info" => match arguments.first() {
+                Some(&"b") | Some(&"break") | Some(&"breakpoints") => {
+                    Ok(self.format_breakpoints())
+                }
+                Some(&"w") | Some(&"watch") | Some(&"watchpoints") => {
+                    Ok(self.format_watchpoints())
+                }
+                Some(&"r") | Some(&"registers") | None => {
+                    let view = parse_view(arguments.get(1))?;
+                    Ok(self.processor.register_dump(view).to_string())
+                }
+                Some(&"s") | Some(&"stats") => Ok(self.format_stats()),
+                Some(&"cache") => self.format_caches(arguments.get(1).copied()),
+                Some(&"m") | Some(&"map") => Ok(self.format_map()),
+                Some(&"tlb") => match self.processor.get_memory().get_tlb() {
+                    Some(tlb) => Ok(tlb.to_string()),
+                    None => Err("No TLB".to_string())
+                },
+                Some(&"c") | Some(&"changed") => {
+                    let view = parse_view(arguments.get(1))?;
+                    Ok(self.processor.register_diff(view).to_string())
+                }
+                Some(other) => Err(format!("Unknown info topic {}", other))
+            },
+            "p" | "print" => match arguments.first() {
+                Some(name) => {
+                    let register = parse_register(name)?;
+                    let value = self.processor.get_register(register);
+                    Ok(format!(
+                        "${} = {:#010x} ({})\n",
+                        register.name(),
+                        value,
+                        value as i32
+                    ))
+                }
+                None => Ok(self.processor.register_dump(RegisterView::Hex).to_string())
+            },
+            "set" => {
+                if arguments.len() != 2 {
+                    return Err("set needs a register and a value".to_string());
+                }
+                let register = parse_register(arguments[0])?;
+                let value = self.parse_value(arguments[1])?;
+                self.processor.set_register(register, value);
+                Ok(format!("${} = {:#010x}\n", register.name(), value))
+            }
+            "x" | "examine" => {
+                let target = arguments.first().ok_or("examine needs an address")?;
+                let address = self.parse_value(target)?;
+                let length = match arguments.get(1) {
+                    Some(length) => self.parse_value(length)?,
+                    None => 16
+                };
+                let format = match arguments.get(2).copied() {
+                    None | Some("hex") | Some("x") | Some("w") => Format::Hex(Size::Word),
+                    Some("h") => Format::Hex(Size::Halfword),
+                    Some("b") => Format::Hex(Size::Byte),
+                    Some("ascii") | Some("a") => Format::Ascii,
+                    Some("dis") | Some("i") => Format::Disassembly,
+                    Some(other) => return Err(format!("Unknown format {}", other))
+                };
+                Ok(self.examine(address, length.min(EXAMINE_LIMIT), format))
+            }
+            "pipe" | "pipeline" => {
+                let mut output = format!(
+                    "{}{}{}{}",
+                    self.processor.get_if_id_buffer(),
+                    self.processor.get_id_ex_buffer(),
+                    self.processor.get_ex_mem_buffer(),
+                    self.processor.get_mem_wb_buffer()
+                );
+                if let Some(cause) = self.processor.get_stall_cause() {
+                    output += &format!("Stalled on a {}\n", cause);
+                }
+                Ok(output)
+            }
+            "save" => {
+                let path = arguments.first().ok_or("save needs a file name")?;
+                self.processor
+                    .save_snapshot_file(path)
+                    .map_err(|error| error.to_string())?;
+                Ok(format!("Saved snapshot to {}\n", path))
+            }
+            "restore" => {
+                let path = arguments.first().ok_or("restore needs a file name")?;
+                self.processor
+                    .restore_snapshot_file(path)
+                    .map_err(|error| error.to_string())?;
+                Ok(self.location(self.processor.get_status()))
+            }
+            "h" | "help" => Ok(HELP.to_string()),
+            _ => Err(format!("Unknown command {}, try help", command))
+        }
+    }
+
+    /// Cycles until an instruction commits or something stops the processor
+    pub fn step_instruction(&mut self) -> Status {
+        for _ in 0..MAX_CYCLES_PER_INSTRUCTION {
+            let status = self.processor.cycle();
+            if status != Status::Running || self.processor.get_last_commit().is_some() {
+                return status;
+            }
+        }
+        Status::Running
+    }
+
+    /// Cycles until the processor stops or is about to fetch from a breakpoint.
+    /// Returns the status along with the number of the breakpoint reached, if any.
+    pub fn resume(&mut self) -> (Status, Option<usize>) {
+        self.resume_for(u64::MAX)
+    }
+
+    /// Like [`Debugger::resume`] but gives up after `max_cycles`, returning
+    /// `Running` with no breakpoint. Only a PC moving onto a breakpoint stops,
+    /// so a stall holding it on the one just reached does not.
+    pub fn resume_for(&mut self, max_cycles: u64) -> (Status, Option<usize>) {
+        let mut previous = self.processor.get_program_counter();
+        for _ in 0..max_cycles {
+            let status = self.processor.cycle();
+            if status != Status::Running {
+                return (status, None);
+            }
+            let pc = self.processor.get_program_counter();
+            if pc == previous {
+                continue;
+            }
+            previous = pc;
+            if let Some(index) = self.breakpoints.iter().position(|address| *address == pc) {
+                return (status, Some(index + 1));
+            }
+        }
+        (Status::Running, None)
+    }
+
+    fn continue_execution(&mut self) -> String {
+        match self.resume() {
+            (status, Some(number)) => {
+                format!("Breakpoint {} reached\n{}", number, self.location(status))
+            }
+            (status, None) => self.location(status)
+        }
+    }
+
+    /// Why stepping back stopped once the processor ran out of history
+    fn history_end(&self) -> &'static str {
+        if self.processor.is_history_cut_by_device() {
+            DEVICE_HISTORY
+        } else {
+            NO_HISTORY
+        }
+    }
+
+    /// Steps back until the previous instruction commit. Returns false if the
+    /// recorded history ran out first.
+    fn step_back_instruction(&mut self) -> bool {
+        loop {
+            if self.processor.step_back().is_none() {
+                return false;
+            }
+            if self.processor.get_last_commit().is_some() {
+                return true;
+            }
+        }
+    }
+
+    /// Steps back until the processor is about to fetch from a breakpoint or a
+    /// cycle that hit a watchpoint has been undone. The cycle undone first is
+    /// not checked so repeating the command moves on to the previous stop.
+    fn reverse_continue(&mut self) -> String {
+        let mut first = true;
+        loop {
+            let hit = match self.processor.step_back() {
+                Some(hit) => hit,
+                None => return format!("{}{}", self.history_end(), self.location(Status::Running))
+            };
+            if let Some(hit) = hit.filter(|_| !first) {
+                return self.location(Status::Watchpoint(hit));
+            }
+            first = false;
+            let pc = self.processor.get_program_counter();
+            if let Some(index) = self.breakpoints.iter().position(|address| *address == pc) {
+                return format!(
+                    "Breakpoint {} reached\n{}",
+                    index + 1,
+                    self.location(Status::Running)
+                );
+            }
+        }
+    }
+
+    fn location(&self, status: Status) -> String {
+        let processor = &self.processor;
+        let pc = processor.get_program_counter();
+        let instruction = Instruction::load(processor.get_memory().peek_word(pc));
+        let mut output = format!("cycle {} ", processor.get_cycle_count());
+        match processor.get_symbols().nearest(pc) {
+            Some((name, 0)) => output += &format!("{:#010x} <{}>", pc, name),
+            Some((name, offset)) => output += &format!("{:#010x} <{}+{}>", pc, name, offset),
+            None => output += &format!("{:#010x}", pc)
+        }
+        output += &format!(": {}\n", instruction.disassemble());
+        match status {
+            Status::Running => {}
+            Status::Exited(code) => output += &format!("Program exited with status {}\n", code),
+            Status::Faulted(fault) => output += &format!("Program faulted: {}\n", fault),
+            Status::Exception(exception) => {
+                output += &format!("Program stopped on {}\n", exception)
+            }
+            Status::Watchpoint(hit) => output += &format!("Watchpoint hit: {}\n", hit)
+        }
+        output
+    }
+
+    fn format_watchpoints(&self) -> String {
+        let memory = self.processor.get_memory().get_watchpoints();
+        let registers = self.processor.get_registers().get_watchpoints();
+        if memory.is_empty() && registers.is_empty() {
+            return "No watchpoints\n".to_string();
+        }
+        let mut output = String::new();
+        for watchpoint in registers {
+            output += &format!("${} {}\n", watchpoint.register.name(), watchpoint.kind);
+        }
+        for watchpoint in memory {
+            output += &format!(
+                "{:#010x}+{} {}\n",
+                watchpoint.start, watchpoint.length, watchpoint.kind
+            );
+        }
+        output
+    }
+
+    fn format_breakpoints(&self) -> String {
+        if self.breakpoints.is_empty() {
+            return "No breakpoints\n".to_string();
+        }
+        let mut output = String::new();
+        for (i, address) in self.breakpoints.iter().enumerate() {
+            output += &format!("{}: {:#010x}", i + 1, address);
+            if let Some(name) = self.processor.get_symbols().name(*address) {
+                output += &format!(" <{}>", name);
+            }
+            output += "\n";
+        }
+        output
+    }
+
+    fn format_stats(&self) -> String {
+        format!(
+            "{}{}",
+            self.processor.get_pipeline_stats(),
+            self.processor.get_caches()
+        )
+    }
+
+    /// Every segment of the address space, marking the ones devices back
+    fn format_map(&self) -> String {
+        let memory = self.processor.get_memory();
+        let mut output = String::new();
+        for segment in memory.get_segments() {
+            if segment.is_empty() {
+                continue;
+            }
+            output += &segment.to_string();
+            if memory.is_device(segment.start) {
+                output += " device";
+            }
+            output += "\n";
+        }
+        output
+    }
+
+    /// Contents and recent accesses of one cache, or of every cache present
+    fn format_caches(&self, name: Option<&str>) -> Result<String, String> {
+        let levels = match name {
+            Some(name) => {
+                let level = CacheLevel::from_name(name)
+                    .ok_or(format!("Unknown cache {}, expected i, d or l2", name))?;
+                if self.processor.get_caches().get(level).is_none() {
+                    return Err(format!("No {}", level));
+                }
+                vec![level]
+            }
+            None => CacheLevel::ALL.to_vec()
+        };
+        let mut output = String::new();
+        for level in levels {
+            let cache = match self.processor.get_caches().get(level) {
+                Some(cache) => cache,
+                None => continue
+            };
+            output += &format!("{}:\n{}", level, CacheView::new(cache));
+            let log: Vec<_> = cache.get_access_log().collect();
+            for access in &log[log.len().saturating_sub(CACHE_LOG_LINES)..] {
+                output += &format!("  {}\n", access);
+            }
+        }
+        if output.is_empty() {
+            return Err("No caches configured".to_string());
+        }
+        Ok(output)
+    }
+
+    fn examine(&self, address: u32, length: u32, format: Format) -> String {
+        let memory = self.processor.get_memory();
+        let mut output = String::new();
+        match format {
+            Format::Hex(grouping) => {
+                output += &self.processor.memory_dump(address, length, grouping).to_string();
+            }
+            Format::Ascii => {
+                let text: String = (0..length)
+                    .map(|i| memory.peek_byte(address.wrapping_add(i)))
+                    .map(|byte| match byte {
+                        0x20..=0x7E => byte as char,
+                        _ => '.'
+                    })
+                    .collect();
+                output += &format!("{:#010x}: {}\n", address, text);
+            }
+            Format::Disassembly => {
+                for i in (0..length).step_by(4) {
+                    let current = address.wrapping_add(i);
+                    let instruction = Instruction::load(memory.peek_word(current));
+                    let marker = if current == self.processor.get_program_counter() {
+                        "=>"
+                    } else {
+                        "  "
+                    };
+                    output += &format!(
+                        "{} {:#010x}: {:08x}  {}\n",
+                        marker,
+                        current,
+                        instruction.word,
+                        instruction.disassemble()
+                    );
+                }
+            }
+        }
+        output
+    }
+
+    fn parse_count(&self, argument: Option<&&str>) -> Result<u32, String> {
+        match argument {
+            Some(argument) => self.parse_value(argument),
+            None => Ok(1)
+        }
+    }
+
+    /// Accepts decimal, `0x` hex, negative numbers, labels and `$register` values
+    fn parse_value(&self, text: &str) -> Result<u32, String> {
+        if text.starts_with('$') {
+            return Ok(self.processor.get_register(parse_register(text)?));
+        }
+        if let Some(hex) = text.strip_prefix("0x") {
+            return u32::from_str_radix(hex, 16).map_err(|error| error.to_string());
+        }
+        if let Ok(value) = text.parse::<i64>() {
+            return Ok(value as u32);
+        }
+        self.processor
+            .get_symbols()
+            .address(text)
+            .ok_or(format!("Unknown label {}", text))
+    }
+}
+
+fn parse_register(name: &str) -> Result<Register, String> {
+    Register::from_name(name).ok_or(format!("Unknown register {}", name))
+}
+
+fn parse_view(name: Option<&&str>) -> Result<RegisterView, String> {
+    match name {
+        Some(name) => {
+            RegisterView::from_name(name).ok_or(format!("Unknown register view {}", name))
+        }
+        None => Ok(RegisterView::Hex)
+    }
+}
+
+const NO_HISTORY: &str = "Reached the start of the recorded history\n";
+const DEVICE_HISTORY: &str = "Cannot step back over the previous cycle, it accessed a device\n";
+
+const HELP: &str = "\
+step [n]                   run n cycles (s)
+stepi [n]                  run until n instructions commit (si)
+continue                   run until a breakpoint or the program halts (c)
+reverse-step [n]           undo n cycles (rs)
+reverse-stepi [n]          undo until n earlier instructions commit (rsi)
+reverse-continue           undo until a breakpoint or watchpoint (rc)
+break <address|label>      stop before fetching from an address (b)
+delete <n>                 remove breakpoint n (d)
+watch <address|register> [length]
+                           stop when it is written, rwatch for reads,
+                           awatch for either
+unwatch <address|register> remove watchpoints
+info breakpoints|watchpoints|registers
+                           list breakpoints, watchpoints or registers (i)
+info stats                 show cycle, stall and cache statistics
+info map                   list the segments and mapped devices
+info cache [i|d|l2]        show the sets of a cache and its last accesses
+info tlb                   list the valid TLB entries
+info registers|changed [hex|signed|unsigned|all]
+                           show every register or those the last cycle
+                           changed
+print [register]           show a register or all of them (p)
+set <register> <value>     write a register
+examine <address> [length] [hex|b|h|w|ascii|dis]
+                           show memory (x), hex groups words unless
+                           b or h asks for bytes or halfwords, at most
+                           4096 bytes
+pipeline                   show the pipeline buffers (pipe)
+save <file>                write a snapshot of the machine state
+restore <file>             load a snapshot written by save
+quit                       leave the debugger (q)
+";
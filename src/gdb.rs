@@ -0,0 +1,562 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use log::{debug, info, warn};
+use num_traits::FromPrimitive;
+use crate::debugger::Debugger;
+use crate::processor::cp0::{BAD_VADDR, CAUSE, COMPARE, COUNT, EPC, STATUS};
+use crate::processor::registers::Register;
+use crate::processor::watchpoint::{WatchKind, WatchTarget};
+use crate::processor::Status;
+
+/// Register numbers in the order GDB's MIPS port lays out the `g` packet
+const STATUS_REGISTER: usize = 32;
+const LO_REGISTER: usize = 33;
+const HI_REGISTER: usize = 34;
+const BAD_VADDR_REGISTER: usize = 35;
+const CAUSE_REGISTER: usize = 36;
+const PC_REGISTER: usize = 37;
+const FIRST_FLOAT_REGISTER: usize = 38;
+const FCSR_REGISTER: usize = 70;
+const FIR_REGISTER: usize = 71;
+/// Past the standard layout, only known to GDB through the target description
+const COUNT_REGISTER: usize = 72;
+const COMPARE_REGISTER: usize = 73;
+const EPC_REGISTER: usize = 74;
+const REGISTER_COUNT: usize = 75;
+
+/// Largest packet GDB is told it may send, replies are kept to it too
+const PACKET_SIZE: usize = 0x1000;
+/// Cycles run between checks for an interrupt from GDB while continuing
+const CONTINUE_SLICE: u64 = 10_000;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// A byte stream GDB talks over
+pub trait Connection: Read + Write {
+    /// Switches reads between waiting for data and failing with
+    /// [`ErrorKind::WouldBlock`]. Streams that never block can keep the default.
+    fn set_nonblocking(&mut self, _nonblocking: bool) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for TcpStream {
+    fn set_nonblocking(&mut self, nonblocking: bool) -> std::io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+/// Serves the GDB remote serial protocol over `stream`, mapping register,
+/// memory, breakpoint and stepping packets onto a [`Debugger`]
+pub struct GdbStub<S: Connection> {
+    debugger: Debugger,
+    stream: S,
+    buffer: Vec<u8>,
+    position: usize,
+    no_ack: bool,
+    last_status: Status,
+    /// Whether the last stop was GDB interrupting a continue
+    interrupted: bool
+}
+
+/// Waits for one GDB connection on `address` and serves it until GDB detaches
+/// or kills the session, then hands the debugger back
+pub fn listen<A: ToSocketAddrs>(debugger: Debugger, address: A) -> std::io::Result<Debugger> {
+    let listener = TcpListener::bind(address)?;
+    info!("Waiting for gdb on {}", listener.local_addr()?);
+    let (stream, peer) = listener.accept()?;
+    info!("gdb connected from {}", peer);
+    stream.set_nodelay(true)?;
+    let mut stub = GdbStub::new(debugger, stream);
+    stub.serve()?;
+    Ok(stub.into_debugger())
+}
+
+impl<S: Connection> GdbStub<S> {
+    pub fn new(debugger: Debugger, stream: S) -> Self {
+        Self {
+            debugger,
+            stream,
+            buffer: Vec::new(),
+            position: 0,
+            no_ack: false,
+            last_status: Status::Running,
+            interrupted: false
+        }
+    }
+
+    pub fn get_debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    /// The connection GDB is talking over
+    pub fn get_session(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn into_debugger(self) -> Debugger {
+        self.debugger
+    }
+
+    /// Answers packets until the connection closes or GDB sends `k` or `D`
+    pub fn serve(&mut self) -> std::io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            debug!("gdb <- {}", packet);
+            let (reply, done) = self.handle(&packet)?;
+            self.write_packet(&reply)?;
+            if packet == "QStartNoAckMode" {
+                self.no_ack = true;
+            }
+            if done {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the reply to a packet and whether the session is over
+    fn handle(&mut self, packet: &str) -> std::io::Result<(String, bool)> {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => self.stop_reply(),
+            Some(b'g') => (0..REGISTER_COUNT)
+                .map(|number| encode_word(self.read_register(number).unwrap_or(0)))
+                .collect(),
+            Some(b'G') => match decode_hex(&packet.as_bytes()[1..]) {
+                Some(values) => {
+                    for (number, bytes) in values.chunks_exact(4).enumerate() {
+                        self.write_register(number, u32::from_le_bytes(bytes.try_into().unwrap()));
+                    }
+                    "OK".to_string()
+                }
+                None => "E01".to_string()
+            },
+            Some(b'p') => usize::from_str_radix(&packet[1..], 16)
+                .ok()
+                .and_then(|number| self.read_register(number))
+                .map_or("E01".to_string(), encode_word),
+            Some(b'P') => self.set_register_packet(&packet[1..]),
+            Some(b'm') => self.read_memory_packet(&packet[1..]),
+            Some(b'M') => self.write_memory_packet(&packet[1..]),
+            Some(b'Z') => self.breakpoint_packet(&packet[1..], true),
+            Some(b'z') => self.breakpoint_packet(&packet[1..], false),
+            Some(b's') => {
+                self.resume_at(&packet[1..]);
+                self.interrupted = false;
+                self.last_status = self.debugger.step_instruction();
+                self.stop_reply()
+            }
+            Some(b'c') => {
+                self.resume_at(&packet[1..]);
+                self.last_status = self.continue_execution()?;
+                self.stop_reply()
+            }
+            Some(b'H') | Some(b'T') => "OK".to_string(),
+            Some(b'k') => return Ok((String::new(), true)),
+            Some(b'D') => return Ok(("OK".to_string(), true)),
+            _ => self.query(packet)
+        };
+        Ok((reply, false))
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+", PACKET_SIZE);
+        }
+        if let Some(request) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match parse_pair(request, ',') {
+                Some((offset, length)) => {
+                    let description = target_description();
+                    let start = (offset as usize).min(description.len());
+                    let end = (start + length as usize).min(description.len());
+                    let marker = if end == description.len() { 'l' } else { 'm' };
+                    format!("{}{}", marker, &description[start..end])
+                }
+                None => "E01".to_string()
+            };
+        }
+        match packet {
+            "QStartNoAckMode" => "OK".to_string(),
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            // Anything else is unsupported, which GDB expects as an empty reply
+            _ => String::new()
+        }
+    }
+
+    /// Runs until the processor stops, a breakpoint is reached or GDB sends an
+    /// interrupt, which is looked for between slices of cycles
+    fn continue_execution(&mut self) -> std::io::Result<Status> {
+        self.interrupted = false;
+        loop {
+            let (status, breakpoint) = self.debugger.resume_for(CONTINUE_SLICE);
+            if status != Status::Running || breakpoint.is_some() {
+                return Ok(status);
+            }
+            if self.poll_interrupt()? {
+                self.interrupted = true;
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Why the processor last stopped, in GDB's stop reply format
+    fn stop_reply(&self) -> String {
+        if self.interrupted {
+            return format!("S{:02x}", SIGINT);
+        }
+        match self.last_status {
+            Status::Exited(code) => format!("W{:02x}", code as u8),
+            Status::Faulted(_) => format!("S{:02x}", SIGSEGV),
+            Status::Exception(_) => format!("S{:02x}", SIGILL),
+            Status::Watchpoint(hit) => match hit.target {
+                WatchTarget::Memory(address) => {
+                    let kind = match hit.access {
+                        WatchKind::Read => "rwatch",
+                        _ => "watch"
+                    };
+                    format!("T{:02x}{}:{:x};", SIGTRAP, kind, address)
+                }
+                WatchTarget::Register(_) => format!("S{:02x}", SIGTRAP)
+            },
+            Status::Running => format!("S{:02x}", SIGTRAP)
+        }
+    }
+
+    /// `s` and `c` may carry an address to resume from
+    fn resume_at(&mut self, address: &str) {
+        if let Ok(address) = u32::from_str_radix(address, 16) {
+            self.debugger
+                .get_processor_mut()
+                .set_program_counter(address);
+        }
+    }
+
+    fn read_register(&self, number: usize) -> Option<u32> {
+        let processor = self.debugger.get_processor();
+        let cp0 = processor.get_cp0();
+        let value = match number {
+            0..=31 => processor.get_register(Register::from_usize(number)?),
+            STATUS_REGISTER => cp0.get(STATUS),
+            LO_REGISTER => processor.get_lo(),
+            HI_REGISTER => processor.get_hi(),
+            BAD_VADDR_REGISTER => cp0.get(BAD_VADDR),
+            CAUSE_REGISTER => processor.get_cause(),
+            PC_REGISTER => processor.get_program_counter(),
+            FIRST_FLOAT_REGISTER..FCSR_REGISTER => processor
+                .get_float_registers()
+                .get(number - FIRST_FLOAT_REGISTER),
+            // No floating point control state is modelled
+            FCSR_REGISTER | FIR_REGISTER => 0,
+            COUNT_REGISTER => cp0.get(COUNT),
+            COMPARE_REGISTER => cp0.get(COMPARE),
+            EPC_REGISTER => cp0.get(EPC),
+            _ => return None
+        };
+        Some(value)
+    }
+
+    fn write_register(&mut self, number: usize, value: u32) -> bool {
+        let processor = self.debugger.get_processor_mut();
+        match number {
+            // $zero is hardwired
+            0 | FCSR_REGISTER | FIR_REGISTER => {}
+            1..=31 => processor.set_register(Register::from_usize(number).unwrap(), value),
+            STATUS_REGISTER => processor.get_cp0_mut().set(STATUS, value),
+            LO_REGISTER => processor.set_lo(value),
+            HI_REGISTER => processor.set_hi(value),
+            BAD_VADDR_REGISTER => processor.get_cp0_mut().set(BAD_VADDR, value),
+            CAUSE_REGISTER => processor.get_cp0_mut().set(CAUSE, value),
+            PC_REGISTER => processor.set_program_counter(value),
+            FIRST_FLOAT_REGISTER..FCSR_REGISTER => processor
+                .get_float_registers_mut()
+                .set(number - FIRST_FLOAT_REGISTER, value),
+            COUNT_REGISTER => processor.get_cp0_mut().set(COUNT, value),
+            COMPARE_REGISTER => processor.get_cp0_mut().set(COMPARE, value),
+            EPC_REGISTER => processor.get_cp0_mut().set(EPC, value),
+            _ => return false
+        }
+        true
+    }
+
+    fn set_register_packet(&mut self, arguments: &str) -> String {
+        let parsed = arguments.split_once('=').and_then(|(number, value)| {
+            let number = usize::from_str_radix(number, 16).ok()?;
+            let bytes: [u8; 4] = decode_hex(value.as_bytes())?.try_into().ok()?;
+            Some((number, u32::from_le_bytes(bytes)))
+        });
+        match parsed {
+            Some((number, value)) if self.write_register(number, value) => "OK".to_string(),
+            _ => "E01".to_string()
+        }
+    }
+
+    /// Reads are cut short to fit a packet or at the first unmapped page,
+    /// GDB asks again for the rest
+    fn read_memory_packet(&self, arguments: &str) -> String {
+        let memory = self.debugger.get_processor().get_memory();
+        let (address, length) = match parse_pair(arguments, ',') {
+            Some(pair) => pair,
+            None => return "E01".to_string()
+        };
+        let bytes: String = (0..length.min(PACKET_SIZE as u32 / 2))
+            .map_while(|i| memory.lookup(address.wrapping_add(i)))
+            .map(|physical| format!("{:02x}", memory.read_byte(physical)))
+            .collect();
+        if bytes.is_empty() && length > 0 {
+            return "E14".to_string();
+        }
+        bytes
+    }
+
+    fn write_memory_packet(&mut self, arguments: &str) -> String {
+        let (range, data) = match arguments.split_once(':') {
+            Some(split) => split,
+            None => return "E01".to_string()
+        };
+        let (address, length) = match parse_pair(range, ',') {
+            Some(pair) => pair,
+            None => return "E01".to_string()
+        };
+        let bytes = match decode_hex(data.as_bytes()) {
+            Some(bytes) if bytes.len() == length as usize => bytes,
+            _ => return "E01".to_string()
+        };
+        let memory = self.debugger.get_processor_mut().get_memory_mut();
+        let physical: Option<Vec<u32>> = (0..length)
+            .map(|i| memory.lookup(address.wrapping_add(i)))
+            .collect();
+        let physical = match physical {
+            Some(physical) => physical,
+            None => return "E14".to_string()
+        };
+        for (address, byte) in physical.into_iter().zip(bytes) {
+            memory.write_byte(address, byte);
+        }
+        "OK".to_string()
+    }
+
+    /// `Z`/`z` packets: types 0 and 1 are breakpoints, 2 to 4 are write, read
+    /// and access watchpoints
+    fn breakpoint_packet(&mut self, arguments: &str, insert: bool) -> String {
+        let mut fields = arguments.split(',');
+        let kind = fields.next();
+        let address = fields.next().and_then(|address| u32::from_str_radix(address, 16).ok());
+        let length = fields.next().and_then(|length| u32::from_str_radix(length, 16).ok());
+        let (address, length) = match (address, length) {
+            (Some(address), Some(length)) => (address, length),
+            _ => return "E01".to_string()
+        };
+        let watch = match kind {
+            Some("0") | Some("1") => {
+                if insert {
+                    self.debugger.add_breakpoint(address);
+                } else {
+                    self.debugger.remove_breakpoint(address);
+                }
+                return "OK".to_string();
+            }
+            Some("2") => WatchKind::Write,
+            Some("3") => WatchKind::Read,
+            Some("4") => WatchKind::Access,
+            _ => return String::new()
+        };
+        let memory = self.debugger.get_processor_mut().get_memory_mut();
+        if insert {
+            memory.add_watchpoint(address, length, watch);
+        } else {
+            memory.remove_watchpoint_kind(address, watch);
+        }
+        "OK".to_string()
+    }
+
+    /// Reads the next `$packet#checksum`, acknowledging it unless no-ack mode is
+    /// on. Returns `None` once the connection closes.
+    fn read_packet(&mut self) -> std::io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                // Interrupt request while stopped, report where we are
+                Some(0x03) => {
+                    let reply = self.stop_reply();
+                    self.write_packet(&reply)?;
+                    continue;
+                }
+                // Acknowledgements and noise between packets
+                Some(_) => continue
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte)
+                }
+            }
+            let mut checksum = [0; 2];
+            for digit in checksum.iter_mut() {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(byte) => *digit = byte
+                }
+            }
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+            let valid = self.no_ack || expected == Some(checksum_of(&data));
+            if !self.no_ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if !valid {
+                warn!("Dropping gdb packet with a bad checksum");
+                continue;
+            }
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> std::io::Result<()> {
+        debug!("gdb -> {}", data);
+        let mut escaped = Vec::with_capacity(data.len());
+        for byte in data.bytes() {
+            if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                escaped.push(b'}');
+                escaped.push(byte ^ 0x20);
+            } else {
+                escaped.push(byte);
+            }
+        }
+        let checksum = checksum_of(&escaped);
+        self.stream.write_all(b"$")?;
+        self.stream.write_all(&escaped)?;
+        write!(self.stream, "#{:02x}", checksum)?;
+        self.stream.flush()
+    }
+
+    fn read_byte(&mut self) -> std::io::Result<Option<u8>> {
+        if self.position == self.buffer.len() && self.fill_buffer()? == 0 {
+            return Ok(None);
+        }
+        self.position += 1;
+        Ok(Some(self.buffer[self.position - 1]))
+    }
+
+    /// Replaces the fully read buffer with whatever the stream has next
+    fn fill_buffer(&mut self) -> std::io::Result<usize> {
+        self.buffer.resize(4096, 0);
+        self.position = 0;
+        let read = loop {
+            match self.stream.read(&mut self.buffer) {
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => {
+                    self.buffer.clear();
+                    return Err(error);
+                }
+                Ok(read) => break read
+            }
+        };
+        self.buffer.truncate(read);
+        Ok(read)
+    }
+
+    /// Takes an interrupt byte GDB sent while the program runs, without
+    /// waiting for one. Anything else that arrived stays buffered.
+    fn poll_interrupt(&mut self) -> std::io::Result<bool> {
+        if self.position == self.buffer.len() {
+            self.stream.set_nonblocking(true)?;
+            let read = self.fill_buffer();
+            self.stream.set_nonblocking(false)?;
+            match read {
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return Ok(false),
+                read => read?
+            };
+        }
+        match self.buffer[self.position..].iter().position(|byte| *byte == 0x03) {
+            Some(offset) => {
+                self.buffer.remove(self.position + offset);
+                Ok(true)
+            }
+            None => Ok(false)
+        }
+    }
+}
+
+/// Target description advertising the MIPS32 register layout with HI/LO, the
+/// CP0 registers the simulator models and the FPU
+pub fn target_description() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\">\
+         <architecture>mips</architecture>\
+         <feature name=\"org.gnu.gdb.mips.cpu\">"
+    );
+    let register = |name: &str, number: usize, extra: &str| {
+        format!(
+            "<reg name=\"{}\" bitsize=\"32\" regnum=\"{}\"{}/>",
+            name, number, extra
+        )
+    };
+    for number in 0..32 {
+        xml += &register(&format!("r{}", number), number, "");
+    }
+    xml += &register("lo", LO_REGISTER, "");
+    xml += &register("hi", HI_REGISTER, "");
+    xml += &register("pc", PC_REGISTER, " type=\"code_ptr\"");
+    xml += "</feature><feature name=\"org.gnu.gdb.mips.cp0\">";
+    xml += &register("status", STATUS_REGISTER, "");
+    xml += &register("badvaddr", BAD_VADDR_REGISTER, "");
+    xml += &register("cause", CAUSE_REGISTER, "");
+    xml += &register("count", COUNT_REGISTER, "");
+    xml += &register("compare", COMPARE_REGISTER, "");
+    xml += &register("epc", EPC_REGISTER, " type=\"code_ptr\"");
+    xml += "</feature><feature name=\"org.gnu.gdb.mips.fpu\">";
+    for number in 0..32 {
+        xml += &register(
+            &format!("f{}", number),
+            FIRST_FLOAT_REGISTER + number,
+            " type=\"ieee_single\""
+        );
+    }
+    xml += &register("fcsr", FCSR_REGISTER, " group=\"float\"");
+    xml += &register("fir", FIR_REGISTER, " group=\"float\"");
+    xml += "</feature></target>";
+    xml
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Registers travel in target byte order, which is little-endian here
+fn encode_word(value: u32) -> String {
+    value
+        .to_le_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Bytes spelled out as pairs of hex digits, `None` if any pair is not one
+fn decode_hex(text: &[u8]) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    let digit = |byte: u8| (byte as char).to_digit(16);
+    text.chunks_exact(2)
+        .map(|pair| Some((digit(pair[0])? << 4 | digit(pair[1])?) as u8))
+        .collect()
+}
+
+fn parse_pair(text: &str, separator: char) -> Option<(u32, u32)> {
+    let (first, second) = text.split_once(separator)?;
+    Some((
+        u32::from_str_radix(first, 16).ok()?,
+        u32::from_str_radix(second, 16).ok()?
+    ))
+}
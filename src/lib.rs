@@ -1,3 +1,4 @@
-#![feature(non_null_convenience)]
-
+pub mod debugger;
+pub mod gdb;
 pub mod processor;
+pub mod tui;
@@ -1,39 +1,205 @@
-#![feature(non_null_convenience)]
-
 use log::info;
-use text_io::read;
-
-mod processor;
+use mips_sim::debugger::Debugger;
+use mips_sim::gdb;
+use mips_sim::processor;
+use mips_sim::processor::cache::{Cache, CacheConfig};
+use mips_sim::processor::cache_hierarchy::InclusionPolicy;
+use mips_sim::processor::console::{Console, ScriptedConsole, StdConsole};
+use mips_sim::processor::device::Device;
+use mips_sim::processor::exception::ExceptionHandler;
+use mips_sim::processor::memory::{DATA_BASE, RAM_SIZE, TEXT_BASE};
+use mips_sim::processor::mmio::{KeyboardDisplay, MMIO_BASE, TRANSMIT_DELAY};
+use mips_sim::processor::tlb::Tlb;
+use mips_sim::processor::Status;
+use mips_sim::tui::Tui;
 
+#[allow(clippy::unusual_byte_groupings)]
 fn main() {
     pretty_env_logger::init();
 
+    let data = vec![
+        // Prompt string, little-endian so the first character is the low byte
+        0b01100101_01110100_01101110_01000101,
+        0b00100000_01100001_00100000_01110010,
+        0b01100010_01101101_01110101_01101110,
+        0b01110100_00100000_01110010_01100101,
+        0b01101111_01100100_00100000_01101111,
+        0b01100101_01101100_01100010_01110101,
+        0b00000000_00000000_00100000_00111010
+    ];
     let instructions = vec![
-        // Prompt string
-        0b01000101_01101110_01110100_01100101,
-        0b01110010_00100000_01100001_00100000,
-        0b01101110_01110101_01101101_01100010,
-        0b01100101_01110010_00100000_01110100,
-        0b01101111_00100000_01100100_01101111,
-        0b01110101_01100010_01101100_01100101,
-        0b00111010_00100000_00000000_00000000,
         // Set $v0 to 4
         0b001101_00000_00010_0000000000000100,
-        // Set $a0 to 0x0 pointer to prompt string
-        0b001101_00000_00100_0000000000000100,
+        // Set $a0 to 0x10010000 pointer to prompt string
+        0b001111_00000_00100_0001000000000001,
+        // nop while $a0 is written back
+        0b000000_00000_00000_00000_00000_000000,
+        // syscall
+        0b000000_00000_00000_00000_00000_001100,
+        // Set $v0 to 10
+        0b001101_00000_00010_0000000000001010,
+        // nop while $v0 is written back
+        0b000000_00000_00000_00000_00000_000000,
         // syscall
         0b000000_00000_00000_00000_00000_001100
     ];
 
+    let arguments: Vec<String> = std::env::args().collect();
+    let tui_console = arguments
+        .iter()
+        .any(|argument| argument == "--tui")
+        .then(|| ScriptedConsole::new(""));
+    let console = || -> Box<dyn Console> {
+        match &tui_console {
+            Some(console) => Box::new(console.clone()),
+            None => Box::new(StdConsole)
+        }
+    };
+    let mut devices: Vec<(u32, Box<dyn Device>)> = Vec::new();
+    if let Some(index) = arguments.iter().position(|argument| argument == "--mmio") {
+        let delay = arguments
+            .get(index + 1)
+            .and_then(|delay| delay.parse::<u32>().ok())
+            .unwrap_or(TRANSMIT_DELAY);
+        devices.push((MMIO_BASE, Box::new(KeyboardDisplay::new_with_delay(console(), delay))));
+    }
+    let rom = match arguments.iter().position(|argument| argument == "--rom") {
+        Some(index) => {
+            let path = arguments.get(index + 1).map_or("rom.bin", |path| path.as_str());
+            match std::fs::read(path) {
+                // Little-endian words, the last one padded with zeros
+                Ok(bytes) => Some(
+                    bytes
+                        .chunks(4)
+                        .map(|chunk| {
+                            let mut word = [0; 4];
+                            word[..chunk.len()].copy_from_slice(chunk);
+                            u32::from_le_bytes(word)
+                        })
+                        .collect::<Vec<u32>>()
+                ),
+                Err(error) => {
+                    eprintln!("Could not read ROM image {}: {}", path, error);
+                    return;
+                }
+            }
+        }
+        None => None
+    };
+    let mut processor = match rom {
+        Some(rom) => match processor::Processor::new_bare_metal(console(), rom, RAM_SIZE) {
+            Ok(processor) => processor,
+            Err(error) => {
+                eprintln!("Could not load ROM image: {}", error);
+                return;
+            }
+        },
+        None => processor::Processor::new_with_console(console())
+    };
+    for (start, device) in devices {
+        if let Err(error) = processor.get_memory_mut().map_device(start, device) {
+            eprintln!("Could not map devices: {}", error);
+            return;
+        }
+    }
+    if !processor.is_bare_metal() {
+        if arguments.iter().any(|argument| argument == "--tlb") {
+            processor.get_memory_mut().set_tlb(Tlb::new());
+        }
+        processor.load_data(data);
+        processor.load_program(instructions);
+        processor.add_symbol("main", TEXT_BASE);
+        processor.add_symbol("prompt", DATA_BASE);
+    }
+
+    if let Some(index) = arguments.iter().position(|argument| argument == "--trace") {
+        let path = arguments.get(index + 1).map_or("trace.jsonl", |path| path.as_str());
+        if let Err(error) = processor.set_trace_file(path) {
+            eprintln!("Could not open trace file {}: {}", path, error);
+            return;
+        }
+    }
+    if let Some(index) = arguments.iter().position(|argument| argument == "--commit-log") {
+        let path = arguments.get(index + 1).map_or("commits.log", |path| path.as_str());
+        if let Err(error) = processor.set_commit_log_file(path) {
+            eprintln!("Could not open commit log {}: {}", path, error);
+            return;
+        }
+    }
+    if let Some(index) = arguments.iter().position(|argument| argument == "--memory-latency") {
+        match arguments.get(index + 1).and_then(|cycles| cycles.parse::<u32>().ok()) {
+            Some(cycles) => processor.set_memory_latency(cycles),
+            None => {
+                eprintln!("--memory-latency needs a number of cycles");
+                return;
+            }
+        }
+    }
+    for flag in ["--icache", "--dcache", "--l2cache"] {
+        let spec = match arguments.iter().position(|argument| argument == flag) {
+            Some(index) => arguments.get(index + 1).map_or(
+                if flag == "--l2cache" { "65536,32,4,latency=4" } else { "4096,16,2" },
+                |spec| spec.as_str()
+            ),
+            None => continue
+        };
+        let cache = match spec.parse::<CacheConfig>().and_then(Cache::new) {
+            Ok(cache) => cache,
+            Err(error) => {
+                eprintln!("Could not set up {}: {}", flag, error);
+                return;
+            }
+        };
+        match flag {
+            "--icache" => processor.set_instruction_cache(cache),
+            "--dcache" => processor.set_data_cache(cache),
+            _ => processor.set_l2_cache(cache)
+        }
+    }
+    if arguments.iter().any(|argument| argument == "--exclusive") {
+        processor.set_inclusion_policy(InclusionPolicy::Exclusive);
+    }
+    if let Some(index) = arguments.iter().position(|argument| argument == "--exceptions") {
+        match arguments.get(index + 1).and_then(|name| ExceptionHandler::from_name(name)) {
+            Some(handler) => processor.set_exception_handler(handler),
+            None => {
+                eprintln!("--exceptions needs halt, builtin or vector");
+                return;
+            }
+        }
+    }
+    if let Some(index) = arguments.iter().position(|argument| argument == "--gdb") {
+        let port = arguments
+            .get(index + 1)
+            .and_then(|port| port.parse::<u16>().ok())
+            .unwrap_or(1234);
+        if let Err(error) = gdb::listen(Debugger::new(processor), ("127.0.0.1", port)) {
+            eprintln!("gdb server failed: {}", error);
+        }
+        return;
+    }
+
+    if let Some(console) = tui_console {
+        if let Err(error) = Tui::new(Debugger::new(processor), console).run() {
+            eprintln!("Terminal error: {}", error);
+        }
+        return;
+    }
+
+    if arguments.iter().any(|argument| argument == "--debug") {
+        Debugger::new(processor).run();
+        return;
+    }
+
     print!("Enter cycle count: ");
     // let cycle_count: u32 = read!();
-    let cycle_count: u32 = 1;
-    let mut processor = processor::Processor::new();
-    processor.load_program(instructions);
-    processor.set_entry_point(0x1F);
+    let cycle_count: u32 = 16;
     for i in 0..cycle_count {
         info!("Cycle {}", i);
-        processor.cycle();
+        let status = processor.cycle();
         println!("{}", processor);
+        if status != Status::Running {
+            break;
+        }
     }
 }
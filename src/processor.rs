@@ -1,66 +1,444 @@
-use std::cmp::PartialEq;
-use log::{debug, info, trace};
-use text_io::read;
+use std::io::Write;
+use log::{error, info};
 use crate::processor::alu::ALU;
 use crate::processor::buffer::{EXMEMBuffer, IDEXBuffer, IFIDBuffer, MEMWBBuffer};
+use crate::processor::cache_hierarchy::CacheHierarchy;
+use crate::processor::commit_log::CommitLog;
+use crate::processor::console::{Console, StdConsole};
+use crate::processor::cp0::{Cp0, RESET_VECTOR, STATUS, STATUS_BEV};
+use crate::processor::device::{Device, DeviceMapError};
+use crate::processor::exception::{Exception, ExceptionCode, ExceptionHandler};
+use crate::processor::float_registers::FloatRegisters;
+use crate::processor::history::History;
 use crate::processor::instruction::Instruction;
-use crate::processor::memory::{DataMemory, Memory};
+use crate::processor::interrupts::InterruptController;
+use crate::processor::memory::{DataMemory, Memory, RomError, Size, GLOBAL_POINTER, TEXT_BASE};
+use crate::processor::memory_dump::MemoryDump;
+use crate::processor::pipeline_stats::{PipelineStats, StallCause};
 use crate::processor::program_counter::ProgramCounter;
+use crate::processor::register_dump::{RegisterDump, RegisterState, RegisterView};
 use crate::processor::registers::{DecodeReturn, Register, Registers};
+use crate::processor::segment::MemoryFault;
+use crate::processor::symbols::SymbolTable;
+use crate::processor::syscall::SyscallState;
+use crate::processor::watchpoint::WatchpointHit;
 
 pub mod alu;
 pub mod buffer;
+pub mod cache;
+pub mod cache_hierarchy;
+pub mod cache_view;
+pub mod commit_log;
+pub mod console;
+pub mod cp0;
+pub mod device;
+pub mod exception;
+pub mod float_registers;
+pub mod history;
 pub mod instruction;
+pub mod interrupts;
 pub mod memory;
+pub mod memory_dump;
+pub mod mmio;
+pub mod pipeline_stats;
 pub mod program_counter;
+pub mod register_dump;
 pub mod registers;
-
-const MEMORY_SIZE: u32 = 1024;
+pub mod segment;
+pub mod snapshot;
+pub mod symbols;
+pub mod syscall;
+pub mod tlb;
+pub mod trace;
+pub mod watchpoint;
 
 pub struct Processor {
     program_counter: ProgramCounter,
     memory: Memory,
     if_id_buffer: IFIDBuffer,
     registers: Registers,
+    float_registers: FloatRegisters,
+    cp0: Cp0,
     id_ex_buffer: IDEXBuffer,
     alu: ALU,
     ex_mem_buffer: EXMEMBuffer,
     mem_wb_buffer: MEMWBBuffer,
-    stall: bool
+    stall: bool,
+    status: Status,
+    cycles: u64,
+    last_commit: Option<Commit>,
+    symbols: SymbolTable,
+    console: Box<dyn Console>,
+    syscalls: SyscallState,
+    history: Option<History>,
+    trace: Option<Box<dyn Write>>,
+    commit_log: Option<CommitLog>,
+    /// Set when the cycle discarded instructions already in the pipeline
+    flush: bool,
+    /// Register values when the last cycle started, for diffing
+    previous_registers: RegisterState,
+    caches: CacheHierarchy,
+    /// Cycles left before an instruction cache miss delivers, `None` when
+    /// no fetch is in flight
+    fetch_wait: Option<u32>,
+    /// Cycles left before a data cache miss completes
+    memory_wait: Option<u32>,
+    stall_cause: Option<StallCause>,
+    pipeline_stats: PipelineStats,
+    interrupts: InterruptController,
+    exception_handler: ExceptionHandler,
+    /// Started from a ROM image, syscalls are exceptions for the firmware
+    bare_metal: bool
+}
+
+/// An instruction leaving the pipeline through writeback
+#[derive(Clone, Copy)]
+pub struct Commit {
+    /// Address the instruction was fetched from
+    pub address: u32,
+    pub instruction: Instruction
+}
+
+/// Whether the processor can keep cycling and why it stopped if not
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Running,
+    /// The program exited through a syscall with this status code
+    Exited(i32),
+    Faulted(MemoryFault),
+    /// An instruction raised an exception with no handler to take it
+    Exception(Exception),
+    /// An instruction touched a watched location this cycle. Unlike the other
+    /// states this does not halt the processor, cycling again continues.
+    Watchpoint(WatchpointHit)
 }
 
 impl Processor {
     pub fn new() -> Self {
+        Self::new_with_console(Box::new(StdConsole))
+    }
+
+    /// Creates a processor whose syscalls talk to `console` instead of stdin/stdout
+    pub fn new_with_console(console: Box<dyn Console>) -> Self {
         let mut processor = Processor {
             program_counter: ProgramCounter::new(),
-            memory: Memory::new_with_capacity(MEMORY_SIZE),
+            memory: Memory::new(),
             if_id_buffer: IFIDBuffer::new(),
             registers: Registers::new(),
+            float_registers: FloatRegisters::new(),
+            cp0: Cp0::new(),
             id_ex_buffer: IDEXBuffer::new(),
             alu: ALU::new(),
             ex_mem_buffer: EXMEMBuffer::new(),
             mem_wb_buffer: MEMWBBuffer::new(),
-            stall: false
+            stall: false,
+            status: Status::Running,
+            cycles: 0,
+            last_commit: None,
+            symbols: SymbolTable::new(),
+            console,
+            syscalls: SyscallState::new(),
+            history: None,
+            trace: None,
+            commit_log: None,
+            flush: false,
+            previous_registers: RegisterState {
+                registers: [0; 32],
+                hi: 0,
+                lo: 0,
+                pc: 0
+            },
+            caches: CacheHierarchy::new(),
+            fetch_wait: None,
+            memory_wait: None,
+            stall_cause: None,
+            pipeline_stats: PipelineStats::default(),
+            interrupts: InterruptController::new(),
+            exception_handler: ExceptionHandler::default(),
+            bare_metal: false
         };
         processor
             .registers
             .set(Register::Sp, processor.memory.get_stack_pointer());
+        processor.registers.set(Register::Gp, GLOBAL_POINTER);
+        processor.previous_registers = processor.get_register_state();
         processor
     }
 
+    /// Creates a processor with `devices` mapped at their start addresses, see
+    /// [`Memory::map_device`]
+    pub fn new_with_devices(
+        console: Box<dyn Console>,
+        devices: Vec<(u32, Box<dyn Device>)>
+    ) -> Result<Self, DeviceMapError> {
+        let mut processor = Self::new_with_console(console);
+        for (start, device) in devices {
+            processor.memory.map_device(start, device)?;
+        }
+        Ok(processor)
+    }
+
+    /// Creates a bare-metal machine that boots `rom` from the reset vector in
+    /// kernel mode, with `ram_size` bytes of RAM and a TLB. Nothing is set up
+    /// for it: registers start at zero, exceptions go to the boot vectors in
+    /// ROM and syscalls raise exceptions instead of being emulated. Fails if
+    /// `rom` does not fit in the boot ROM.
+    pub fn new_bare_metal(
+        console: Box<dyn Console>,
+        rom: Vec<u32>,
+        ram_size: u32
+    ) -> Result<Self, RomError> {
+        let mut processor = Self::new_with_console(console);
+        processor.memory = Memory::new_bare_metal(ram_size);
+        processor.memory.load_rom(rom)?;
+        processor.registers = Registers::new();
+        processor.cp0.set(STATUS, STATUS_BEV);
+        processor.program_counter.set(RESET_VECTOR);
+        processor.exception_handler = ExceptionHandler::Vector;
+        processor.bare_metal = true;
+        processor.sync_mode();
+        processor.previous_registers = processor.get_register_state();
+        Ok(processor)
+    }
+
+    /// Whether the processor was created by [`Processor::new_bare_metal`]
+    pub fn is_bare_metal(&self) -> bool {
+        self.bare_metal
+    }
+
+    /// Loads the program into the text segment and points the program counter at its start
     pub fn load_program(&mut self, program: Vec<u32>) {
         self.memory.load_program(program);
-        self.registers
-            .set(Register::Sp, self.memory.get_stack_pointer());
+        self.program_counter.set(TEXT_BASE);
+    }
+
+    /// Loads words into the static data segment
+    pub fn load_data(&mut self, data: Vec<u32>) {
+        self.memory.load_data(data);
     }
 
     pub fn set_entry_point(&mut self, address: u32) {
         self.program_counter.set(address);
     }
 
-    pub fn cycle(&mut self) {
+    pub fn get_status(&self) -> Status {
+        self.status
+    }
+
+    /// Address of the next instruction to be fetched
+    pub fn get_program_counter(&self) -> u32 {
+        self.program_counter.get()
+    }
+
+    pub fn get_register(&self, register: Register) -> u32 {
+        self.registers.get(register)
+    }
+
+    pub fn set_register(&mut self, register: Register, value: u32) {
+        self.registers.set(register, value);
+    }
+
+    pub fn get_registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn get_registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    pub fn get_hi(&self) -> u32 {
+        self.alu.get_hi()
+    }
+
+    pub fn get_lo(&self) -> u32 {
+        self.alu.get_lo()
+    }
+
+    pub fn set_hi(&mut self, value: u32) {
+        self.alu.set_hi(value);
+    }
+
+    pub fn set_lo(&mut self, value: u32) {
+        self.alu.set_lo(value);
+    }
+
+    pub fn set_program_counter(&mut self, address: u32) {
+        self.program_counter.set(address);
+    }
+
+    pub fn get_float_registers(&self) -> &FloatRegisters {
+        &self.float_registers
+    }
+
+    pub fn get_float_registers_mut(&mut self) -> &mut FloatRegisters {
+        &mut self.float_registers
+    }
+
+    pub fn get_cp0(&self) -> &Cp0 {
+        &self.cp0
+    }
+
+    pub fn get_cp0_mut(&mut self) -> &mut Cp0 {
+        &mut self.cp0
+    }
+
+    pub fn get_register_state(&self) -> RegisterState {
+        RegisterState {
+            registers: self.registers.get_all(),
+            hi: self.alu.get_hi(),
+            lo: self.alu.get_lo(),
+            pc: self.program_counter.get()
+        }
+    }
+
+    /// Register values from before the most recent cycle
+    pub fn get_previous_register_state(&self) -> RegisterState {
+        self.previous_registers
+    }
+
+    /// Every register along with HI, LO and the program counter
+    pub fn register_dump(&self, view: RegisterView) -> RegisterDump {
+        RegisterDump::new(self.get_register_state(), view)
+    }
+
+    /// Only the registers the most recent cycle changed
+    pub fn register_diff(&self, view: RegisterView) -> RegisterDump {
+        RegisterDump::new_diff(self.get_register_state(), self.previous_registers, view)
+    }
+
+    pub fn get_memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn get_memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn get_symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Hex dump of `length` bytes from `start` labelled with the program's symbols
+    pub fn memory_dump(&self, start: u32, length: u32, grouping: Size) -> MemoryDump<'_> {
+        MemoryDump::new_with_symbols(&self.memory, &self.symbols, start, length, grouping)
+    }
+
+    pub fn add_symbol(&mut self, name: &str, address: u32) {
+        self.symbols.insert(name, address);
+    }
+
+    pub fn get_if_id_buffer(&self) -> &IFIDBuffer {
+        &self.if_id_buffer
+    }
+
+    pub fn get_id_ex_buffer(&self) -> &IDEXBuffer {
+        &self.id_ex_buffer
+    }
+
+    pub fn get_ex_mem_buffer(&self) -> &EXMEMBuffer {
+        &self.ex_mem_buffer
+    }
+
+    pub fn get_mem_wb_buffer(&self) -> &MEMWBBuffer {
+        &self.mem_wb_buffer
+    }
+
+    /// Number of cycles run so far
+    pub fn get_cycle_count(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The instruction written back during the last cycle, if any
+    pub fn get_last_commit(&self) -> Option<Commit> {
+        self.last_commit
+    }
+
+    /// The memory fault that halted the processor, if any
+    pub fn get_fault(&self) -> Option<MemoryFault> {
+        match self.status {
+            Status::Faulted(fault) => Some(fault),
+            _ => None
+        }
+    }
+
+    /// Cycles until the processor halts, a watchpoint is hit or `max_cycles` have run
+    pub fn run(&mut self, max_cycles: u64) -> Status {
+        for _ in 0..max_cycles {
+            let status = self.cycle();
+            if status != Status::Running {
+                return status;
+            }
+        }
+        self.status
+    }
+
+    pub fn cycle(&mut self) -> Status {
+        if self.status != Status::Running {
+            return self.status;
+        }
+        self.previous_registers = self.get_register_state();
+        let pending = self.begin_record();
+        let trace = self.begin_trace();
+        let commit_start = self.commit_log.as_ref().map(|_| self.if_id_buffer);
+        let journaling = pending.is_some() || trace.is_some() || commit_start.is_some();
+        if journaling {
+            self.start_journals();
+        }
+        self.execute_cycle();
+        self.pipeline_stats
+            .record(self.stall_cause, self.last_commit.is_some());
+        let memory_hit = self.memory.take_watchpoint_hit();
+        let register_hit = self.registers.take_watchpoint_hit();
+        let hit = memory_hit.or(register_hit);
+        if journaling {
+            let writes = self.take_journals();
+            if let Some(trace) = trace {
+                self.end_trace(trace, &writes);
+            }
+            if let Some(if_id_buffer) = commit_start {
+                self.end_commit_log(if_id_buffer, &writes);
+            }
+            if let Some(pending) = pending {
+                self.end_record(pending, writes, hit);
+            }
+        }
+        match hit {
+            Some(hit) if self.status == Status::Running => Status::Watchpoint(hit),
+            _ => self.status
+        }
+    }
+
+    /// Decodes the instruction in IF/ID again next cycle, once the load or
+    /// store ahead of it is done
+    fn hold_decode(&mut self) {
+        self.id_ex_buffer = IDEXBuffer::new();
+        self.stall_cause = Some(StallCause::DataHazard);
+    }
+
+    fn execute_cycle(&mut self) {
         info!("Cycle start");
-        DataMemory::execute(&self.ex_mem_buffer, &mut self.mem_wb_buffer, &mut self.memory);
+        self.cycles += 1;
+        self.last_commit = None;
+        self.flush = false;
+        self.stall_cause = None;
+        self.caches.begin_cycle();
+        self.cp0.tick();
+        self.memory.tick_devices();
+        self.sync_mode();
+        if self.wait_for_data() {
+            // Everything behind the load or store in MEM holds still
+            self.mem_wb_buffer = MEMWBBuffer::new();
+            self.stall_cause = Some(StallCause::DataCache);
+            return;
+        }
+        self.check_interrupts();
+        let memory_result =
+            DataMemory::execute(&self.ex_mem_buffer, &mut self.mem_wb_buffer, &mut self.memory);
+        if let Err(fault) = memory_result {
+            if self.data_exception(fault) {
+                return;
+            }
+        }
         self.alu
             .execute(&self.id_ex_buffer, &mut self.ex_mem_buffer);
         let decode = self.registers.execute(
@@ -68,57 +446,115 @@ impl Processor {
             &mut self.id_ex_buffer,
             &mut self.mem_wb_buffer
         );
+        self.last_commit = self.mem_wb_buffer.instruction.map(|instruction| Commit {
+            address: self.mem_wb_buffer.address,
+            instruction
+        });
         match decode {
-            DecodeReturn::Jump(address) => { self.program_counter.set(address) }
-            DecodeReturn::Syscall => { self.syscall() }
+            DecodeReturn::Jump(address) => {
+                self.program_counter.set(address);
+                // A fetch still waiting on the cache was for the old address
+                self.fetch_wait = None;
+            }
+            DecodeReturn::Syscall if self.bare_metal => {
+                if self.access_pending() {
+                    self.hold_decode();
+                    return;
+                }
+                // Buffers carry the already incremented PC
+                let epc = self.id_ex_buffer.pc.wrapping_sub(4);
+                self.instruction_exception(Exception::new(ExceptionCode::Sys, epc));
+                if self.status != Status::Running {
+                    return;
+                }
+            }
+            DecodeReturn::Syscall => {
+                self.syscall();
+                if self.status != Status::Running {
+                    return;
+                }
+            }
+            DecodeReturn::Cop0 => {
+                if !self.execute_cop0() {
+                    self.hold_decode();
+                    return;
+                }
+                if self.status != Status::Running {
+                    return;
+                }
+            }
+            DecodeReturn::Cop1 => self.execute_cop1(),
+            DecodeReturn::Reserved => {
+                if self.access_pending() {
+                    self.hold_decode();
+                    return;
+                }
+                // Buffers carry the already incremented PC
+                let epc = self.id_ex_buffer.pc.wrapping_sub(4);
+                self.instruction_exception(Exception::new(ExceptionCode::Ri, epc));
+                if self.status != Status::Running {
+                    return;
+                }
+            }
             DecodeReturn::Stall => { self.stall = true }
             DecodeReturn::None => {}
         }
-        let instruction;
-        if self.stall {
+        // Decode may have changed modes
+        self.sync_mode();
+        let instruction = if self.stall {
             if decode == DecodeReturn::None {
                 self.stall = false;
             }
-            instruction = None;
+            // Only branches stall fetch
+            self.stall_cause = Some(StallCause::Control);
+            None
         } else {
-            instruction = Some(Instruction::load(self.memory.read_word(self.program_counter.get())));
-            self.program_counter.increment();
-        }
+            match self.memory.fetch(self.program_counter.get()) {
+                Ok(word) => {
+                    if self.wait_for_instruction() {
+                        self.stall_cause = Some(StallCause::InstructionCache);
+                        None
+                    } else {
+                        self.program_counter.increment();
+                        Some(Instruction::load(word))
+                    }
+                }
+                Err(fault) => {
+                    self.fetch_exception(fault);
+                    return;
+                }
+            }
+        };
         self.if_id_buffer.instruction = instruction;
         self.if_id_buffer.pc = self.program_counter.get();
     }
 
-    fn syscall(&mut self) {
-        let syscall_code = self.registers.get(Register::V0);
-        debug!("Syscall code: {:#x}", syscall_code);
-        match syscall_code {
-            1 => {
-                println!(
-                    "{}",
-                    self.memory.read_word(self.registers.get(Register::A0))
-                )
-            }
-            4 => {
-                println!(
-                    "{}",
-                    self.memory.read_cstring(self.registers.get(Register::A0))
-                )
-            }
-            5 => {
-                let num: i32 = read!();
-                self.registers.set(Register::V0, num as u32);
-            }
-            10 => std::process::exit(0),
-            _ => {}
-        }
+    fn raise_fault(&mut self, fault: MemoryFault) {
+        error!("Memory fault at pc {:#x}: {}", self.program_counter.get(), fault);
+        self.status = Status::Faulted(fault);
+    }
+}
+
+impl Default for Processor {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl std::fmt::Display for Processor {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         writeln!(f, "Program Counter: {:#x}\n", self.program_counter.get())?;
+        match self.status {
+            Status::Running => {}
+            Status::Exited(code) => writeln!(f, "Exited with status {}\n", code)?,
+            Status::Faulted(fault) => writeln!(f, "Fault: {}\n", fault)?,
+            Status::Exception(exception) => writeln!(f, "Exception: {}\n", exception)?,
+            Status::Watchpoint(_) => {}
+        }
         writeln!(f, "{}", self.memory)?;
-        // writeln!(f, "{}", self.registers)?;
+        write!(f, "{}", self.caches)?;
+        writeln!(f, "{}", self.pipeline_stats)?;
+        writeln!(f, "{}", self.register_dump(RegisterView::Hex))?;
         writeln!(f, "{}", self.if_id_buffer)?;
         writeln!(f, "{}", self.id_ex_buffer)?;
         writeln!(f, "{}", self.ex_mem_buffer)?;
@@ -1,9 +1,8 @@
-use log::{debug, info, trace};
+use log::{debug, info};
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 use crate::processor::buffer::{EXMEMBuffer, IDEXBuffer};
 use crate::processor::instruction::InstructionType;
-use crate::processor::registers::{Register, Registers};
 
 pub struct ALU {
     hi: u32,
@@ -43,6 +42,8 @@ pub enum OpCode {
     Andi = 0x0C,
     Beq = 0x04,
     Bne = 0x05,
+    Cop0 = 0x10,
+    Cop1 = 0x11,
     Lbu = 0x24,
     Lhu = 0x25,
     Ll = 0x30,
@@ -62,10 +63,28 @@ impl ALU {
         Self { hi: 0, lo: 0 }
     }
 
+    pub fn get_hi(&self) -> u32 {
+        self.hi
+    }
+
+    pub fn get_lo(&self) -> u32 {
+        self.lo
+    }
+
+    pub fn set_hi(&mut self, value: u32) {
+        self.hi = value;
+    }
+
+    pub fn set_lo(&mut self, value: u32) {
+        self.lo = value;
+    }
+
     pub fn execute(&mut self, idex: &IDEXBuffer, exmem: &mut EXMEMBuffer) {
         info!("Executing ALU stage");
         let instruction = idex.instruction;
         exmem.pc = idex.pc;
+        // Buffers carry the already incremented PC
+        exmem.address = idex.pc.wrapping_sub(4);
         exmem.instruction = instruction;
         exmem.alu_result = 0;
         exmem.data_2 = idex.data_2;
@@ -75,7 +94,11 @@ impl ALU {
         let instruction = instruction.unwrap();
         match instruction.instruction_type {
             InstructionType::R => {
-                let funct = FunctionCode::from_u8(instruction.funct.unwrap()).unwrap();
+                // Decode already raised an exception for anything else
+                let funct = match instruction.funct.and_then(FunctionCode::from_u8) {
+                    Some(funct) => funct,
+                    None => return
+                };
                 debug!("Executing R-type instruction: {:?}", funct);
                 match funct {
                     FunctionCode::Add => {
@@ -152,7 +175,10 @@ impl ALU {
                 }
             }
             InstructionType::I => {
-                let opcode = OpCode::from_u8(instruction.opcode).unwrap();
+                let opcode = match OpCode::from_u8(instruction.opcode) {
+                    Some(opcode) => opcode,
+                    None => return
+                };
                 debug!("Executing I-type instruction: {:?}", opcode);
                 match opcode {
                     OpCode::Addi => {
@@ -175,6 +201,10 @@ impl ALU {
                             exmem.pc = idex.pc.wrapping_add(4).wrapping_add(idex.sign_extended);
                         }
                     }
+                    OpCode::Cop0 | OpCode::Cop1 => {
+                        // Decode put the value `mfc0` or `mfc1` reads in data_1
+                        exmem.alu_result = idex.data_1;
+                    }
                     OpCode::Lui => {
                         exmem.alu_result = idex.sign_extended << 16;
                     }
@@ -203,3 +233,9 @@ impl ALU {
         }
     }
 }
+
+impl Default for ALU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
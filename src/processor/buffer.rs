@@ -6,6 +6,7 @@ pub struct IFIDBuffer {
     pub pc: u32
 }
 
+#[derive(Copy, Clone)]
 pub struct IDEXBuffer {
     pub instruction: Option<Instruction>,
     pub data_1: u32,
@@ -14,17 +15,25 @@ pub struct IDEXBuffer {
     pub pc: u32
 }
 
+#[derive(Copy, Clone)]
 pub struct EXMEMBuffer {
     pub instruction: Option<Instruction>,
     pub alu_result: u32,
     pub data_2: u32,
-    pub pc: u32
+    /// The incremented PC, or the target once a jump or branch replaced it
+    pub pc: u32,
+    /// Where the instruction itself was fetched from
+    pub address: u32
 }
 
+#[derive(Copy, Clone)]
 pub struct MEMWBBuffer {
     pub instruction: Option<Instruction>,
     pub data: u32,
-    pub pc: u32
+    /// The incremented PC, or the target once a jump or branch replaced it
+    pub pc: u32,
+    /// Where the instruction itself was fetched from
+    pub address: u32
 }
 
 impl IFIDBuffer {
@@ -54,7 +63,8 @@ impl EXMEMBuffer {
             instruction: None,
             alu_result: 0,
             data_2: 0,
-            pc: 0
+            pc: 0,
+            address: 0
         }
     }
 }
@@ -64,11 +74,36 @@ impl MEMWBBuffer {
         Self {
             instruction: None,
             data: 0,
-            pc: 0
+            pc: 0,
+            address: 0
         }
     }
 }
 
+impl Default for IFIDBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for IDEXBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for EXMEMBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for MEMWBBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl std::fmt::Display for IFIDBuffer {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         writeln!(f, "IF/ID Buffer:")?;
@@ -105,13 +140,21 @@ impl std::fmt::Display for EXMEMBuffer {
         }
         writeln!(f, "    ALU Result: {:#x}", self.alu_result)?;
         writeln!(f, "    PC: {:#x}", self.pc)?;
+        writeln!(f, "    Address: {:#x}", self.address)?;
         Ok(())
     }
 }
 
 impl std::fmt::Display for MEMWBBuffer {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        writeln!(f, "MEM/WB Buffer")?;
+        writeln!(f, "MEM/WB Buffer:")?;
+        match &self.instruction {
+            Some(instruction) => write!(f, "    Instruction:\n{}", instruction)?,
+            None => writeln!(f, "    No instruction")?
+        }
+        writeln!(f, "    Data: {:#x}", self.data)?;
+        writeln!(f, "    PC: {:#x}", self.pc)?;
+        writeln!(f, "    Address: {:#x}", self.address)?;
         Ok(())
     }
 }
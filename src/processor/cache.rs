@@ -0,0 +1,653 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use crate::processor::segment::Access;
+
+/// Accesses kept in each cache's access log
+pub const ACCESS_LOG_LIMIT: usize = 256;
+
+/// Which line of a full set is evicted to make room
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplacementPolicy {
+    /// Least recently used
+    Lru,
+    /// Oldest fill
+    Fifo,
+    Random
+}
+
+/// When stores reach the next level
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WritePolicy {
+    /// Only once a dirty line is evicted
+    WriteBack,
+    /// On every store
+    WriteThrough
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CacheConfig {
+    /// Total capacity in bytes
+    pub size: u32,
+    pub block_size: u32,
+    /// Lines per set, `size / block_size` makes the cache fully associative
+    pub associativity: u32,
+    pub replacement: ReplacementPolicy,
+    pub write_policy: WritePolicy,
+    /// Whether a store that misses brings its block into the cache
+    pub write_allocate: bool,
+    /// Cycles every access waits before the lookup completes. L1 hits are
+    /// otherwise free, so this is usually only set for an L2.
+    pub latency: u32,
+    /// Cycles a miss costs on top of the transfer from the level below
+    pub miss_penalty: u32
+}
+
+/// Why a cache configuration was rejected
+#[derive(Clone, Debug, PartialEq)]
+pub enum CacheConfigError {
+    /// Size, block size and set count must be powers of two
+    NotPowerOfTwo(&'static str),
+    /// The size does not hold a whole number of sets
+    Geometry,
+    /// A spec string that could not be parsed
+    Invalid(String)
+}
+
+/// Hit and miss counts since the cache was created or last reset
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub reads: u64,
+    pub read_misses: u64,
+    pub writes: u64,
+    pub write_misses: u64,
+    pub compulsory_misses: u64,
+    pub capacity_misses: u64,
+    pub conflict_misses: u64,
+    /// Lines dropped to make room or to keep an inclusive L2 inclusive
+    pub evictions: u64,
+    /// Dirty lines written to the next level on eviction
+    pub writebacks: u64
+}
+
+/// What an access asks of the next level down
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CacheOutcome {
+    pub hit: bool,
+    /// Block address read to fill the line
+    pub fill: Option<u32>,
+    /// Block address of the line dropped to make room
+    pub evicted: Option<u32>,
+    /// Same block as `evicted` when the line was dirty and has to be written back
+    pub writeback: Option<u32>,
+    /// Store passed on because of write-through or no-allocate
+    pub write_through: Option<u32>
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct CacheLine {
+    valid: bool,
+    tag: u32,
+    dirty: bool,
+    last_used: u64,
+    filled: u64
+}
+
+/// Why an access hit or missed, following the three C model
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessClass {
+    Hit,
+    /// First access to the block, any cache would have missed
+    Compulsory,
+    /// A fully associative cache of the same size would have missed too
+    Capacity,
+    /// Only missed because too many blocks map to the same set
+    Conflict
+}
+
+/// An entry in a cache's access log
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoggedAccess {
+    pub address: u32,
+    pub access: Access,
+    pub set: usize,
+    /// Way the block ended up in, `None` for a store that missed without
+    /// allocating
+    pub way: Option<usize>,
+    pub class: AccessClass
+}
+
+/// One way of one set as shown to users
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineState {
+    pub valid: bool,
+    pub dirty: bool,
+    pub tag: u32,
+    /// Position among the valid lines of the set by last use, 0 for the most
+    /// recently used
+    pub lru: usize,
+    /// Whether an access this cycle landed on the line
+    pub touched: bool
+}
+
+/// Set associative cache model placed between the pipeline and [`Memory`].
+///
+/// Only tags are tracked, the data itself always lives in `Memory`, so a cache
+/// changes statistics and timing but never the values loaded. Cache contents
+/// are not part of snapshots.
+///
+/// [`Memory`]: crate::processor::memory::Memory
+#[derive(Clone)]
+pub struct Cache {
+    config: CacheConfig,
+    /// `associativity` ways per set
+    sets: Vec<Vec<CacheLine>>,
+    stats: CacheStats,
+    /// Counts accesses to order lines for LRU and FIFO
+    clock: u64,
+    /// Fixed seed so random replacement is reproducible
+    generator: StdRng,
+    /// Every block number ever accessed, to spot compulsory misses
+    seen: HashSet<u32>,
+    /// Block numbers a fully associative LRU cache of the same size would
+    /// hold, most recently used last, to tell capacity from conflict misses
+    shadow: VecDeque<u32>,
+    log: VecDeque<LoggedAccess>,
+    /// Set and way accessed since the processor last started a cycle
+    touched: Option<(usize, usize)>
+}
+
+impl CacheConfig {
+    /// A write-back, write-allocate cache with LRU replacement and no latency
+    /// or miss penalty
+    pub fn new(size: u32, block_size: u32, associativity: u32) -> Self {
+        Self {
+            size,
+            block_size,
+            associativity,
+            replacement: ReplacementPolicy::Lru,
+            write_policy: WritePolicy::WriteBack,
+            write_allocate: true,
+            latency: 0,
+            miss_penalty: 0
+        }
+    }
+
+    pub fn sets(&self) -> u32 {
+        self.size / (self.block_size * self.associativity)
+    }
+
+    fn validate(&self) -> Result<(), CacheConfigError> {
+        if !self.size.is_power_of_two() {
+            return Err(CacheConfigError::NotPowerOfTwo("size"));
+        }
+        if !self.block_size.is_power_of_two() || self.block_size < 4 {
+            return Err(CacheConfigError::NotPowerOfTwo("block size"));
+        }
+        match self.block_size.checked_mul(self.associativity) {
+            Some(set_bytes) if set_bytes != 0 && self.size.is_multiple_of(set_bytes) => {}
+            _ => return Err(CacheConfigError::Geometry)
+        }
+        if !self.sets().is_power_of_two() {
+            return Err(CacheConfigError::NotPowerOfTwo("set count"));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for CacheConfig {
+    type Err = CacheConfigError;
+
+    /// Parses `size,block,ways` followed by any of `lru`, `fifo`, `random`,
+    /// `wb`, `wt`, `wa`, `nwa`, `latency=N` and `penalty=N`, for example
+    /// `4096,16,2,fifo,wt,nwa,penalty=2`
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut fields = spec.split(',').map(str::trim);
+        let mut number = |name: &str| {
+            fields
+                .next()
+                .and_then(|field| field.parse::<u32>().ok())
+                .ok_or(CacheConfigError::Invalid(format!("missing or bad {}", name)))
+        };
+        let mut config = CacheConfig::new(number("size")?, number("block size")?, number("ways")?);
+        for option in spec.split(',').skip(3).map(str::trim) {
+            match option {
+                "lru" => config.replacement = ReplacementPolicy::Lru,
+                "fifo" => config.replacement = ReplacementPolicy::Fifo,
+                "random" => config.replacement = ReplacementPolicy::Random,
+                "wb" => config.write_policy = WritePolicy::WriteBack,
+                "wt" => config.write_policy = WritePolicy::WriteThrough,
+                "wa" => config.write_allocate = true,
+                "nwa" => config.write_allocate = false,
+                other if other.starts_with("latency=") => {
+                    config.latency = parse_cycles(other)?
+                }
+                other if other.starts_with("penalty=") => {
+                    config.miss_penalty = parse_cycles(other)?
+                }
+                other => return Err(CacheConfigError::Invalid(format!("unknown option {}", other)))
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl CacheOutcome {
+    fn new() -> Self {
+        Self {
+            hit: false,
+            fill: None,
+            evicted: None,
+            writeback: None,
+            write_through: None
+        }
+    }
+}
+
+impl CacheStats {
+    pub fn accesses(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.read_misses + self.write_misses
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.accesses() - self.misses()
+    }
+
+    /// Fraction of accesses that hit, zero before the first access
+    pub fn hit_rate(&self) -> f64 {
+        match self.accesses() {
+            0 => 0.0,
+            accesses => self.hits() as f64 / accesses as f64
+        }
+    }
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Result<Self, CacheConfigError> {
+        config.validate()?;
+        let ways = vec![CacheLine::default(); config.associativity as usize];
+        Ok(Self {
+            config,
+            sets: vec![ways; config.sets() as usize],
+            stats: CacheStats::default(),
+            clock: 0,
+            generator: StdRng::seed_from_u64(0),
+            seen: HashSet::new(),
+            shadow: VecDeque::new(),
+            log: VecDeque::new(),
+            touched: None
+        })
+    }
+
+    pub fn get_config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    pub fn get_stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
+    /// Empties every set, dirty lines are dropped without a writeback
+    pub fn invalidate(&mut self) {
+        for line in self.sets.iter_mut().flatten() {
+            line.valid = false;
+        }
+    }
+
+    /// Whether the block holding `address` is in the cache
+    pub fn contains(&self, address: u32) -> bool {
+        let (set, tag) = self.locate(address);
+        self.find(set, tag).is_some()
+    }
+
+    /// The most recent accesses, oldest first, up to [`ACCESS_LOG_LIMIT`]
+    pub fn get_access_log(&self) -> impl Iterator<Item = &LoggedAccess> {
+        self.log.iter()
+    }
+
+    pub fn get_line(&self, set: usize, way: usize) -> LineState {
+        let line = self.sets[set][way];
+        let lru = self.sets[set]
+            .iter()
+            .filter(|other| other.valid && other.last_used > line.last_used)
+            .count();
+        LineState {
+            valid: line.valid,
+            dirty: line.dirty,
+            tag: line.tag,
+            lru,
+            touched: self.touched == Some((set, way))
+        }
+    }
+
+    /// Set and way accessed this cycle
+    pub fn get_touched(&self) -> Option<(usize, usize)> {
+        self.touched
+    }
+
+    /// Forgets which line was touched, called as every cycle starts
+    pub(crate) fn begin_cycle(&mut self) {
+        self.touched = None;
+    }
+
+    /// Looks up `address`, updating the lines and statistics. Fetches use
+    /// [`Access::Execute`] which counts as a read.
+    pub fn access(&mut self, address: u32, access: Access) -> CacheOutcome {
+        self.clock += 1;
+        let write = access == Access::Write;
+        let write_back = self.config.write_policy == WritePolicy::WriteBack;
+        let (set_index, tag) = self.locate(address);
+        let mut outcome = CacheOutcome::new();
+        if write {
+            self.stats.writes += 1;
+        } else {
+            self.stats.reads += 1;
+        }
+        if write && !write_back {
+            outcome.write_through = Some(address);
+        }
+
+        if let Some(way) = self.find(set_index, tag) {
+            let line = &mut self.sets[set_index][way];
+            line.last_used = self.clock;
+            line.dirty |= write && write_back;
+            outcome.hit = true;
+            self.record(address, access, set_index, Some(way), true);
+            return outcome;
+        }
+
+        if write {
+            self.stats.write_misses += 1;
+            if !self.config.write_allocate {
+                outcome.write_through = Some(address);
+                self.record(address, access, set_index, None, false);
+                return outcome;
+            }
+        } else {
+            self.stats.read_misses += 1;
+        }
+        outcome.fill = Some(self.block_address(set_index, tag));
+        let way = self.place(set_index, tag, write && write_back, &mut outcome);
+        self.record(address, access, set_index, Some(way), false);
+        outcome
+    }
+
+    /// Puts the block holding `address` in the cache without counting an
+    /// access, as an exclusive L2 does with lines the L1 evicts
+    pub fn insert(&mut self, address: u32, dirty: bool) -> CacheOutcome {
+        self.clock += 1;
+        let (set_index, tag) = self.locate(address);
+        let mut outcome = CacheOutcome::new();
+        let way = match self.find(set_index, tag) {
+            Some(way) => {
+                let line = &mut self.sets[set_index][way];
+                line.dirty |= dirty;
+                line.last_used = self.clock;
+                way
+            }
+            None => self.place(set_index, tag, dirty, &mut outcome)
+        };
+        self.touched = Some((set_index, way));
+        outcome
+    }
+
+    /// Reads the block holding `address` and removes it, as an exclusive L2
+    /// hands lines up to the L1. Returns whether the line was dirty, `None`
+    /// on a miss, which allocates nothing.
+    pub fn take(&mut self, address: u32) -> Option<bool> {
+        self.stats.reads += 1;
+        let (set_index, tag) = self.locate(address);
+        match self.find(set_index, tag) {
+            Some(way) => {
+                self.record(address, Access::Read, set_index, Some(way), true);
+                let line = &mut self.sets[set_index][way];
+                line.valid = false;
+                Some(line.dirty)
+            }
+            None => {
+                self.stats.read_misses += 1;
+                self.record(address, Access::Read, set_index, None, false);
+                None
+            }
+        }
+    }
+
+    /// Drops the block holding `address`, counted as an eviction. Returns
+    /// whether it was dirty and needs writing back, `None` if it was absent.
+    pub fn invalidate_block(&mut self, address: u32) -> Option<bool> {
+        let (set_index, tag) = self.locate(address);
+        let way = self.find(set_index, tag)?;
+        let line = &mut self.sets[set_index][way];
+        line.valid = false;
+        self.stats.evictions += 1;
+        if line.dirty {
+            self.stats.writebacks += 1;
+        }
+        Some(line.dirty)
+    }
+
+    /// Marks the block holding `address` as modified if it is present
+    pub fn mark_dirty(&mut self, address: u32) {
+        let (set_index, tag) = self.locate(address);
+        if let Some(way) = self.find(set_index, tag) {
+            self.sets[set_index][way].dirty = true;
+        }
+    }
+
+    /// Way holding `tag` in a set
+    fn find(&self, set: usize, tag: u32) -> Option<usize> {
+        self.sets[set]
+            .iter()
+            .position(|line| line.valid && line.tag == tag)
+    }
+
+    /// Fills a line in a set, evicting one first when the set is full, and
+    /// returns its way
+    fn place(
+        &mut self,
+        set_index: usize,
+        tag: u32,
+        dirty: bool,
+        outcome: &mut CacheOutcome
+    ) -> usize {
+        let way = match self.sets[set_index].iter().position(|line| !line.valid) {
+            Some(way) => way,
+            None => {
+                let way = self.victim(set_index);
+                let evicted = self.sets[set_index][way];
+                let address = self.block_address(set_index, evicted.tag);
+                self.stats.evictions += 1;
+                outcome.evicted = Some(address);
+                if evicted.dirty {
+                    self.stats.writebacks += 1;
+                    outcome.writeback = Some(address);
+                }
+                way
+            }
+        };
+        self.sets[set_index][way] = CacheLine {
+            valid: true,
+            tag,
+            dirty,
+            last_used: self.clock,
+            filled: self.clock
+        };
+        way
+    }
+
+    /// Classifies an access, updates the shadow cache and logs it
+    fn record(
+        &mut self,
+        address: u32,
+        access: Access,
+        set: usize,
+        way: Option<usize>,
+        hit: bool
+    ) {
+        let block = address / self.config.block_size;
+        let shadow_hit = match self.shadow.iter().position(|other| *other == block) {
+            Some(index) => {
+                self.shadow.remove(index);
+                true
+            }
+            None => false
+        };
+        self.shadow.push_back(block);
+        if self.shadow.len() > (self.config.size / self.config.block_size) as usize {
+            self.shadow.pop_front();
+        }
+        let first = self.seen.insert(block);
+        let class = if hit {
+            AccessClass::Hit
+        } else if first {
+            self.stats.compulsory_misses += 1;
+            AccessClass::Compulsory
+        } else if !shadow_hit {
+            self.stats.capacity_misses += 1;
+            AccessClass::Capacity
+        } else {
+            self.stats.conflict_misses += 1;
+            AccessClass::Conflict
+        };
+
+        if self.log.len() == ACCESS_LOG_LIMIT {
+            self.log.pop_front();
+        }
+        self.log.push_back(LoggedAccess {
+            address,
+            access,
+            set,
+            way,
+            class
+        });
+        if let Some(way) = way {
+            self.touched = Some((set, way));
+        }
+    }
+
+    /// Set index and tag of the block holding `address`
+    fn locate(&self, address: u32) -> (usize, u32) {
+        let block = address / self.config.block_size;
+        let sets = self.config.sets();
+        ((block % sets) as usize, block / sets)
+    }
+
+    fn block_address(&self, set: usize, tag: u32) -> u32 {
+        (tag * self.config.sets() + set as u32) * self.config.block_size
+    }
+
+    /// Way to evict from a full set
+    fn victim(&mut self, set: usize) -> usize {
+        let lines = &self.sets[set];
+        match self.config.replacement {
+            ReplacementPolicy::Lru => (0..lines.len()).min_by_key(|&i| lines[i].last_used).unwrap(),
+            ReplacementPolicy::Fifo => (0..lines.len()).min_by_key(|&i| lines[i].filled).unwrap(),
+            ReplacementPolicy::Random => self.generator.random_range(0..lines.len())
+        }
+    }
+}
+
+impl Display for Cache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.config, self.stats)
+    }
+}
+
+impl Display for AccessClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccessClass::Hit => write!(f, "hit"),
+            AccessClass::Compulsory => write!(f, "compulsory miss"),
+            AccessClass::Capacity => write!(f, "capacity miss"),
+            AccessClass::Conflict => write!(f, "conflict miss")
+        }
+    }
+}
+
+impl Display for LoggedAccess {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let access = match self.access {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::Execute => "fetch"
+        };
+        write!(f, "{:#010x} {:<5} set {}", self.address, access, self.set)?;
+        if let Some(way) = self.way {
+            write!(f, " way {}", way)?;
+        }
+        write!(f, " {}", self.class)
+    }
+}
+
+impl Display for CacheConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bytes, {} byte blocks, {}-way, {:?}, {:?}, {}, {} cycle latency, \
+             {} cycle miss penalty",
+            self.size,
+            self.block_size,
+            self.associativity,
+            self.replacement,
+            self.write_policy,
+            if self.write_allocate { "write-allocate" } else { "no-write-allocate" },
+            self.latency,
+            self.miss_penalty
+        )
+    }
+}
+
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} reads ({} misses), {} writes ({} misses), {} compulsory, {} capacity, \
+             {} conflict, {} evictions, {} writebacks, {:.2}% hit rate",
+            self.reads,
+            self.read_misses,
+            self.writes,
+            self.write_misses,
+            self.compulsory_misses,
+            self.capacity_misses,
+            self.conflict_misses,
+            self.evictions,
+            self.writebacks,
+            self.hit_rate() * 100.0
+        )
+    }
+}
+
+/// Reads the cycle count out of a `name=N` spec option
+fn parse_cycles(option: &str) -> Result<u32, CacheConfigError> {
+    option
+        .split_once('=')
+        .and_then(|(_, cycles)| cycles.parse().ok())
+        .ok_or(CacheConfigError::Invalid(format!("bad {}", option)))
+}
+
+impl Display for CacheConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CacheConfigError::NotPowerOfTwo(field) => {
+                write!(f, "cache {} must be a power of two", field)
+            }
+            CacheConfigError::Geometry => {
+                write!(f, "cache size must hold a whole number of sets")
+            }
+            CacheConfigError::Invalid(reason) => write!(f, "bad cache spec: {}", reason)
+        }
+    }
+}
+
+impl std::error::Error for CacheConfigError {}
@@ -0,0 +1,385 @@
+use std::fmt::{Display, Formatter};
+use crate::processor::cache::{Cache, CacheOutcome};
+use crate::processor::memory::DataMemory;
+use crate::processor::pipeline_stats::{PipelineStats, StallCause};
+use crate::processor::segment::Access;
+use crate::processor::Processor;
+
+/// How the L2 relates to the L1 caches above it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InclusionPolicy {
+    /// Every block held by an L1 is also in the L2, blocks the L2 evicts are
+    /// invalidated in the L1s too
+    Inclusive,
+    /// A block lives in at most one level. An L1 miss that hits in the L2
+    /// moves the block up, lines the L1s evict move down into the L2.
+    Exclusive
+}
+
+/// One of the caches in a [`CacheHierarchy`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CacheLevel {
+    Instruction,
+    Data,
+    L2
+}
+
+impl CacheLevel {
+    pub const ALL: [CacheLevel; 3] = [CacheLevel::Instruction, CacheLevel::Data, CacheLevel::L2];
+
+    /// Parses the short names `i`, `d` and `l2` used by the debugger
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "i" | "instruction" | "l1i" => Some(CacheLevel::Instruction),
+            "d" | "data" | "l1d" => Some(CacheLevel::Data),
+            "l2" => Some(CacheLevel::L2),
+            _ => None
+        }
+    }
+}
+
+/// Split L1 instruction and data caches over an optional unified L2, in
+/// front of main memory. Levels that are not set are skipped, with no caches
+/// at all accesses never stall.
+///
+/// An access costs the latency of every level it reaches, the miss penalty of
+/// every level it misses in and the memory latency for every block moved to
+/// or from main memory. The processor indexes it with physical addresses.
+#[derive(Clone)]
+pub struct CacheHierarchy {
+    instruction: Option<Cache>,
+    data: Option<Cache>,
+    l2: Option<Cache>,
+    inclusion: InclusionPolicy,
+    /// Cycles every block transfer to or from main memory takes
+    memory_latency: u32
+}
+
+impl CacheHierarchy {
+    /// No caches, an inclusive policy once an L2 is added and no memory latency
+    pub fn new() -> Self {
+        Self {
+            instruction: None,
+            data: None,
+            l2: None,
+            inclusion: InclusionPolicy::Inclusive,
+            memory_latency: 0
+        }
+    }
+
+    pub fn set_instruction_cache(&mut self, cache: Option<Cache>) {
+        self.instruction = cache;
+    }
+
+    pub fn get_instruction_cache(&self) -> Option<&Cache> {
+        self.instruction.as_ref()
+    }
+
+    pub fn set_data_cache(&mut self, cache: Option<Cache>) {
+        self.data = cache;
+    }
+
+    pub fn get_data_cache(&self) -> Option<&Cache> {
+        self.data.as_ref()
+    }
+
+    pub fn set_l2_cache(&mut self, cache: Option<Cache>) {
+        self.l2 = cache;
+    }
+
+    pub fn get_l2_cache(&self) -> Option<&Cache> {
+        self.l2.as_ref()
+    }
+
+    pub fn get(&self, level: CacheLevel) -> Option<&Cache> {
+        match level {
+            CacheLevel::Instruction => self.get_instruction_cache(),
+            CacheLevel::Data => self.get_data_cache(),
+            CacheLevel::L2 => self.get_l2_cache()
+        }
+    }
+
+    /// Only applies to accesses made from now on, blocks already cached stay put
+    pub fn set_inclusion_policy(&mut self, inclusion: InclusionPolicy) {
+        self.inclusion = inclusion;
+    }
+
+    pub fn get_inclusion_policy(&self) -> InclusionPolicy {
+        self.inclusion
+    }
+
+    pub fn set_memory_latency(&mut self, cycles: u32) {
+        self.memory_latency = cycles;
+    }
+
+    pub fn get_memory_latency(&self) -> u32 {
+        self.memory_latency
+    }
+
+    /// Forgets which lines were touched, called as every cycle starts
+    pub(crate) fn begin_cycle(&mut self) {
+        for cache in [&mut self.instruction, &mut self.data, &mut self.l2]
+            .into_iter()
+            .flatten()
+        {
+            cache.begin_cycle();
+        }
+    }
+
+    /// Runs an access through the hierarchy and returns the cycles it costs.
+    /// Fetches use [`Access::Execute`] and go to the instruction cache.
+    pub fn access(&mut self, address: u32, access: Access) -> u32 {
+        let instruction = access == Access::Execute;
+        let l1 = if instruction {
+            self.instruction.as_mut()
+        } else {
+            self.data.as_mut()
+        };
+        let (outcome, config) = match l1 {
+            Some(cache) => (cache.access(address, access), *cache.get_config()),
+            None if self.l2.is_some() => return self.l2_access(address, access),
+            None => return 0
+        };
+        let mut cycles = config.latency;
+        if let Some(block) = outcome.fill {
+            cycles += config.miss_penalty + self.fill(block, instruction);
+        }
+        if let Some(block) = outcome.evicted {
+            cycles += self.evict(block, outcome.writeback.is_some());
+        }
+        if let Some(address) = outcome.write_through {
+            cycles += self.write_below(address);
+        }
+        cycles
+    }
+
+    /// Cycles to bring a block into an L1
+    fn fill(&mut self, block: u32, instruction: bool) -> u32 {
+        if self.l2.is_some() && self.inclusion == InclusionPolicy::Inclusive {
+            return self.l2_access(block, Access::Read);
+        }
+        let l2 = match self.l2.as_mut() {
+            Some(l2) => l2,
+            None => return self.memory_latency
+        };
+        let config = *l2.get_config();
+        match l2.take(block) {
+            Some(dirty) => {
+                // The modified data moves up with the block
+                if dirty {
+                    let l1 = if instruction { &mut self.instruction } else { &mut self.data };
+                    if let Some(l1) = l1.as_mut() {
+                        l1.mark_dirty(block);
+                    }
+                }
+                config.latency
+            }
+            None => config.latency + config.miss_penalty + self.memory_latency
+        }
+    }
+
+    /// Cycles to deal with a line an L1 evicted
+    fn evict(&mut self, block: u32, dirty: bool) -> u32 {
+        match (self.l2.as_mut(), self.inclusion) {
+            (None, _) | (Some(_), InclusionPolicy::Inclusive) if !dirty => 0,
+            (None, _) => self.memory_latency,
+            (Some(_), InclusionPolicy::Inclusive) => self.l2_access(block, Access::Write),
+            (Some(l2), InclusionPolicy::Exclusive) => {
+                let latency = l2.get_config().latency;
+                let outcome = l2.insert(block, dirty);
+                latency + transfers(&outcome) * self.memory_latency
+            }
+        }
+    }
+
+    /// Cycles for a store an L1 passed on without keeping it
+    fn write_below(&mut self, address: u32) -> u32 {
+        match (self.l2.as_ref(), self.inclusion) {
+            (None, _) => self.memory_latency,
+            (Some(l2), InclusionPolicy::Exclusive) if !l2.contains(address) => {
+                self.memory_latency
+            }
+            (Some(_), _) => self.l2_access(address, Access::Write)
+        }
+    }
+
+    /// Cycles for an access to the L2, which has to be present
+    fn l2_access(&mut self, address: u32, access: Access) -> u32 {
+        let l2 = self.l2.as_mut().unwrap();
+        let outcome = l2.access(address, access);
+        let config = *l2.get_config();
+        let mut cycles = config.latency + transfers(&outcome) * self.memory_latency;
+        if outcome.fill.is_some() {
+            cycles += config.miss_penalty;
+        }
+        if let (Some(block), InclusionPolicy::Inclusive) = (outcome.evicted, self.inclusion) {
+            cycles += self.back_invalidate(block, config.block_size);
+        }
+        cycles
+    }
+
+    /// Removes an evicted L2 block from both L1s, writing dirty lines to memory
+    fn back_invalidate(&mut self, block: u32, block_size: u32) -> u32 {
+        let mut cycles = 0;
+        for l1 in [self.instruction.as_mut(), self.data.as_mut()].into_iter().flatten() {
+            let step = l1.get_config().block_size;
+            for offset in (0..block_size).step_by(step as usize) {
+                if l1.invalidate_block(block.wrapping_add(offset)) == Some(true) {
+                    cycles += self.memory_latency;
+                }
+            }
+        }
+        cycles
+    }
+}
+
+/// Block transfers to or from memory an L2 access caused
+fn transfers(outcome: &CacheOutcome) -> u32 {
+    [outcome.fill, outcome.writeback, outcome.write_through]
+        .iter()
+        .filter(|transfer| transfer.is_some())
+        .count() as u32
+}
+
+impl Default for CacheHierarchy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Processor {
+    /// Puts `cache` in front of instruction fetches
+    pub fn set_instruction_cache(&mut self, cache: Cache) {
+        self.caches.set_instruction_cache(Some(cache));
+    }
+
+    pub fn clear_instruction_cache(&mut self) {
+        self.caches.set_instruction_cache(None);
+    }
+
+    pub fn get_instruction_cache(&self) -> Option<&Cache> {
+        self.caches.get_instruction_cache()
+    }
+
+    /// Puts `cache` in front of loads and stores
+    pub fn set_data_cache(&mut self, cache: Cache) {
+        self.caches.set_data_cache(Some(cache));
+    }
+
+    pub fn clear_data_cache(&mut self) {
+        self.caches.set_data_cache(None);
+    }
+
+    pub fn get_data_cache(&self) -> Option<&Cache> {
+        self.caches.get_data_cache()
+    }
+
+    /// Puts `cache` behind both L1 caches, shared by instructions and data
+    pub fn set_l2_cache(&mut self, cache: Cache) {
+        self.caches.set_l2_cache(Some(cache));
+    }
+
+    pub fn clear_l2_cache(&mut self) {
+        self.caches.set_l2_cache(None);
+    }
+
+    pub fn get_l2_cache(&self) -> Option<&Cache> {
+        self.caches.get_l2_cache()
+    }
+
+    pub fn set_inclusion_policy(&mut self, inclusion: InclusionPolicy) {
+        self.caches.set_inclusion_policy(inclusion);
+    }
+
+    pub fn get_caches(&self) -> &CacheHierarchy {
+        &self.caches
+    }
+
+    /// Sets the cycles every block transfer to or from main memory takes
+    pub fn set_memory_latency(&mut self, cycles: u32) {
+        self.caches.set_memory_latency(cycles);
+    }
+
+    pub fn get_memory_latency(&self) -> u32 {
+        self.caches.get_memory_latency()
+    }
+
+    pub fn get_pipeline_stats(&self) -> &PipelineStats {
+        &self.pipeline_stats
+    }
+
+    /// Why the last cycle stalled, if it did
+    pub fn get_stall_cause(&self) -> Option<StallCause> {
+        self.stall_cause
+    }
+
+    /// Whether fetch has to keep waiting on the caches. The hierarchy is
+    /// consulted once per fetch, the cycles it costs are counted down before
+    /// the instruction is let through.
+    pub(crate) fn wait_for_instruction(&mut self) -> bool {
+        let wait = match self.fetch_wait {
+            Some(wait) => wait,
+            None => {
+                let pc = self.program_counter.get();
+                match self.cached_address(pc, 4, Access::Execute) {
+                    Some(physical) => self.caches.access(physical, Access::Execute),
+                    None => 0
+                }
+            }
+        };
+        self.fetch_wait = wait.checked_sub(1);
+        wait > 0
+    }
+
+    /// Whether the load or store in MEM has to keep waiting on the caches
+    pub(crate) fn wait_for_data(&mut self) -> bool {
+        let wait = match self.memory_wait {
+            Some(wait) => wait,
+            None => match DataMemory::access(&self.ex_mem_buffer) {
+                Some((address, size, access)) => {
+                    match self.cached_address(address, size.bytes(), access) {
+                        Some(physical) => self.caches.access(physical, access),
+                        None => 0
+                    }
+                }
+                None => 0
+            }
+        };
+        self.memory_wait = wait.checked_sub(1);
+        wait > 0
+    }
+
+    /// Physical address the caches see for an access, `None` if it goes
+    /// around them. Accesses that are about to fault do not reach the
+    /// caches, neither do device registers, kseg1 and uncached TLB pages.
+    fn cached_address(&self, address: u32, length: u32, access: Access) -> Option<u32> {
+        let physical = self.memory.translate(address, length, access).ok()?;
+        let cached = self.memory.is_cached(address) && !self.memory.is_device(physical);
+        cached.then_some(physical)
+    }
+}
+
+impl Display for CacheLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CacheLevel::Instruction => write!(f, "L1 instruction cache"),
+            CacheLevel::Data => write!(f, "L1 data cache"),
+            CacheLevel::L2 => write!(f, "L2 cache")
+        }
+    }
+}
+
+impl Display for CacheHierarchy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(cache) = &self.instruction {
+            writeln!(f, "L1 instruction cache: {}", cache)?;
+        }
+        if let Some(cache) = &self.data {
+            writeln!(f, "L1 data cache: {}", cache)?;
+        }
+        if let Some(cache) = &self.l2 {
+            writeln!(f, "L2 cache ({:?}): {}", self.inclusion, cache)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt::{Display, Formatter};
+use crate::processor::cache::{Cache, LineState};
+
+/// Text view of what a cache holds:
+///
+/// ```text
+/// set   way 0             way 1
+///   2   V- 0x00004002 1  [VD 0x00001000 0]
+/// 63 of 64 sets empty
+/// ```
+///
+/// Each way shows its valid and dirty bits, its tag and its LRU position, 0
+/// for the most recently used line of the set. Sets with no valid lines are
+/// left out and the line accessed this cycle is bracketed.
+pub struct CacheView<'a> {
+    cache: &'a Cache
+}
+
+impl<'a> CacheView<'a> {
+    pub fn new(cache: &'a Cache) -> Self {
+        Self { cache }
+    }
+}
+
+/// One way as shown in the view, without the brackets
+pub fn format_line(line: &LineState) -> String {
+    if !line.valid {
+        return "-- ---------- -".to_string();
+    }
+    format!(
+        "V{} {:#010x} {}",
+        if line.dirty { 'D' } else { '-' },
+        line.tag,
+        line.lru
+    )
+}
+
+impl Display for CacheView<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let config = self.cache.get_config();
+        let sets = config.sets() as usize;
+        let ways = config.associativity as usize;
+
+        write!(f, "set ")?;
+        for way in 0..ways {
+            write!(f, "  {:<16}", format!("way {}", way))?;
+        }
+        writeln!(f)?;
+
+        let mut empty = 0;
+        for set in 0..sets {
+            let lines: Vec<LineState> = (0..ways)
+                .map(|way| self.cache.get_line(set, way))
+                .collect();
+            if lines.iter().all(|line| !line.valid) {
+                empty += 1;
+                continue;
+            }
+            write!(f, "{:>3} ", set)?;
+            for line in &lines {
+                let text = format_line(line);
+                if line.touched {
+                    write!(f, " [{}]", text)?;
+                } else {
+                    write!(f, "  {} ", text)?;
+                }
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "{} of {} sets empty", empty, sets)
+    }
+}
@@ -0,0 +1,206 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use log::warn;
+use num_traits::FromPrimitive;
+use crate::processor::alu::{FunctionCode, OpCode};
+use crate::processor::buffer::IFIDBuffer;
+use crate::processor::history::CycleWrites;
+use crate::processor::instruction::{Instruction, InstructionType};
+use crate::processor::trace::memory_runs;
+use crate::processor::{Processor, Status};
+
+/// Destination of the commit log along with the side effects of syscalls
+/// that have run but not yet reached writeback
+pub(crate) struct CommitLog {
+    writer: Box<dyn Write>,
+    syscall_effects: Vec<(u32, Vec<String>)>
+}
+
+impl Processor {
+    /// Writes a line for every instruction that reaches writeback, in program
+    /// order:
+    ///
+    /// ```text
+    /// 0x00400000 0x3c091001 $t1=0x10010000 # lui $t1, 0x1001
+    /// 0x00400010 0xad280008 [0x10010008]=0x00000055 # sw $t0, 8($t1)
+    /// 0x00400018 0x00000000 - # nop
+    /// ```
+    ///
+    /// The columns are the instruction address, its encoding, what it wrote
+    /// and its disassembly. Effects are separated by spaces, `-` when there are
+    /// none. Registers use their ABI names with `$f0` to `$f31` for floating
+    /// point registers, memory writes show 2, 4 or 8 hex digits for bytes,
+    /// halfwords and words. Syscalls list everything they wrote. HI and LO are
+    /// not logged.
+    ///
+    /// Everything after `#` is for people, strip it with `cut -d'#' -f1` before
+    /// diffing against a log from another simulator. Instructions still in the
+    /// pipeline when an exit syscall halts it are never written back so they
+    /// do not appear.
+    pub fn set_commit_log_writer(&mut self, writer: Box<dyn Write>) {
+        self.commit_log = Some(CommitLog {
+            writer,
+            syscall_effects: Vec::new()
+        });
+    }
+
+    pub fn set_commit_log_file<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        self.set_commit_log_writer(Box::new(BufWriter::new(File::create(path)?)));
+        Ok(())
+    }
+
+    /// Stops the commit log, flushing whatever is still buffered
+    pub fn clear_commit_log(&mut self) {
+        if let Some(mut log) = self.commit_log.take() {
+            let _ = log.writer.flush();
+        }
+    }
+
+    /// Splits the cycle's writes between the committing instruction and a
+    /// syscall decoded this cycle, then logs whatever committed
+    pub(crate) fn end_commit_log(&mut self, if_id_buffer: IFIDBuffer, writes: &CycleWrites) {
+        let commit = self.last_commit;
+        let destination = commit.and_then(|commit| commit.instruction.destination());
+        let store = commit.and_then(|commit| store_size(&commit.instruction));
+        let mut commit_effects = Vec::new();
+        let mut other_effects = Vec::new();
+
+        let mut written_back = false;
+        for (i, (register, _)) in writes.registers.iter().enumerate() {
+            // The value written is what the next write to the register replaced
+            let value = writes.registers[i + 1..]
+                .iter()
+                .find(|(later, _)| later == register)
+                .map_or(self.registers.get(*register), |(_, previous)| *previous);
+            let effect = format!("${}={:#010x}", register.name(), value);
+            if !written_back && destination == Some(*register as u8) {
+                written_back = true;
+                commit_effects.push(effect);
+            } else {
+                other_effects.push(effect);
+            }
+        }
+        for (i, (index, _)) in writes.float_registers.iter().enumerate() {
+            let value = writes.float_registers[i + 1..]
+                .iter()
+                .find(|(later, _)| later == index)
+                .map_or(self.float_registers.get(*index), |(_, previous)| *previous);
+            other_effects.push(format!("$f{}={:#010x}", index, value));
+        }
+        // Stores leave their virtual address in the MEM/WB buffer, the
+        // journal holds physical ones
+        let store_address = self.mem_wb_buffer.data;
+        let store_physical = self.memory.lookup(store_address).unwrap_or(store_address);
+        for (address, size) in memory_runs(&writes.memory) {
+            let from_store = store.is_some_and(|store_size| {
+                address.wrapping_sub(store_physical) < store_size
+            });
+            if !from_store {
+                other_effects.push(self.memory_effect(address, address, size));
+            }
+        }
+        if let Some(size) = store {
+            commit_effects.push(self.memory_effect(store_address, store_physical, size));
+        }
+
+        let decoded_syscall = if_id_buffer
+            .instruction
+            .filter(is_syscall)
+            // Buffers carry the already incremented PC
+            .map(|_| if_id_buffer.pc.wrapping_sub(4));
+        let exited = matches!(self.status, Status::Exited(_));
+        let log = match self.commit_log.as_mut() {
+            Some(log) => log,
+            None => return
+        };
+        if let Some(address) = decoded_syscall {
+            if !other_effects.is_empty() {
+                log.syscall_effects.push((address, other_effects));
+            }
+        }
+
+        let mut lines = Vec::new();
+        if let Some(commit) = commit {
+            if is_syscall(&commit.instruction) {
+                commit_effects.extend(log.take_syscall_effects(commit.address));
+            }
+            lines.push(format_line(commit.address, &commit.instruction, &commit_effects));
+        }
+        // An exit syscall never reaches writeback, log it as it runs
+        if let (true, Some(address)) = (exited, decoded_syscall) {
+            let effects = log.take_syscall_effects(address);
+            lines.push(format_line(address, &if_id_buffer.instruction.unwrap(), &effects));
+        }
+        for line in lines {
+            if let Err(error) = writeln!(log.writer, "{}", line) {
+                warn!("Stopping the commit log after a write failed: {}", error);
+                self.commit_log = None;
+                return;
+            }
+        }
+    }
+
+    /// Shows the `size` bytes at `physical` as written to `address`
+    fn memory_effect(&self, address: u32, physical: u32, size: u32) -> String {
+        let mut value: u64 = 0;
+        for i in (0..size).rev() {
+            value = (value << 8) | self.memory.read_byte(physical.wrapping_add(i)) as u64;
+        }
+        format!(
+            "[{:#010x}]=0x{:0width$x}",
+            address,
+            value,
+            width = size as usize * 2
+        )
+    }
+}
+
+impl CommitLog {
+    fn take_syscall_effects(&mut self, address: u32) -> Vec<String> {
+        match self
+            .syscall_effects
+            .iter()
+            .position(|(pending, _)| *pending == address)
+        {
+            Some(index) => self.syscall_effects.remove(index).1,
+            None => Vec::new()
+        }
+    }
+}
+
+fn format_line(address: u32, instruction: &Instruction, effects: &[String]) -> String {
+    let effects = if effects.is_empty() {
+        "-".to_string()
+    } else {
+        effects.join(" ")
+    };
+    format!(
+        "{:#010x} {:#010x} {} # {}",
+        address,
+        instruction.word,
+        effects,
+        instruction.disassemble()
+    )
+}
+
+fn is_syscall(instruction: &Instruction) -> bool {
+    instruction.instruction_type == InstructionType::R &&
+        matches!(
+            instruction.funct.and_then(FunctionCode::from_u8),
+            Some(FunctionCode::Syscall)
+        )
+}
+
+/// Bytes written by a store instruction
+fn store_size(instruction: &Instruction) -> Option<u32> {
+    if instruction.instruction_type != InstructionType::I {
+        return None;
+    }
+    match OpCode::from_u8(instruction.opcode)? {
+        OpCode::Sb => Some(1),
+        OpCode::Sh => Some(2),
+        OpCode::Sw => Some(4),
+        _ => None
+    }
+}
@@ -0,0 +1,142 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Mutex, OnceLock};
+
+/// Where the syscalls read program input from and write program output to
+pub trait Console {
+    fn write(&mut self, text: &str);
+
+    fn write_error(&mut self, text: &str) {
+        self.write(text);
+    }
+
+    /// Reads a line without its trailing newline, `None` once input is exhausted
+    fn read_line(&mut self) -> Option<String>;
+
+    fn read_char(&mut self) -> Option<char>;
+
+    /// Takes a character of input if one is already waiting, without blocking.
+    /// Devices use this to receive keys while the program keeps running.
+    fn poll_char(&mut self) -> Option<char> {
+        None
+    }
+}
+
+/// The host's stdin and stdout
+pub struct StdConsole;
+
+/// Console fed from a fixed script of input that captures everything written.
+/// Clones share the same buffers so a test can keep one and hand the other to
+/// the processor.
+#[derive(Clone, Default)]
+pub struct ScriptedConsole {
+    input: Rc<RefCell<VecDeque<char>>>,
+    output: Rc<RefCell<String>>
+}
+
+impl Console for StdConsole {
+    fn write(&mut self, text: &str) {
+        print!("{}", text);
+        let _ = std::io::stdout().flush();
+    }
+
+    fn write_error(&mut self, text: &str) {
+        eprint!("{}", text);
+    }
+
+    fn read_line(&mut self) -> Option<String> {
+        let bytes = stdin_bytes().lock().ok()?;
+        let mut line = Vec::new();
+        loop {
+            match bytes.recv() {
+                Ok(b'\n') => break,
+                Ok(byte) => line.push(byte),
+                Err(_) if line.is_empty() => return None,
+                Err(_) => break
+            }
+        }
+        let line = String::from_utf8_lossy(&line);
+        Some(line.trim_end_matches('\r').to_string())
+    }
+
+    fn read_char(&mut self) -> Option<char> {
+        stdin_bytes().lock().ok()?.recv().ok().map(char::from)
+    }
+
+    fn poll_char(&mut self) -> Option<char> {
+        stdin_bytes().lock().ok()?.try_recv().ok().map(char::from)
+    }
+}
+
+/// Bytes of stdin read by a background thread, started the first time input
+/// is needed. Every read goes through it, so keys a device polls for and
+/// lines a syscall waits on come from one queue in the order they were typed.
+fn stdin_bytes() -> &'static Mutex<Receiver<u8>> {
+    static BYTES: OnceLock<Mutex<Receiver<u8>>> = OnceLock::new();
+    BYTES.get_or_init(|| {
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || {
+            let mut byte = [0];
+            while let Ok(1) = std::io::stdin().read(&mut byte) {
+                if sender.send(byte[0]).is_err() {
+                    break;
+                }
+            }
+        });
+        Mutex::new(receiver)
+    })
+}
+
+impl ScriptedConsole {
+    pub fn new(input: &str) -> Self {
+        let console = Self::default();
+        console.push_input(input);
+        console
+    }
+
+    pub fn push_input(&self, input: &str) {
+        self.input.borrow_mut().extend(input.chars());
+    }
+
+    /// Whether input is queued for the program to read
+    pub fn has_input(&self) -> bool {
+        !self.input.borrow().is_empty()
+    }
+
+    /// Everything written to the console so far
+    pub fn output(&self) -> String {
+        self.output.borrow().clone()
+    }
+}
+
+impl Console for ScriptedConsole {
+    fn write(&mut self, text: &str) {
+        self.output.borrow_mut().push_str(text);
+    }
+
+    fn read_line(&mut self) -> Option<String> {
+        let mut input = self.input.borrow_mut();
+        if input.is_empty() {
+            return None;
+        }
+        let mut line = String::new();
+        while let Some(character) = input.pop_front() {
+            if character == '\n' {
+                break;
+            }
+            line.push(character);
+        }
+        Some(line)
+    }
+
+    fn read_char(&mut self) -> Option<char> {
+        self.input.borrow_mut().pop_front()
+    }
+
+    fn poll_char(&mut self) -> Option<char> {
+        self.read_char()
+    }
+}
@@ -0,0 +1,247 @@
+use log::{trace, warn};
+use crate::processor::exception::{Exception, ExceptionCode};
+use crate::processor::tlb::{TlbEntry, TLB_ENTRIES, TLB_WIRED};
+use crate::processor::Processor;
+
+pub const INDEX: usize = 0;
+pub const RANDOM: usize = 1;
+pub const ENTRY_LO: usize = 2;
+pub const CONTEXT: usize = 4;
+pub const BAD_VADDR: usize = 8;
+pub const COUNT: usize = 9;
+pub const ENTRY_HI: usize = 10;
+pub const COMPARE: usize = 11;
+pub const STATUS: usize = 12;
+pub const CAUSE: usize = 13;
+pub const EPC: usize = 14;
+
+/// Status bit that enables interrupts
+pub const STATUS_IE: u32 = 0x1;
+/// Status bit set while an exception is being handled, masking interrupts
+pub const STATUS_EXL: u32 = 0x2;
+/// Status bit for user mode, it only takes effect while EXL is clear
+pub const STATUS_UM: u32 = 0x10;
+/// Status interrupt mask, one bit per Cause IP bit
+pub const STATUS_IM: u32 = 0xFF00;
+/// Status bit that sends exceptions to the boot vectors in ROM
+pub const STATUS_BEV: u32 = 0x0040_0000;
+/// Status bit that lets user mode use coprocessor 0 instructions
+pub const STATUS_CU0: u32 = 0x1000_0000;
+/// Cause interrupt pending bits, IP0 to IP7
+pub const CAUSE_IP: u32 = 0xFF00;
+/// Cause exception code field
+pub const CAUSE_EXC_CODE: u32 = 0x7C;
+/// The two software interrupt bits, the only ones `mtc0` can set in Cause
+const CAUSE_SOFTWARE: u32 = 0x0300;
+/// Interrupt line the Count/Compare timer raises
+pub const TIMER_INTERRUPT: u8 = 7;
+/// Where exceptions and interrupts send the program counter
+pub const EXCEPTION_VECTOR: u32 = 0x8000_0180;
+/// Where TLB misses on kuseg addresses go while EXL is clear
+pub const TLB_REFILL_VECTOR: u32 = 0x8000_0000;
+/// Where the processor starts after a reset, the start of the boot ROM
+pub const RESET_VECTOR: u32 = 0xBFC0_0000;
+/// [`EXCEPTION_VECTOR`] while Status BEV is set
+pub const BOOT_EXCEPTION_VECTOR: u32 = 0xBFC0_0380;
+/// [`TLB_REFILL_VECTOR`] while Status BEV is set
+pub const BOOT_TLB_REFILL_VECTOR: u32 = 0xBFC0_0200;
+
+/// Index bit `tlbp` sets when no entry matched
+pub const INDEX_P: u32 = 0x8000_0000;
+/// Index and Random field holding the entry number
+pub const INDEX_ENTRY: u32 = 0x3F00;
+/// Context field the program points at its page table
+pub const CONTEXT_PTE_BASE: u32 = 0xFFE0_0000;
+/// Context field holding the page that missed, as a page table offset
+pub const CONTEXT_BAD_VPN: u32 = 0x001F_FFFC;
+
+/// Status MARS starts programs with: user mode, every interrupt line unmasked
+/// and interrupts enabled
+const STATUS_RESET: u32 = 0x0000_FF11;
+
+/// `rs` field values that pick the coprocessor 0 operation
+const MF: u8 = 0x00;
+const MT: u8 = 0x04;
+const CO: u8 = 0x10;
+/// Function code of `eret` under [`CO`]
+const ERET: u8 = 0x18;
+/// Function codes of the TLB instructions under [`CO`]
+const TLBR: u8 = 0x01;
+const TLBWI: u8 = 0x02;
+const TLBWR: u8 = 0x06;
+const TLBP: u8 = 0x08;
+
+/// The coprocessor 0 instructions the processor understands
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cop0Operation {
+    /// Copies a CP0 register into `rt`
+    Mfc0,
+    /// Copies `rt` into a CP0 register
+    Mtc0,
+    /// Returns from an exception to EPC
+    Eret,
+    /// Reads the TLB entry Index points at into EntryHi and EntryLo
+    Tlbr,
+    /// Writes EntryHi and EntryLo to the TLB entry Index points at
+    Tlbwi,
+    /// Writes EntryHi and EntryLo to the TLB entry Random points at
+    Tlbwr,
+    /// Looks for the TLB entry matching EntryHi and puts it in Index
+    Tlbp
+}
+
+/// Coprocessor 0 system control registers, indexed by their `mfc0` number
+#[derive(Clone, Copy)]
+pub struct Cp0 {
+    r: [u32; 32]
+}
+
+impl Cp0 {
+    pub fn new() -> Self {
+        let mut r = [0; 32];
+        r[STATUS] = STATUS_RESET;
+        r[RANDOM] = ((TLB_ENTRIES - 1) as u32) << 8;
+        Self { r }
+    }
+
+    pub fn get(&self, index: usize) -> u32 {
+        trace!("Reading cp0 register: ${} ({})", index, name(index));
+        self.r[index]
+    }
+
+    pub fn set(&mut self, index: usize, value: u32) {
+        trace!("Writing cp0 register: ${} ({}) with value {:#x}", index, name(index), value);
+        self.r[index] = value;
+    }
+
+    /// Writes a register the way `mtc0` does. Only the software interrupt
+    /// bits of Cause are writable, Random is read only and writing Compare
+    /// acknowledges the timer.
+    pub fn write(&mut self, index: usize, value: u32) {
+        match index {
+            INDEX => self.set(INDEX, (self.r[INDEX] & INDEX_P) | (value & INDEX_ENTRY)),
+            RANDOM => {}
+            ENTRY_LO => self.set(ENTRY_LO, TlbEntry::new(0, value).entry_lo),
+            CONTEXT => {
+                let value = (self.r[CONTEXT] & CONTEXT_BAD_VPN) | (value & CONTEXT_PTE_BASE);
+                self.set(CONTEXT, value);
+            }
+            ENTRY_HI => self.set(ENTRY_HI, TlbEntry::new(value, 0).entry_hi),
+            CAUSE => {
+                let value = (self.r[CAUSE] & !CAUSE_SOFTWARE) | (value & CAUSE_SOFTWARE);
+                self.set(CAUSE, value);
+            }
+            COMPARE => {
+                self.set(COMPARE, value);
+                self.r[CAUSE] &= !(1 << (8 + TIMER_INTERRUPT));
+            }
+            _ => self.set(index, value)
+        }
+    }
+
+    /// Advances Count by one cycle, raising the timer interrupt when it
+    /// reaches Compare, and steps Random down through the unwired entries
+    pub(crate) fn tick(&mut self) {
+        let random = ((self.r[RANDOM] & INDEX_ENTRY) >> 8) as usize;
+        let random = if random <= TLB_WIRED { TLB_ENTRIES - 1 } else { random - 1 };
+        self.r[RANDOM] = (random as u32) << 8;
+        self.r[COUNT] = self.r[COUNT].wrapping_add(1);
+        if self.r[COUNT] == self.r[COMPARE] {
+            self.r[CAUSE] |= 1 << (8 + TIMER_INTERRUPT);
+        }
+    }
+}
+
+impl Cop0Operation {
+    /// Picks the operation out of a word with the COP0 opcode
+    pub fn decode(word: u32) -> Option<Self> {
+        match ((word >> 21) & 0x1F) as u8 {
+            MF => Some(Cop0Operation::Mfc0),
+            MT => Some(Cop0Operation::Mtc0),
+            CO => match (word & 0x3F) as u8 {
+                ERET => Some(Cop0Operation::Eret),
+                TLBR => Some(Cop0Operation::Tlbr),
+                TLBWI => Some(Cop0Operation::Tlbwi),
+                TLBWR => Some(Cop0Operation::Tlbwr),
+                TLBP => Some(Cop0Operation::Tlbp),
+                _ => None
+            },
+            _ => None
+        }
+    }
+}
+
+/// Name of a coprocessor 0 register, or `"reserved"` for ones that are not modelled
+pub fn name(index: usize) -> &'static str {
+    match index {
+        INDEX => "index",
+        RANDOM => "random",
+        ENTRY_LO => "entrylo",
+        CONTEXT => "context",
+        BAD_VADDR => "badvaddr",
+        COUNT => "count",
+        ENTRY_HI => "entryhi",
+        COMPARE => "compare",
+        STATUS => "status",
+        CAUSE => "cause",
+        EPC => "epc",
+        _ => "reserved"
+    }
+}
+
+impl Processor {
+    /// Carries out the coprocessor 0 instruction decode just moved into ID/EX.
+    /// Returns false while it has to wait, see [`Processor::access_pending`].
+    pub(crate) fn execute_cop0(&mut self) -> bool {
+        let instruction = match self.id_ex_buffer.instruction {
+            Some(instruction) => instruction,
+            None => return true
+        };
+        if self.access_pending() {
+            return false;
+        }
+        if self.is_user_mode() && self.cp0.get(STATUS) & STATUS_CU0 == 0 {
+            // Buffers carry the already incremented PC
+            let epc = self.id_ex_buffer.pc.wrapping_sub(4);
+            self.instruction_exception(Exception::new(ExceptionCode::CpU, epc));
+            return true;
+        }
+        let index = instruction.rd.unwrap_or(0) as usize;
+        match Cop0Operation::decode(instruction.word) {
+            Some(Cop0Operation::Mfc0) => {
+                self.id_ex_buffer.data_1 = match index {
+                    CAUSE => self.get_cause(),
+                    _ => self.cp0.get(index)
+                };
+            }
+            Some(Cop0Operation::Mtc0) => self.cp0.write(index, self.id_ex_buffer.data_2),
+            Some(Cop0Operation::Eret) => {
+                self.cp0.set(STATUS, self.cp0.get(STATUS) & !STATUS_EXL);
+                self.program_counter.set(self.cp0.get(EPC));
+                self.fetch_wait = None;
+            }
+            Some(operation) => self.execute_tlb(operation),
+            None => warn!("Ignoring unknown coprocessor 0 instruction {:#010x}", instruction.word)
+        }
+        true
+    }
+}
+
+impl Default for Cp0 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for Cp0 {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Coprocessor 0:")?;
+        for index in [
+            INDEX, RANDOM, ENTRY_LO, CONTEXT, BAD_VADDR, COUNT, ENTRY_HI, COMPARE, STATUS, CAUSE,
+            EPC
+        ] {
+            writeln!(f, "    ${} ({}): {:#010x}", index, name(index), self.r[index])?;
+        }
+        Ok(())
+    }
+}
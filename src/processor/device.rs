@@ -0,0 +1,171 @@
+use std::fmt::{Debug, Display, Formatter};
+use crate::processor::memory::Size;
+
+/// A peripheral mapped into the address space. Loads and stores the pipeline
+/// makes inside its range go to the device instead of memory, with offsets
+/// relative to the start of the range.
+///
+/// Only the pipeline reaches devices, the plain `read_*`/`write_*` methods
+/// of [`Memory`] see the bytes underneath. Snapshots keep what
+/// [`Device::save`] returns, but the reverse execution history cannot step
+/// back over a cycle whose load or store reached a device.
+///
+/// [`Memory`]: crate::processor::memory::Memory
+pub trait Device {
+    /// Name the device's segment gets in the memory map
+    fn name(&self) -> &'static str;
+
+    /// Bytes of address space the device takes up
+    fn size(&self) -> u32;
+
+    /// Handles a load, the result is zero extended like a memory load
+    fn read(&mut self, offset: u32, size: Size) -> u32;
+
+    /// Handles a store, `value` only holds the bytes being stored
+    fn write(&mut self, offset: u32, size: Size, value: u32);
+
+    /// Called once at the start of every cycle so devices can model delays
+    fn tick(&mut self) {}
+
+    /// Interrupt lines the device is asserting, bit `n` standing for Cause
+    /// bit `8 + n`
+    fn interrupts(&self) -> u8 {
+        0
+    }
+
+    /// State a snapshot keeps, nothing for a device without any
+    fn save(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Takes back the state [`Device::save`] returned, false if `state` is
+    /// not something it could have returned
+    fn restore(&mut self, state: &[u8]) -> bool {
+        state.is_empty()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeviceMapError {
+    /// A device has to take up at least one byte and stay below 4 GiB
+    BadRange { device: &'static str, start: u32 },
+    /// The range overlaps a segment or another device
+    Overlap {
+        device: &'static str,
+        segment: &'static str
+    }
+}
+
+/// Devices mapped into a [`Memory`], each at a fixed start address
+///
+/// [`Memory`]: crate::processor::memory::Memory
+#[derive(Default)]
+pub struct DeviceMap {
+    devices: Vec<(u32, Box<dyn Device>)>
+}
+
+impl DeviceMap {
+    pub fn new() -> Self {
+        Self {
+            devices: Vec::new()
+        }
+    }
+
+    /// Checking the range against the segment map is up to the caller
+    pub(crate) fn insert(&mut self, start: u32, device: Box<dyn Device>) {
+        self.devices.push((start, device));
+    }
+
+    /// Start address and device of every mapping, in the order they were made
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &dyn Device)> {
+        self.devices
+            .iter()
+            .map(|(start, device)| (*start, device.as_ref()))
+    }
+
+    /// The device whose range holds `address` along with the offset into it
+    fn find(&mut self, address: u32) -> Option<(&mut Box<dyn Device>, u32)> {
+        self.devices
+            .iter_mut()
+            .find(|(start, device)| address.wrapping_sub(*start) < device.size())
+            .map(|(start, device)| (device, address - *start))
+    }
+
+    pub fn contains(&self, address: u32) -> bool {
+        self.iter()
+            .any(|(start, device)| address.wrapping_sub(start) < device.size())
+    }
+
+    /// Passes a load on to the device at `address`, `None` if there is none
+    pub fn read(&mut self, address: u32, size: Size) -> Option<u32> {
+        let (device, offset) = self.find(address)?;
+        Some(device.read(offset, size))
+    }
+
+    /// Passes a store on to the device at `address`, returns whether there was one
+    pub fn write(&mut self, address: u32, size: Size, value: u32) -> bool {
+        match self.find(address) {
+            Some((device, offset)) => {
+                device.write(offset, size, value);
+                true
+            }
+            None => false
+        }
+    }
+
+    pub fn tick(&mut self) {
+        for (_, device) in &mut self.devices {
+            device.tick();
+        }
+    }
+
+    /// Interrupt lines asserted by any device, laid out like [`Device::interrupts`]
+    pub fn interrupts(&self) -> u8 {
+        self.iter().fold(0, |lines, (_, device)| lines | device.interrupts())
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// State of every device for a snapshot, in the order they were mapped
+    pub(crate) fn save(&self) -> Vec<Vec<u8>> {
+        self.iter().map(|(_, device)| device.save()).collect()
+    }
+
+    /// Hands each device its state from [`DeviceMap::save`], false as soon as
+    /// one rejects it
+    pub(crate) fn restore(&mut self, states: &[Vec<u8>]) -> bool {
+        self.devices
+            .iter_mut()
+            .zip(states)
+            .all(|((_, device), state)| device.restore(state))
+    }
+}
+
+impl Debug for DeviceMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.iter().map(|(start, device)| (start, device.name())))
+            .finish()
+    }
+}
+
+impl Display for DeviceMapError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceMapError::BadRange { device, start } => {
+                write!(f, "device {} does not fit at {:#010x}", device, start)
+            }
+            DeviceMapError::Overlap { device, segment } => {
+                write!(f, "device {} overlaps the {} segment", device, segment)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DeviceMapError {}
@@ -0,0 +1,363 @@
+use std::fmt::{Display, Formatter};
+use log::{debug, error};
+use crate::processor::buffer::{EXMEMBuffer, IDEXBuffer, IFIDBuffer, MEMWBBuffer};
+use crate::processor::cp0::{
+    BAD_VADDR,
+    BOOT_EXCEPTION_VECTOR,
+    BOOT_TLB_REFILL_VECTOR,
+    CAUSE,
+    CAUSE_EXC_CODE,
+    CONTEXT,
+    CONTEXT_BAD_VPN,
+    CONTEXT_PTE_BASE,
+    ENTRY_HI,
+    EPC,
+    EXCEPTION_VECTOR,
+    STATUS,
+    STATUS_BEV,
+    STATUS_EXL,
+    STATUS_UM,
+    TLB_REFILL_VECTOR
+};
+use crate::processor::memory::DataMemory;
+use crate::processor::segment::{Access, MemoryFault};
+use crate::processor::tlb::{ENTRY_HI_ASID, ENTRY_HI_VPN, KSEG0};
+use crate::processor::{Processor, Status};
+
+/// Exception codes as they appear in the Cause ExcCode field
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExceptionCode {
+    /// Interrupt
+    Int = 0,
+    /// Store to a TLB page that is not dirty
+    Mod = 1,
+    /// TLB miss on a load or fetch
+    TlbL = 2,
+    /// TLB miss on a store
+    TlbS = 3,
+    /// Address error on a load or fetch
+    AdEL = 4,
+    /// Address error on a store
+    AdES = 5,
+    /// Bus error on a fetch
+    Ibe = 6,
+    /// Bus error on a load or store
+    Dbe = 7,
+    Sys = 8,
+    Bp = 9,
+    /// Reserved instruction
+    Ri = 10,
+    /// Coprocessor unusable
+    CpU = 11,
+    /// Arithmetic overflow
+    Ov = 12,
+    Tr = 13
+}
+
+/// An exception along with the registers it sets in coprocessor 0
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Exception {
+    pub code: ExceptionCode,
+    /// Address of the instruction that raised it
+    pub epc: u32,
+    /// The address that could not be reached, for memory exceptions
+    pub bad_address: Option<u32>
+}
+
+/// What happens when an instruction raises an exception. Interrupts always
+/// go to the exception vector.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ExceptionHandler {
+    /// Stop the processor, memory faults leave [`Status::Faulted`] behind
+    #[default]
+    Halt,
+    /// Report the exception on the console and skip the instruction, like
+    /// the default handler SPIM loads from exceptions.s
+    Builtin,
+    /// Jump to the handler at the exception vector, see
+    /// [`Processor::load_kernel_program`]
+    Vector
+}
+
+impl ExceptionCode {
+    pub fn from_u8(code: u8) -> Option<Self> {
+        Some(match code {
+            0 => ExceptionCode::Int,
+            1 => ExceptionCode::Mod,
+            2 => ExceptionCode::TlbL,
+            3 => ExceptionCode::TlbS,
+            4 => ExceptionCode::AdEL,
+            5 => ExceptionCode::AdES,
+            6 => ExceptionCode::Ibe,
+            7 => ExceptionCode::Dbe,
+            8 => ExceptionCode::Sys,
+            9 => ExceptionCode::Bp,
+            10 => ExceptionCode::Ri,
+            11 => ExceptionCode::CpU,
+            12 => ExceptionCode::Ov,
+            13 => ExceptionCode::Tr,
+            _ => return None
+        })
+    }
+
+    /// The message SPIM's exceptions.s prints for the code
+    pub fn description(&self) -> &'static str {
+        match self {
+            ExceptionCode::Int => "Interrupt",
+            ExceptionCode::Mod | ExceptionCode::TlbL | ExceptionCode::TlbS => "TLB",
+            ExceptionCode::AdEL => "Address error in inst/data fetch",
+            ExceptionCode::AdES => "Address error in store",
+            ExceptionCode::Ibe => "Bad instruction address",
+            ExceptionCode::Dbe => "Bad data address",
+            ExceptionCode::Sys => "Error in syscall",
+            ExceptionCode::Bp => "Breakpoint",
+            ExceptionCode::Ri => "Reserved instruction",
+            ExceptionCode::CpU => "Coprocessor unusable",
+            ExceptionCode::Ov => "Arithmetic overflow",
+            ExceptionCode::Tr => "Trap"
+        }
+    }
+}
+
+impl Exception {
+    pub fn new(code: ExceptionCode, epc: u32) -> Self {
+        Self {
+            code,
+            epc,
+            bad_address: None
+        }
+    }
+
+    /// The exception a memory fault raises. Unmapped addresses are bus errors,
+    /// anything the segment map or user mode forbids is an address error and
+    /// TLB faults raise the matching TLB exception.
+    pub fn from_fault(fault: MemoryFault, epc: u32) -> Self {
+        let code = match (fault, fault.access()) {
+            (MemoryFault::TlbModified { .. }, _) => ExceptionCode::Mod,
+            (_, Access::Write) if fault.is_tlb() => ExceptionCode::TlbS,
+            _ if fault.is_tlb() => ExceptionCode::TlbL,
+            (MemoryFault::Unmapped { .. }, Access::Execute) => ExceptionCode::Ibe,
+            (MemoryFault::Unmapped { .. }, _) => ExceptionCode::Dbe,
+            (_, Access::Write) => ExceptionCode::AdES,
+            _ => ExceptionCode::AdEL
+        };
+        Self {
+            code,
+            epc,
+            bad_address: Some(fault.address())
+        }
+    }
+}
+
+impl ExceptionHandler {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "halt" => Some(ExceptionHandler::Halt),
+            "builtin" => Some(ExceptionHandler::Builtin),
+            "vector" => Some(ExceptionHandler::Vector),
+            _ => None
+        }
+    }
+}
+
+impl Processor {
+    pub fn get_exception_handler(&self) -> ExceptionHandler {
+        self.exception_handler
+    }
+
+    pub fn set_exception_handler(&mut self, handler: ExceptionHandler) {
+        self.exception_handler = handler;
+    }
+
+    /// Loads an exception handler at the exception vector, like `.ktext` does,
+    /// and sends exceptions to it from now on
+    pub fn load_kernel_program(&mut self, program: Vec<u32>) {
+        self.memory.load_kernel_program(program);
+        self.exception_handler = ExceptionHandler::Vector;
+    }
+
+    /// Loads words into the kernel data segment, like `.kdata` does
+    pub fn load_kernel_data(&mut self, data: Vec<u32>) {
+        self.memory.load_kernel_data(data);
+    }
+
+    /// Whether Status puts the processor in user mode. Handling an exception
+    /// always happens in kernel mode.
+    pub fn is_user_mode(&self) -> bool {
+        let status = self.cp0.get(STATUS);
+        status & STATUS_UM != 0 && status & STATUS_EXL == 0
+    }
+
+    /// Brings the memory's privilege checks in line with Status and the
+    /// TLB's ASID in line with EntryHi
+    pub(crate) fn sync_mode(&mut self) {
+        let user_mode = self.is_user_mode();
+        self.memory.set_user_mode(user_mode);
+        let entry_hi = self.cp0.get(ENTRY_HI);
+        if let Some(tlb) = self.memory.get_tlb_mut() {
+            tlb.set_asid(entry_hi);
+        }
+    }
+
+    /// Handles the load or store in MEM faulting, returns whether the rest of
+    /// the cycle has to be skipped
+    pub(crate) fn data_exception(&mut self, fault: MemoryFault) -> bool {
+        let exception = Exception::from_fault(fault, self.ex_mem_buffer.address);
+        match self.exception_handler {
+            ExceptionHandler::Halt => {
+                self.raise_fault(fault);
+                true
+            }
+            ExceptionHandler::Builtin => {
+                self.report_exception(exception);
+                self.mem_wb_buffer = MEMWBBuffer::new();
+                false
+            }
+            ExceptionHandler::Vector => {
+                // The faulting instruction and everything behind it is redone
+                self.flush = true;
+                self.mem_wb_buffer = MEMWBBuffer::new();
+                self.ex_mem_buffer = EXMEMBuffer::new();
+                self.id_ex_buffer = IDEXBuffer::new();
+                self.if_id_buffer = IFIDBuffer::new();
+                self.enter_fault_handler(fault, exception);
+                true
+            }
+        }
+    }
+
+    /// Handles the fetch at the program counter faulting
+    pub(crate) fn fetch_exception(&mut self, fault: MemoryFault) {
+        let exception = Exception::from_fault(fault, self.program_counter.get());
+        match self.exception_handler {
+            ExceptionHandler::Halt => self.raise_fault(fault),
+            ExceptionHandler::Builtin => {
+                // There is no instruction to skip, so give up like SPIM does
+                self.report_exception(exception);
+                self.raise_fault(fault);
+            }
+            ExceptionHandler::Vector => {
+                self.if_id_buffer = IFIDBuffer::new();
+                self.enter_fault_handler(fault, exception);
+            }
+        }
+    }
+
+    /// Handles the instruction decode just moved into ID/EX raising
+    /// `exception`, it never gets past decode
+    pub(crate) fn instruction_exception(&mut self, exception: Exception) {
+        match self.exception_handler {
+            ExceptionHandler::Halt => {
+                error!("Exception at pc {:#x}: {}", exception.epc, exception);
+                self.status = Status::Exception(exception);
+            }
+            ExceptionHandler::Builtin => {
+                self.report_exception(exception);
+                self.id_ex_buffer = IDEXBuffer::new();
+            }
+            ExceptionHandler::Vector => {
+                self.id_ex_buffer = IDEXBuffer::new();
+                self.enter_handler(exception);
+            }
+        }
+    }
+
+    /// Handles a syscall faulting on the buffer it was handed. Like MARS the
+    /// fault belongs to the syscall instruction decode just moved into ID/EX.
+    pub(crate) fn syscall_exception(&mut self, fault: MemoryFault) {
+        // Buffers carry the already incremented PC
+        let exception = Exception::from_fault(fault, self.id_ex_buffer.pc.wrapping_sub(4));
+        match self.exception_handler {
+            ExceptionHandler::Halt => self.raise_fault(fault),
+            ExceptionHandler::Builtin => self.report_exception(exception),
+            ExceptionHandler::Vector => {
+                self.id_ex_buffer = IDEXBuffer::new();
+                self.enter_fault_handler(fault, exception);
+            }
+        }
+    }
+
+    /// Records `exception` in coprocessor 0 and jumps to the exception
+    /// vector. Dropping the instructions that have to be redone is up to the
+    /// caller.
+    pub(crate) fn enter_handler(&mut self, exception: Exception) {
+        debug!("Taking {:?} exception with EPC {:#x}", exception.code, exception.epc);
+        self.cp0.set(EPC, exception.epc);
+        if let Some(address) = exception.bad_address {
+            self.cp0.set(BAD_VADDR, address);
+        }
+        let cause = self.cp0.get(CAUSE) & !CAUSE_EXC_CODE;
+        self.cp0.set(CAUSE, cause | (exception.code as u32) << 2);
+        self.cp0.set(STATUS, self.cp0.get(STATUS) | STATUS_EXL);
+        self.program_counter.set(self.exception_vector(false));
+        // A fetch still waiting on the cache was for the old address
+        self.fetch_wait = None;
+    }
+
+    /// Enters the handler for a memory fault. TLB faults also leave the page
+    /// in EntryHi and Context, misses on kuseg addresses go to the refill
+    /// vector unless EXL is already set.
+    fn enter_fault_handler(&mut self, fault: MemoryFault, exception: Exception) {
+        let address = fault.address();
+        if fault.is_tlb() {
+            let entry_hi = self.cp0.get(ENTRY_HI) & ENTRY_HI_ASID | address & ENTRY_HI_VPN;
+            self.cp0.set(ENTRY_HI, entry_hi);
+            let bad_vpn = (address >> 12) << 2 & CONTEXT_BAD_VPN;
+            self.cp0.set(CONTEXT, self.cp0.get(CONTEXT) & CONTEXT_PTE_BASE | bad_vpn);
+        }
+        let refill = matches!(fault, MemoryFault::TlbMiss { .. }) &&
+            address < KSEG0 &&
+            self.cp0.get(STATUS) & STATUS_EXL == 0;
+        self.enter_handler(exception);
+        if refill {
+            self.program_counter.set(self.exception_vector(true));
+        }
+    }
+
+    /// Where exceptions go, the boot vectors in ROM while Status BEV is set
+    fn exception_vector(&self, refill: bool) -> u32 {
+        match (self.cp0.get(STATUS) & STATUS_BEV != 0, refill) {
+            (false, false) => EXCEPTION_VECTOR,
+            (false, true) => TLB_REFILL_VECTOR,
+            (true, false) => BOOT_EXCEPTION_VECTOR,
+            (true, true) => BOOT_TLB_REFILL_VECTOR
+        }
+    }
+
+    /// Whether the load or store ahead of decode has yet to reach memory.
+    /// Instructions that change modes or raise exceptions in decode wait for
+    /// it, so it runs in the old mode and its exceptions come first.
+    pub(crate) fn access_pending(&self) -> bool {
+        DataMemory::access(&self.ex_mem_buffer).is_some()
+    }
+
+    /// Prints the exception the way SPIM's default handler does
+    fn report_exception(&mut self, exception: Exception) {
+        let mut report = format!("Exception occurred at PC={:#010x}\n", exception.epc);
+        if let Some(address) = exception.bad_address {
+            report += &format!("  Bad address: {:#010x}\n", address);
+        }
+        report += &format!(
+            "  Exception {}  [{}]  occurred and ignored\n",
+            exception.code as u8,
+            exception.code.description()
+        );
+        self.console.write(&report);
+    }
+}
+
+impl Display for Exception {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "exception {} [{}] at {:#010x}",
+            self.code as u8,
+            self.code.description(),
+            self.epc
+        )?;
+        if let Some(address) = self.bad_address {
+            write!(f, ", bad address {:#010x}", address)?;
+        }
+        Ok(())
+    }
+}
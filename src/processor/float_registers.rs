@@ -0,0 +1,130 @@
+use log::trace;
+use crate::processor::Processor;
+
+/// `rs` field values that pick the coprocessor 1 move
+const MF: u8 = 0x00;
+const MT: u8 = 0x04;
+
+/// Coprocessor 1 register file. Doubles occupy an even/odd register pair with
+/// the low word in the even register.
+pub struct FloatRegisters {
+    f: [u32; 32],
+    /// Previous value of every register written while journaling
+    journal: Option<Vec<(usize, u32)>>
+}
+
+/// The coprocessor 1 instructions the processor understands, the float
+/// registers are only reachable through these moves and the syscalls
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cop1Operation {
+    /// Copies a float register into `rt`
+    Mfc1,
+    /// Copies `rt` into a float register
+    Mtc1
+}
+
+impl Cop1Operation {
+    /// Picks the operation out of a word with the COP1 opcode
+    pub fn decode(word: u32) -> Option<Self> {
+        match ((word >> 21) & 0x1F) as u8 {
+            MF => Some(Cop1Operation::Mfc1),
+            MT => Some(Cop1Operation::Mtc1),
+            _ => None
+        }
+    }
+}
+
+impl FloatRegisters {
+    pub fn new() -> Self {
+        Self {
+            f: [0; 32],
+            journal: None
+        }
+    }
+
+    /// Starts recording the previous value of every register written
+    pub fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+    }
+
+    /// Stops recording and returns what was overwritten, oldest first
+    pub fn take_journal(&mut self) -> Vec<(usize, u32)> {
+        self.journal.take().unwrap_or_default()
+    }
+
+    /// Restores registers recorded by the journal, newest first
+    pub fn undo(&mut self, journal: &[(usize, u32)]) {
+        for (index, value) in journal.iter().rev() {
+            self.f[*index] = *value;
+        }
+    }
+
+    pub fn get(&self, index: usize) -> u32 {
+        trace!("Reading float register: $f{}", index);
+        self.f[index]
+    }
+
+    pub fn set(&mut self, index: usize, value: u32) {
+        trace!("Writing float register: $f{} with value {:#x}", index, value);
+        if let Some(journal) = self.journal.as_mut() {
+            journal.push((index, self.f[index]));
+        }
+        self.f[index] = value;
+    }
+
+    pub fn get_single(&self, index: usize) -> f32 {
+        f32::from_bits(self.get(index))
+    }
+
+    pub fn set_single(&mut self, index: usize, value: f32) {
+        self.set(index, value.to_bits());
+    }
+
+    pub fn get_double(&self, index: usize) -> f64 {
+        let low = self.get(index & !1) as u64;
+        let high = self.get(index | 1) as u64;
+        f64::from_bits((high << 32) | low)
+    }
+
+    pub fn set_double(&mut self, index: usize, value: f64) {
+        let bits = value.to_bits();
+        self.set(index & !1, bits as u32);
+        self.set(index | 1, (bits >> 32) as u32);
+    }
+}
+
+impl Processor {
+    /// Carries out the coprocessor 1 move decode just moved into ID/EX
+    pub(crate) fn execute_cop1(&mut self) {
+        let instruction = match self.id_ex_buffer.instruction {
+            Some(instruction) => instruction,
+            None => return
+        };
+        let index = instruction.rd.unwrap_or(0) as usize;
+        match Cop1Operation::decode(instruction.word) {
+            Some(Cop1Operation::Mfc1) => {
+                self.id_ex_buffer.data_1 = self.float_registers.get(index);
+            }
+            Some(Cop1Operation::Mtc1) => {
+                self.float_registers.set(index, self.id_ex_buffer.data_2);
+            }
+            None => {}
+        }
+    }
+}
+
+impl Default for FloatRegisters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for FloatRegisters {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Float Registers:")?;
+        for (i, j) in self.f.iter().enumerate() {
+            writeln!(f, "    $f{}: {:#x} ({})", i, j, f32::from_bits(*j))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::VecDeque;
+use log::debug;
+use crate::processor::buffer::{EXMEMBuffer, IDEXBuffer, IFIDBuffer, MEMWBBuffer};
+use crate::processor::cache_hierarchy::CacheHierarchy;
+use crate::processor::cp0::Cp0;
+use crate::processor::interrupts::InterruptController;
+use crate::processor::pipeline_stats::{PipelineStats, StallCause};
+use crate::processor::registers::Register;
+use crate::processor::tlb::Tlb;
+use crate::processor::watchpoint::{WatchKind, WatchTarget, WatchpointHit};
+use crate::processor::{Commit, Processor, Status};
+
+/// Everything needed to rewind a single cycle. Small state, the TLB and the
+/// caches are copied whole, registers and memory are restored from undo logs of the
+/// writes made.
+struct CycleRecord {
+    program_counter: u32,
+    if_id_buffer: IFIDBuffer,
+    id_ex_buffer: IDEXBuffer,
+    ex_mem_buffer: EXMEMBuffer,
+    mem_wb_buffer: MEMWBBuffer,
+    stall: bool,
+    status: Status,
+    hi: u32,
+    lo: u32,
+    cp0: Cp0,
+    interrupts: InterruptController,
+    tlb: Option<Tlb>,
+    program_break: u32,
+    last_commit: Option<Commit>,
+    fetch_wait: Option<u32>,
+    memory_wait: Option<u32>,
+    stall_cause: Option<StallCause>,
+    pipeline_stats: PipelineStats,
+    caches: CacheHierarchy,
+    register_writes: Vec<(Register, u32)>,
+    float_register_writes: Vec<(usize, u32)>,
+    memory_writes: Vec<(u32, u8)>,
+    /// The watchpoint the cycle hit, kept so reverse execution can stop on it
+    watchpoint_hit: Option<WatchpointHit>
+}
+
+/// Previous values of everything written during one cycle, oldest first
+pub(crate) struct CycleWrites {
+    pub registers: Vec<(Register, u32)>,
+    pub float_registers: Vec<(usize, u32)>,
+    pub memory: Vec<(u32, u8)>,
+    /// Whether a load or store reached a mapped device
+    pub device: bool
+}
+
+/// Undo logs for the most recent cycles, oldest dropped first once `limit` is reached.
+/// Console output and host file I/O done by syscalls cannot be taken back. A
+/// cycle that loads from or stores to a device cannot be either, so it drops
+/// every record before it.
+pub struct History {
+    records: VecDeque<CycleRecord>,
+    limit: usize,
+    /// Whether the oldest record follows a cycle that accessed a device
+    device_barrier: bool
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            records: VecDeque::new(),
+            limit,
+            device_barrier: false
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Forgets every recorded cycle, for when the state they lead to is replaced
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.device_barrier = false;
+    }
+
+    fn push(&mut self, record: CycleRecord) {
+        if self.records.len() == self.limit {
+            self.records.pop_front();
+            self.device_barrier = false;
+        }
+        self.records.push_back(record);
+    }
+
+    /// Drops every record, stepping back past a device access being impossible
+    fn cut_at_device(&mut self) {
+        self.records.clear();
+        self.device_barrier = true;
+    }
+}
+
+impl Processor {
+    /// Starts recording up to `limit` cycles so they can be stepped back over
+    pub fn enable_history(&mut self, limit: usize) {
+        self.history = Some(History::new(limit));
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Number of cycles that can currently be stepped back
+    pub fn get_history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, History::len)
+    }
+
+    /// Whether the recorded history stops at a cycle that accessed a device,
+    /// rather than at the start of the run or the history limit
+    pub fn is_history_cut_by_device(&self) -> bool {
+        self.history
+            .as_ref()
+            .is_some_and(|history| history.device_barrier)
+    }
+
+    /// Rewinds the most recent recorded cycle. Returns the watchpoint that cycle
+    /// hit wrapped in `Some`, or `None` if there was nothing to rewind. Write
+    /// watchpoints set after the cycle ran are matched against its undo logs.
+    pub fn step_back(&mut self) -> Option<Option<WatchpointHit>> {
+        let record = self.history.as_mut()?.records.pop_back()?;
+        debug!("Stepping back from cycle {}", self.cycles);
+        let hit = record
+            .watchpoint_hit
+            .or_else(|| self.undone_write_hit(&record));
+        self.memory.undo(&record.memory_writes);
+        self.memory.set_program_break(record.program_break);
+        self.registers.undo(&record.register_writes);
+        self.float_registers
+            .undo(&record.float_register_writes);
+        self.alu.set_hi(record.hi);
+        self.alu.set_lo(record.lo);
+        self.cp0 = record.cp0;
+        self.interrupts = record.interrupts;
+        if let Some(tlb) = record.tlb {
+            self.memory.set_tlb(tlb);
+        }
+        self.program_counter.set(record.program_counter);
+        self.if_id_buffer = record.if_id_buffer;
+        self.id_ex_buffer = record.id_ex_buffer;
+        self.ex_mem_buffer = record.ex_mem_buffer;
+        self.mem_wb_buffer = record.mem_wb_buffer;
+        self.stall = record.stall;
+        self.status = record.status;
+        self.last_commit = record.last_commit;
+        self.fetch_wait = record.fetch_wait;
+        self.memory_wait = record.memory_wait;
+        self.stall_cause = record.stall_cause;
+        self.pipeline_stats = record.pipeline_stats;
+        self.caches = record.caches;
+        self.cycles -= 1;
+        Some(hit)
+    }
+
+    /// A write watchpoint covering something `record` is about to undo, taken
+    /// before the undo so the value is the one written
+    fn undone_write_hit(&self, record: &CycleRecord) -> Option<WatchpointHit> {
+        // Stores and writebacks belong to the instruction that was entering MEM
+        let (instruction, pc) = match record.ex_mem_buffer.instruction {
+            Some(instruction) => (instruction, record.ex_mem_buffer.address),
+            // Buffers carry the already incremented PC
+            None => (record.if_id_buffer.instruction?, record.if_id_buffer.pc.wrapping_sub(4))
+        };
+        let memory = record
+            .memory_writes
+            .iter()
+            .map(|(address, _)| *address)
+            .filter(|address| self.memory.is_watched(*address, 1, WatchKind::Write))
+            .min()
+            .map(|address| (WatchTarget::Memory(address), self.memory.read_word(address)));
+        let register = record
+            .register_writes
+            .iter()
+            .find(|(register, _)| {
+                self.registers.get_watchpoints().iter().any(|watchpoint| {
+                    watchpoint.register == *register && watchpoint.kind.matches(WatchKind::Write)
+                })
+            })
+            .map(|(register, _)| (WatchTarget::Register(*register), self.registers.get(*register)));
+        let (target, value) = memory.or(register)?;
+        Some(WatchpointHit {
+            pc,
+            instruction,
+            target,
+            access: WatchKind::Write,
+            value
+        })
+    }
+
+    /// Starts logging the previous value of everything written
+    pub(crate) fn start_journals(&mut self) {
+        self.memory.start_journal();
+        self.registers.start_journal();
+        self.float_registers.start_journal();
+    }
+
+    pub(crate) fn take_journals(&mut self) -> CycleWrites {
+        CycleWrites {
+            registers: self.registers.take_journal(),
+            float_registers: self.float_registers.take_journal(),
+            memory: self.memory.take_journal(),
+            device: self.memory.take_device_access()
+        }
+    }
+
+    /// Snapshots the state a cycle is about to change
+    pub(crate) fn begin_record(&self) -> Option<PendingRecord> {
+        self.history.as_ref()?;
+        Some(PendingRecord {
+            program_counter: self.program_counter.get(),
+            if_id_buffer: self.if_id_buffer,
+            id_ex_buffer: self.id_ex_buffer,
+            ex_mem_buffer: self.ex_mem_buffer,
+            mem_wb_buffer: self.mem_wb_buffer,
+            stall: self.stall,
+            status: self.status,
+            hi: self.alu.get_hi(),
+            lo: self.alu.get_lo(),
+            cp0: self.cp0,
+            interrupts: self.interrupts,
+            tlb: self.memory.get_tlb().cloned(),
+            program_break: self.memory.get_program_break(),
+            last_commit: self.last_commit,
+            fetch_wait: self.fetch_wait,
+            memory_wait: self.memory_wait,
+            stall_cause: self.stall_cause,
+            pipeline_stats: self.pipeline_stats,
+            caches: self.caches.clone()
+        })
+    }
+
+    pub(crate) fn end_record(
+        &mut self,
+        pending: PendingRecord,
+        writes: CycleWrites,
+        hit: Option<WatchpointHit>
+    ) {
+        if writes.device {
+            debug!("Cycle {} accessed a device, dropping the history", self.cycles);
+            if let Some(history) = self.history.as_mut() {
+                history.cut_at_device();
+            }
+            return;
+        }
+        let record = CycleRecord {
+            program_counter: pending.program_counter,
+            if_id_buffer: pending.if_id_buffer,
+            id_ex_buffer: pending.id_ex_buffer,
+            ex_mem_buffer: pending.ex_mem_buffer,
+            mem_wb_buffer: pending.mem_wb_buffer,
+            stall: pending.stall,
+            status: pending.status,
+            hi: pending.hi,
+            lo: pending.lo,
+            cp0: pending.cp0,
+            interrupts: pending.interrupts,
+            tlb: pending.tlb,
+            program_break: pending.program_break,
+            last_commit: pending.last_commit,
+            fetch_wait: pending.fetch_wait,
+            memory_wait: pending.memory_wait,
+            stall_cause: pending.stall_cause,
+            pipeline_stats: pending.pipeline_stats,
+            caches: pending.caches,
+            register_writes: writes.registers,
+            float_register_writes: writes.float_registers,
+            memory_writes: writes.memory,
+            watchpoint_hit: hit
+        };
+        if let Some(history) = self.history.as_mut() {
+            history.push(record);
+        }
+    }
+}
+
+/// State captured before a cycle runs, completed into a record once it ends
+pub(crate) struct PendingRecord {
+    program_counter: u32,
+    if_id_buffer: IFIDBuffer,
+    id_ex_buffer: IDEXBuffer,
+    ex_mem_buffer: EXMEMBuffer,
+    mem_wb_buffer: MEMWBBuffer,
+    stall: bool,
+    status: Status,
+    hi: u32,
+    lo: u32,
+    cp0: Cp0,
+    interrupts: InterruptController,
+    tlb: Option<Tlb>,
+    program_break: u32,
+    last_commit: Option<Commit>,
+    fetch_wait: Option<u32>,
+    memory_wait: Option<u32>,
+    stall_cause: Option<StallCause>,
+    pipeline_stats: PipelineStats,
+    caches: CacheHierarchy
+}
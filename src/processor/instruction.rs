@@ -1,12 +1,15 @@
-use log::{debug, info};
-use num_derive::FromPrimitive;
+use log::debug;
 use num_traits::FromPrimitive;
-use crate::processor::alu::FunctionCode;
+use crate::processor::alu::{FunctionCode, OpCode};
+use crate::processor::cp0::Cop0Operation;
+use crate::processor::float_registers::Cop1Operation;
 use crate::processor::registers::Register;
 
 // TODO: Big refactor needed here. Store the enum values rather than the raw values
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Instruction {
+    /// The raw encoding the fields were decoded from
+    pub word: u32,
     pub opcode: u8,
     pub instruction_type: InstructionType,
     pub rs: Option<u8>,
@@ -38,19 +41,36 @@ impl Instruction {
         match opcode {
             0 => {
                 Self {
+                    word: data,
                     opcode,
                     instruction_type: InstructionType::R,
                     rs: Some(((data >> 21) & 0x1F) as u8),
                     rt: Some(((data >> 16) & 0x1F) as u8),
                     rd: Some(((data >> 11) & 0x1F) as u8),
                     shamt: Some(((data >> 6) & 0x1F) as u8),
-                    funct: Some(((data >> 0) & 0x3F) as u8),
+                    funct: Some((data & 0x3F) as u8),
                     imm: None,
                     addr: None
                 }
             }
-            1 | 2 => {
+            // Coprocessor moves name a coprocessor register in rd
+            0x10 | 0x11 => {
                 Self {
+                    word: data,
+                    opcode,
+                    instruction_type: InstructionType::I,
+                    rs: Some(((data >> 21) & 0x1F) as u8),
+                    rt: Some(((data >> 16) & 0x1F) as u8),
+                    rd: Some(((data >> 11) & 0x1F) as u8),
+                    shamt: None,
+                    funct: Some((data & 0x3F) as u8),
+                    imm: Some((data & 0xFFFF) as i32 as u32),
+                    addr: None
+                }
+            }
+            2 | 3 => {
+                Self {
+                    word: data,
                     opcode,
                     instruction_type: InstructionType::J,
                     rs: None,
@@ -59,11 +79,12 @@ impl Instruction {
                     shamt: None,
                     funct: None,
                     imm: None,
-                    addr: Some((data >> 0) & 0x1FFFFFF)
+                    addr: Some(data & 0x3FFFFFF)
                 }
             }
             _ => {
                 Self {
+                    word: data,
                     opcode,
                     instruction_type: InstructionType::I,
                     rs: Some(((data >> 21) & 0x1F) as u8),
@@ -72,12 +93,174 @@ impl Instruction {
                     shamt: None,
                     funct: None,
                     // NOTE: This is a sign-extended immediate value
-                    imm: Some(((data >> 0) & 0xFFFF) as i32 as u32),
+                    imm: Some((data & 0xFFFF) as i32 as u32),
                     addr: None
                 }
             }
         }
     }
+
+    /// The register written back by this instruction, if any
+    pub fn destination(&self) -> Option<u8> {
+        match self.instruction_type {
+            InstructionType::R => match FunctionCode::from_u8(self.funct?)? {
+                FunctionCode::Jr |
+                FunctionCode::Syscall |
+                FunctionCode::Div |
+                FunctionCode::Divu |
+                FunctionCode::Mult |
+                FunctionCode::Multu => None,
+                _ => self.rd
+            },
+            InstructionType::I => match OpCode::from_u8(self.opcode)? {
+                OpCode::Beq | OpCode::Bne | OpCode::Sb | OpCode::Sh | OpCode::Sw => None,
+                OpCode::Cop0 => match Cop0Operation::decode(self.word)? {
+                    Cop0Operation::Mfc0 => self.rt,
+                    _ => None
+                },
+                OpCode::Cop1 => match Cop1Operation::decode(self.word)? {
+                    Cop1Operation::Mfc1 => self.rt,
+                    Cop1Operation::Mtc1 => None
+                },
+                _ => self.rt
+            },
+            _ => None
+        }
+    }
+
+    /// The registers this instruction reads its operands from
+    pub fn sources(&self) -> Vec<u8> {
+        match self.instruction_type {
+            InstructionType::R => match self.funct.and_then(FunctionCode::from_u8) {
+                Some(FunctionCode::Syscall) | Some(FunctionCode::Mfhi) | Some(FunctionCode::Mflo) => {
+                    vec![]
+                }
+                Some(FunctionCode::Jr) => vec![self.rs.unwrap_or(0)],
+                Some(FunctionCode::Sll) | Some(FunctionCode::Srl) | Some(FunctionCode::Sra) => {
+                    vec![self.rt.unwrap_or(0)]
+                }
+                _ => vec![self.rs.unwrap_or(0), self.rt.unwrap_or(0)]
+            },
+            InstructionType::I => match OpCode::from_u8(self.opcode) {
+                Some(OpCode::Lui) => vec![],
+                Some(OpCode::Cop0) => match Cop0Operation::decode(self.word) {
+                    Some(Cop0Operation::Mtc0) => vec![self.rt.unwrap_or(0)],
+                    _ => vec![]
+                },
+                Some(OpCode::Cop1) => match Cop1Operation::decode(self.word) {
+                    Some(Cop1Operation::Mtc1) => vec![self.rt.unwrap_or(0)],
+                    _ => vec![]
+                },
+                Some(OpCode::Beq) |
+                Some(OpCode::Bne) |
+                Some(OpCode::Sb) |
+                Some(OpCode::Sc) |
+                Some(OpCode::Sh) |
+                Some(OpCode::Sw) => vec![self.rs.unwrap_or(0), self.rt.unwrap_or(0)],
+                _ => vec![self.rs.unwrap_or(0)]
+            },
+            _ => vec![]
+        }
+    }
+
+    /// Renders the instruction as a single line of assembly
+    pub fn disassemble(&self) -> String {
+        let reg = |field: Option<u8>| register_name(field.unwrap_or(0));
+        let unknown = format!(".word {:#010x}", self.word);
+        match self.instruction_type {
+            InstructionType::R => {
+                let funct = match self.funct.and_then(FunctionCode::from_u8) {
+                    Some(funct) => funct,
+                    None => return unknown
+                };
+                let name = format!("{:?}", funct).to_lowercase();
+                match funct {
+                    FunctionCode::Sll if self.word == 0 => "nop".to_string(),
+                    FunctionCode::Sll | FunctionCode::Srl | FunctionCode::Sra => format!(
+                        "{} {}, {}, {}",
+                        name,
+                        reg(self.rd),
+                        reg(self.rt),
+                        self.shamt.unwrap_or(0)
+                    ),
+                    FunctionCode::Jr => format!("{} {}", name, reg(self.rs)),
+                    FunctionCode::Syscall => name,
+                    FunctionCode::Mfhi | FunctionCode::Mflo => {
+                        format!("{} {}", name, reg(self.rd))
+                    }
+                    FunctionCode::Mult |
+                    FunctionCode::Multu |
+                    FunctionCode::Div |
+                    FunctionCode::Divu => format!("{} {}, {}", name, reg(self.rs), reg(self.rt)),
+                    _ => format!(
+                        "{} {}, {}, {}",
+                        name,
+                        reg(self.rd),
+                        reg(self.rs),
+                        reg(self.rt)
+                    )
+                }
+            }
+            InstructionType::I => {
+                let opcode = match OpCode::from_u8(self.opcode) {
+                    Some(opcode) => opcode,
+                    None => return unknown
+                };
+                let name = format!("{:?}", opcode).to_lowercase();
+                let imm = self.imm.unwrap_or(0);
+                let signed = imm as u16 as i16;
+                match opcode {
+                    OpCode::Andi | OpCode::Ori => {
+                        format!("{} {}, {}, {:#x}", name, reg(self.rt), reg(self.rs), imm)
+                    }
+                    OpCode::Lui => format!("{} {}, {:#x}", name, reg(self.rt), imm),
+                    OpCode::Cop0 => match Cop0Operation::decode(self.word) {
+                        Some(operation @ (Cop0Operation::Mfc0 | Cop0Operation::Mtc0)) => format!(
+                            "{} {}, ${}",
+                            format!("{:?}", operation).to_lowercase(),
+                            reg(self.rt),
+                            self.rd.unwrap_or(0)
+                        ),
+                        Some(operation) => format!("{:?}", operation).to_lowercase(),
+                        None => unknown
+                    },
+                    OpCode::Cop1 => match Cop1Operation::decode(self.word) {
+                        Some(operation) => format!(
+                            "{} {}, $f{}",
+                            format!("{:?}", operation).to_lowercase(),
+                            reg(self.rt),
+                            self.rd.unwrap_or(0)
+                        ),
+                        None => unknown
+                    },
+                    OpCode::Beq | OpCode::Bne => {
+                        format!("{} {}, {}, {}", name, reg(self.rs), reg(self.rt), signed)
+                    }
+                    OpCode::Lbu |
+                    OpCode::Lhu |
+                    OpCode::Ll |
+                    OpCode::Lw |
+                    OpCode::Sb |
+                    OpCode::Sc |
+                    OpCode::Sh |
+                    OpCode::Sw => format!("{} {}, {}({})", name, reg(self.rt), signed, reg(self.rs)),
+                    _ => format!("{} {}, {}, {}", name, reg(self.rt), reg(self.rs), signed)
+                }
+            }
+            InstructionType::J => {
+                let name = if self.opcode == 0x3 { "jal" } else { "j" };
+                format!("{} {:#010x}", name, self.addr.unwrap_or(0) << 2)
+            }
+            _ => unknown
+        }
+    }
+}
+
+fn register_name(index: u8) -> String {
+    match Register::from_u8(index) {
+        Some(register) => format!("${}", register.name()),
+        None => format!("${}", index)
+    }
 }
 
 impl std::fmt::Display for Instruction {
@@ -102,11 +285,10 @@ impl std::fmt::Display for Instruction {
                     Register::from_u8(self.rd.unwrap()).unwrap()
                 )?;
                 writeln!(f, "            Shift Ammount: {:#x}", self.shamt.unwrap())?;
-                writeln!(
-                    f,
-                    "            Function Code: {:?}",
-                    FunctionCode::from_u8(self.funct.unwrap()).unwrap()
-                )?;
+                match self.funct.and_then(FunctionCode::from_u8) {
+                    Some(funct) => writeln!(f, "            Function Code: {:?}", funct)?,
+                    None => writeln!(f, "            Function Code: reserved")?
+                }
             }
             InstructionType::I => {
                 writeln!(f, "        I-Type:")?;
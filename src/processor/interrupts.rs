@@ -0,0 +1,107 @@
+use log::debug;
+use crate::processor::buffer::IFIDBuffer;
+use crate::processor::cp0::{CAUSE, CAUSE_IP, STATUS, STATUS_EXL, STATUS_IE};
+use crate::processor::exception::{Exception, ExceptionCode};
+use crate::processor::Processor;
+
+/// Gathers the interrupt lines of devices and of anything outside the
+/// processor into the Cause IP bits. Line `n` is Cause bit `8 + n`, line 7
+/// is shared with the Count/Compare timer.
+///
+/// Lines are level triggered, an interrupt stays pending for as long as its
+/// line is held. The timer and the software interrupt bits are latched in
+/// Cause itself instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct InterruptController {
+    /// Lines raised from outside the processor
+    external: u8,
+    /// Every line that was high when the current cycle started
+    asserted: u8
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        Self {
+            external: 0,
+            asserted: 0
+        }
+    }
+
+    pub fn raise(&mut self, line: u8) {
+        self.external |= 1 << line;
+    }
+
+    pub fn lower(&mut self, line: u8) {
+        self.external &= !(1 << line);
+    }
+
+    pub fn get_external(&self) -> u8 {
+        self.external
+    }
+
+    /// Lines high this cycle, from devices or raised from outside
+    pub fn get_asserted(&self) -> u8 {
+        self.asserted
+    }
+
+    /// Puts back the lines a snapshot saved
+    pub(crate) fn set_lines(&mut self, external: u8, asserted: u8) {
+        self.external = external;
+        self.asserted = asserted;
+    }
+
+    pub(crate) fn update(&mut self, device_lines: u8) {
+        self.asserted = self.external | device_lines;
+    }
+}
+
+impl Processor {
+    /// Holds interrupt line `line`, 0 to 7, high until it is lowered again
+    pub fn raise_interrupt(&mut self, line: u8) {
+        self.interrupts.raise(line);
+    }
+
+    pub fn lower_interrupt(&mut self, line: u8) {
+        self.interrupts.lower(line);
+    }
+
+    pub fn get_interrupt_controller(&self) -> &InterruptController {
+        &self.interrupts
+    }
+
+    /// Cause as `mfc0` sees it, with the lines held this cycle in the IP bits
+    pub fn get_cause(&self) -> u32 {
+        self.cp0.get(CAUSE) | (self.interrupts.get_asserted() as u32) << 8
+    }
+
+    /// Pending interrupts Status lets through, as Cause IP bits
+    pub fn get_pending_interrupts(&self) -> u32 {
+        let status = self.cp0.get(STATUS);
+        if status & STATUS_IE == 0 || status & STATUS_EXL != 0 {
+            return 0;
+        }
+        self.get_cause() & status & CAUSE_IP
+    }
+
+    /// Samples the interrupt lines and takes an interrupt if one is pending.
+    /// The instruction waiting in IF/ID is dropped and becomes EPC, every
+    /// older instruction is left to finish so the interrupt lands precisely
+    /// between the two.
+    pub(crate) fn check_interrupts(&mut self) {
+        self.interrupts
+            .update(self.memory.get_devices().interrupts());
+        let pending = self.get_pending_interrupts();
+        if pending == 0 {
+            return;
+        }
+        let epc = match self.if_id_buffer.instruction {
+            // Buffers carry the already incremented PC
+            Some(_) => self.if_id_buffer.pc.wrapping_sub(4),
+            None => self.program_counter.get()
+        };
+        debug!("Taking interrupt {:#x} with EPC {:#x}", pending >> 8, epc);
+        self.flush = self.if_id_buffer.instruction.is_some();
+        self.if_id_buffer = IFIDBuffer::new();
+        self.enter_handler(Exception::new(ExceptionCode::Int, epc));
+    }
+}
@@ -1,16 +1,89 @@
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
-use std::ptr::NonNull;
-use log::{debug, info, trace};
-use crate::processor::buffer::{EXMEMBuffer, MEMWBBuffer};
+use log::{debug, trace};
+use crate::processor::device::{Device, DeviceMap, DeviceMapError};
+use crate::processor::memory_dump::MemoryDump;
+use crate::processor::segment::{
+    Access,
+    MemoryFault,
+    Permissions,
+    Segment,
+    HEAP_BASE,
+    ROM_BASE,
+    ROM_SIZE,
+    STACK_LIMIT
+};
+use crate::processor::tlb::{Tlb, KSEG0, KSEG1, KSEG2};
+use crate::processor::watchpoint::{MemoryWatchpoint, WatchKind, WatchpointHit};
 
+/// Size of a lazily allocated page in bytes
+pub const PAGE_SIZE: u32 = 0x1000;
+/// Start of the text segment
+pub const TEXT_BASE: u32 = 0x0040_0000;
+/// Start of the static data segment
+pub const DATA_BASE: u32 = 0x1001_0000;
+/// Initial value of the global pointer
+pub const GLOBAL_POINTER: u32 = 0x1000_8000;
+/// Initial value of the stack pointer
+pub const STACK_POINTER: u32 = 0x7FFF_FFFC;
+/// Where `.ktext` starts by default, the exception vector
+pub const KTEXT_BASE: u32 = 0x8000_0180;
+/// Start of the kernel data segment
+pub const KDATA_BASE: u32 = 0x9000_0000;
+/// Bytes of RAM a bare-metal machine gets by default
+pub const RAM_SIZE: u32 = 0x0100_0000;
+
+type Page = Box<[u8; PAGE_SIZE as usize]>;
+
+/// Sparse little-endian memory covering the full 32-bit address space.
+/// Pages are only allocated once they are written to, reads from untouched
+/// pages return zero.
+///
+/// The plain `read_*`/`write_*` methods bypass the segment map and are meant
+/// for the loader, syscalls and debugging. The pipeline goes through
+/// [`Memory::fetch`], [`Memory::load`] and [`Memory::store`] which fault on
+/// accesses the segment map does not permit and hand accesses to mapped
+/// devices over to them. In user mode they also fault on kernel segments.
+///
+/// Once a [`Tlb`] is set the pipeline's addresses are virtual, the TLB maps
+/// them onto the pages here and the segment map no longer applies. A
+/// bare-metal memory keeps a map of physical memory instead, whose faults
+/// report the physical address like a bus error would.
 #[derive(Debug)]
 pub struct Memory {
-    pointer: NonNull<u8>,
-    capacity: u32,
+    pages: BTreeMap<u32, Page>,
+    segments: Vec<Segment>,
+    devices: DeviceMap,
     stack_pointer: u32,
-    alloc: u32
+    program_break: u32,
+    /// Whether pipeline accesses are limited to user segments
+    user_mode: bool,
+    tlb: Option<Tlb>,
+    /// Whether the segment map describes physical addresses
+    physical_map: bool,
+    watchpoints: Vec<MemoryWatchpoint>,
+    watchpoint_hit: Option<WatchpointHit>,
+    /// Previous value of every byte written while journaling
+    journal: Option<Vec<(u32, u8)>>,
+    /// Whether a load or store reached a device while journaling
+    device_access: bool
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SbrkError {
+    /// The new break would run into the stack
+    StackCollision { program_break: u64 },
+    /// The new break would drop below the start of the heap
+    BelowHeap { program_break: i64 }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RomError {
+    /// The image holds more bytes than the boot ROM
+    TooLarge { bytes: usize }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Size {
     Byte,
     Halfword,
@@ -18,33 +91,393 @@ pub enum Size {
     Quad
 }
 
+impl Size {
+    pub fn bytes(&self) -> u32 {
+        match self {
+            Size::Byte => 1,
+            Size::Halfword => 2,
+            Size::Word => 4,
+            Size::Quad => 8
+        }
+    }
+}
+
 impl Memory {
     pub fn new() -> Self {
         Memory {
-            pointer: NonNull::dangling(),
-            capacity: 0,
-            stack_pointer: 0,
-            alloc: 0
+            pages: BTreeMap::new(),
+            segments: Segment::default_map(),
+            devices: DeviceMap::new(),
+            stack_pointer: STACK_POINTER,
+            program_break: HEAP_BASE,
+            user_mode: false,
+            tlb: None,
+            physical_map: false,
+            watchpoints: Vec::new(),
+            watchpoint_hit: None,
+            journal: None,
+            device_access: false
         }
     }
 
-    pub fn new_with_capacity(word_capacity: u32) -> Self {
-        let layout = std::alloc::Layout::array::<u8>((word_capacity << 2) as usize).unwrap();
-        let pointer = unsafe { std::alloc::alloc(layout) };
-        if pointer.is_null() {
-            panic!("Failed to allocate memory");
-        }
-        info!(
-            "Allocated {} bytes at address {:#x}",
-            word_capacity << 2,
-            pointer as u32
-        );
+    /// Physical memory for firmware: RAM from address zero and the boot ROM,
+    /// see [`Segment::bare_metal_map`], behind a TLB
+    pub fn new_bare_metal(ram_size: u32) -> Self {
         Memory {
-            pointer: NonNull::new(pointer).unwrap(),
-            capacity: word_capacity * 4,
-            stack_pointer: 0,
-            alloc: 0
+            segments: Segment::bare_metal_map(ram_size),
+            tlb: Some(Tlb::new()),
+            physical_map: true,
+            ..Self::new()
+        }
+    }
+
+    /// Starts recording the previous contents of every byte written
+    pub fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+        self.device_access = false;
+    }
+
+    /// Stops recording and returns what was overwritten, oldest first
+    pub fn take_journal(&mut self) -> Vec<(u32, u8)> {
+        self.journal.take().unwrap_or_default()
+    }
+
+    /// Whether a device was loaded from or stored to since the journal started.
+    /// Such accesses cannot be undone.
+    pub fn take_device_access(&mut self) -> bool {
+        std::mem::take(&mut self.device_access)
+    }
+
+    /// Restores bytes recorded by the journal, newest first
+    pub fn undo(&mut self, journal: &[(u32, u8)]) {
+        for (address, value) in journal.iter().rev() {
+            self.write_bytes(*address, &[*value]);
+        }
+    }
+
+    pub fn add_watchpoint(&mut self, start: u32, length: u32, kind: WatchKind) {
+        self.watchpoints.push(MemoryWatchpoint {
+            start,
+            length,
+            kind
+        });
+    }
+
+    /// Removes every watchpoint starting at `start`, returns whether any existed
+    pub fn remove_watchpoint(&mut self, start: u32) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints
+            .retain(|watchpoint| watchpoint.start != start);
+        self.watchpoints.len() != count
+    }
+
+    /// Removes the watchpoints of one kind starting at `start`, returns
+    /// whether any existed
+    pub fn remove_watchpoint_kind(&mut self, start: u32, kind: WatchKind) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints
+            .retain(|watchpoint| watchpoint.start != start || watchpoint.kind != kind);
+        self.watchpoints.len() != count
+    }
+
+    pub fn get_watchpoints(&self) -> &[MemoryWatchpoint] {
+        &self.watchpoints
+    }
+
+    /// Whether an access of `length` bytes at `address` triggers a watchpoint
+    pub fn is_watched(&self, address: u32, length: u32, access: WatchKind) -> bool {
+        self.watchpoints.iter().any(|watchpoint| {
+            watchpoint.kind.matches(access) && watchpoint.overlaps(address, length)
+        })
+    }
+
+    /// Records a hit for an access made outside the memory stage, a syscall
+    /// filling or reading a buffer. The first hit of a cycle is kept.
+    pub(crate) fn record_watchpoint_hit(&mut self, hit: WatchpointHit) {
+        self.watchpoint_hit.get_or_insert(hit);
+    }
+
+    /// Takes the watchpoint hit recorded by the memory stage this cycle
+    pub fn take_watchpoint_hit(&mut self) -> Option<WatchpointHit> {
+        self.watchpoint_hit.take()
+    }
+
+    pub fn get_program_break(&self) -> u32 {
+        self.program_break
+    }
+
+    /// Moves the program break by `increment` bytes, rounded up to a whole word,
+    /// and returns the previous break. The heap segment grows or shrinks with it.
+    pub fn sbrk(&mut self, increment: i32) -> Result<u32, SbrkError> {
+        self.sbrk_below(increment, STACK_LIMIT)
+    }
+
+    /// Like [`Memory::sbrk`], but the break also has to stay below the live
+    /// stack pointer, so the heap cannot grow over a stack deeper than the
+    /// stack segment. A stack pointer outside the heap and stack is ignored.
+    pub fn sbrk_below(&mut self, increment: i32, stack_pointer: u32) -> Result<u32, SbrkError> {
+        let previous = self.program_break;
+        let limit = match stack_pointer {
+            HEAP_BASE..=STACK_LIMIT => stack_pointer,
+            _ => STACK_LIMIT
+        };
+        let increment = (increment as i64 + 3) & !3;
+        let program_break = previous as i64 + increment;
+        if program_break < HEAP_BASE as i64 {
+            return Err(SbrkError::BelowHeap { program_break });
+        }
+        if program_break > limit as i64 {
+            return Err(SbrkError::StackCollision {
+                program_break: program_break as u64
+            });
+        }
+        self.set_program_break(program_break as u32);
+        debug!("Program break moved from {:#x} to {:#x}", previous, self.program_break);
+        Ok(previous)
+    }
+
+    /// Moves the program break without any checks, used when rewinding
+    pub fn set_program_break(&mut self, program_break: u32) {
+        self.program_break = program_break;
+        if let Some(heap) = self
+            .segments
+            .iter_mut()
+            .find(|segment| segment.name == "heap")
+        {
+            heap.end = program_break.wrapping_sub(1);
+        }
+    }
+
+    /// Base address and contents of every allocated page, lowest first
+    pub fn get_pages(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.pages
+            .iter()
+            .map(|(number, page)| (number * PAGE_SIZE, &page[..]))
+    }
+
+    /// Drops every page so all of memory reads as zero again
+    pub fn clear(&mut self) {
+        self.pages.clear();
+    }
+
+    pub fn get_segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Maps `device` at `start` as a new read/write segment named after it. The
+    /// range may not overlap any segment, the heap counts as reaching up to
+    /// the stack since it can grow that far.
+    pub fn map_device(
+        &mut self,
+        start: u32,
+        device: Box<dyn Device>
+    ) -> Result<(), DeviceMapError> {
+        let name = device.name();
+        let end = device
+            .size()
+            .checked_sub(1)
+            .and_then(|last| start.checked_add(last))
+            .ok_or(DeviceMapError::BadRange { device: name, start })?;
+        for segment in &self.segments {
+            let segment_end = match segment.name {
+                "heap" => STACK_LIMIT - 1,
+                _ => segment.end
+            };
+            if start <= segment_end && segment.start <= end {
+                return Err(DeviceMapError::Overlap {
+                    device: name,
+                    segment: segment.name
+                });
+            }
         }
+        self.segments
+            .push(Segment::new(name, start, end, Permissions::READ_WRITE));
+        self.devices.insert(start, device);
+        debug!("Mapped device {} at {:#x}-{:#x}", name, start, end);
+        Ok(())
+    }
+
+    pub fn get_devices(&self) -> &DeviceMap {
+        &self.devices
+    }
+
+    pub(crate) fn get_devices_mut(&mut self) -> &mut DeviceMap {
+        &mut self.devices
+    }
+
+    /// Whether `address` belongs to a mapped device
+    pub fn is_device(&self, address: u32) -> bool {
+        self.devices.contains(address)
+    }
+
+    /// Lets every device advance by one cycle
+    pub fn tick_devices(&mut self) {
+        self.devices.tick();
+    }
+
+    pub fn is_user_mode(&self) -> bool {
+        self.user_mode
+    }
+
+    /// Limits [`Memory::fetch`], [`Memory::load`] and [`Memory::store`] to
+    /// user segments, the processor keeps this in line with Status
+    pub fn set_user_mode(&mut self, user_mode: bool) {
+        self.user_mode = user_mode;
+    }
+
+    pub fn get_tlb(&self) -> Option<&Tlb> {
+        self.tlb.as_ref()
+    }
+
+    pub fn get_tlb_mut(&mut self) -> Option<&mut Tlb> {
+        self.tlb.as_mut()
+    }
+
+    /// Turns on address translation, set it up before loading kernel code
+    /// so it lands where kseg0 maps to
+    pub fn set_tlb(&mut self, tlb: Tlb) {
+        self.tlb = Some(tlb);
+    }
+
+    /// Turns address translation off again
+    pub fn clear_tlb(&mut self) {
+        self.tlb = None;
+    }
+
+    pub fn segment(&self, address: u32) -> Option<&Segment> {
+        self.segments
+            .iter()
+            .find(|segment| segment.contains(address))
+    }
+
+    /// Checks every byte of an access against the segment map
+    pub fn check(&self, address: u32, length: u32, access: Access) -> Result<(), MemoryFault> {
+        for i in 0..length {
+            let address = address.wrapping_add(i);
+            match self.segment(address) {
+                None => return Err(MemoryFault::Unmapped { address, access }),
+                Some(segment) if segment.kernel && self.user_mode => {
+                    return Err(MemoryFault::Privileged { address, access })
+                }
+                Some(segment) if !segment.permissions.allows(access) => {
+                    return Err(MemoryFault::Permission {
+                        address,
+                        access,
+                        segment: segment.name
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Physical address of a pipeline access. Without a TLB that is the
+    /// address itself once the segment map allows the access.
+    pub fn translate(&self, address: u32, length: u32, access: Access) -> Result<u32, MemoryFault> {
+        match &self.tlb {
+            Some(tlb) => {
+                let physical = tlb.translate(address, access, self.user_mode)?;
+                if self.physical_map {
+                    self.check(physical, length, access)?;
+                }
+                Ok(physical)
+            }
+            None => {
+                self.check(address, length, access)?;
+                Ok(address)
+            }
+        }
+    }
+
+    /// Whether a pipeline access to `address` may be cached. kseg1 never is,
+    /// with a TLB neither are pages mapped with the N bit.
+    pub fn is_cached(&self, address: u32) -> bool {
+        match &self.tlb {
+            Some(tlb) => tlb.is_cached(address),
+            None => !(KSEG1..KSEG2).contains(&address)
+        }
+    }
+
+    /// Where the loader puts words meant for `address`, the physical address
+    /// behind kseg0 and kseg1 when there is a TLB
+    fn physical(&self, address: u32) -> u32 {
+        match self.tlb {
+            Some(_) if (KSEG0..KSEG2).contains(&address) => address & 0x1FFF_FFFF,
+            _ => address
+        }
+    }
+
+    /// Physical address the running program reaches at `address`, without
+    /// faulting. Debuggers and syscalls go through it, None means no TLB
+    /// entry maps the page.
+    pub fn lookup(&self, address: u32) -> Option<u32> {
+        match &self.tlb {
+            Some(tlb) => tlb.translate(address, Access::Read, false).ok(),
+            None => Some(address)
+        }
+    }
+
+    /// Byte the running program sees at `address`, 0 where nothing maps it
+    pub fn peek_byte(&self, address: u32) -> u8 {
+        self.lookup(address).map_or(0, |physical| self.read_byte(physical))
+    }
+
+    /// Little-endian value the running program sees at `address`
+    pub fn peek(&self, address: u32, size: Size) -> u64 {
+        (0..size.bytes()).rev().fold(0, |value, i| {
+            value << 8 | self.peek_byte(address.wrapping_add(i)) as u64
+        })
+    }
+
+    pub fn peek_word(&self, address: u32) -> u32 {
+        self.peek(address, Size::Word) as u32
+    }
+
+    /// Null terminated string the running program sees at `address`
+    pub fn peek_cstring(&self, address: u32) -> String {
+        let mut result = String::new();
+        let mut index = address;
+        loop {
+            let byte = self.peek_byte(index);
+            if byte == 0 {
+                break;
+            }
+            result.push(byte as char);
+            index = index.wrapping_add(1);
+        }
+        result
+    }
+
+    pub fn fetch(&self, address: u32) -> Result<u32, MemoryFault> {
+        let address = self.translate(address, 4, Access::Execute)?;
+        Ok(self.read_word(address))
+    }
+
+    pub fn load(&mut self, address: u32, size: Size) -> Result<u64, MemoryFault> {
+        let address = self.translate(address, size.bytes(), Access::Read)?;
+        match self.devices.read(address, size) {
+            Some(value) => {
+                self.device_access |= self.journal.is_some();
+                Ok(value as u64)
+            }
+            None => Ok(self.read(address, size))
+        }
+    }
+
+    pub fn store(&mut self, address: u32, size: Size, value: u64) -> Result<(), MemoryFault> {
+        let address = self.translate(address, size.bytes(), Access::Write)?;
+        if self.devices.write(address, size, value as u32) {
+            self.device_access |= self.journal.is_some();
+            return Ok(());
+        }
+        match size {
+            Size::Byte => self.write_byte(address, value as u8),
+            Size::Halfword => self.write_halfword(address, value as u16),
+            Size::Word => self.write_word(address, value as u32),
+            Size::Quad => self.write_quad(address, value)
+        }
+        Ok(())
     }
 
     pub fn get_stack_pointer(&self) -> u32 {
@@ -52,13 +485,39 @@ impl Memory {
     }
 
     pub fn load_program(&mut self, program: Vec<u32>) {
-        let mut index = self.alloc;
-        for instruction in program {
-            self.write_word(index, instruction);
-            index += 4;
+        self.load_words(TEXT_BASE, program);
+    }
+
+    pub fn load_data(&mut self, data: Vec<u32>) {
+        self.load_words(DATA_BASE, data);
+    }
+
+    /// Loads a ROM image at the start of the boot ROM
+    pub fn load_rom(&mut self, rom: Vec<u32>) -> Result<(), RomError> {
+        let bytes = rom.len() * 4;
+        if bytes > ROM_SIZE as usize {
+            return Err(RomError::TooLarge { bytes });
+        }
+        self.load_words(ROM_BASE, rom);
+        Ok(())
+    }
+
+    /// Loads an exception handler at the exception vector, like `.ktext`
+    pub fn load_kernel_program(&mut self, program: Vec<u32>) {
+        self.load_words(self.physical(KTEXT_BASE), program);
+    }
+
+    /// Loads words into the kernel data segment, like `.kdata`
+    pub fn load_kernel_data(&mut self, data: Vec<u32>) {
+        self.load_words(self.physical(KDATA_BASE), data);
+    }
+
+    fn load_words(&mut self, base: u32, words: Vec<u32>) {
+        let mut index = base;
+        for word in words {
+            self.write_word(index, word);
+            index = index.wrapping_add(4);
         }
-        self.stack_pointer = index;
-        self.alloc = self.alloc.max(index);
     }
 
     pub fn read<T>(&self, address: u32, size: Size) -> T
@@ -75,174 +534,251 @@ impl Memory {
 
     pub fn read_byte(&self, address: u32) -> u8 {
         trace!("Reading byte from address {:#x}", address);
-        self.check_address(address);
-        let pointer = unsafe { self.pointer.offset(address as isize) };
-        unsafe { pointer.read() }
+        match self.pages.get(&Self::page_number(address)) {
+            Some(page) => page[Self::page_offset(address)],
+            None => 0
+        }
     }
 
     pub fn write_byte(&mut self, address: u32, value: u8) {
         debug!("Writing byte {:#x} to address {:#x}", value, address);
-        self.check_address(address);
-        let pointer = unsafe { self.pointer.offset(address as isize) };
-        unsafe {
-            pointer.write(value);
-        }
-        self.alloc = self.alloc.max(address + 1);
+        self.write_bytes(address, &[value]);
     }
 
     pub fn read_halfword(&self, address: u32) -> u16 {
         trace!("Reading halfword from address {:#x}", address);
-        self.check_address(address);
-        let pointer = unsafe { self.pointer.offset(address as isize) };
-        unsafe { pointer.cast::<u16>().read() }
+        u16::from_le_bytes(self.read_bytes(address))
     }
 
     pub fn write_halfword(&mut self, address: u32, value: u16) {
         debug!("Writing halfword {:#x} to address {:#x}", value, address);
-        self.check_address(address);
-        let pointer = unsafe { self.pointer.offset(address as isize) };
-        unsafe {
-            pointer.cast::<u16>().write(value);
-        }
-        self.alloc = self.alloc.max(address + 2);
+        self.write_bytes(address, &value.to_le_bytes());
     }
 
     pub fn read_word(&self, address: u32) -> u32 {
         trace!("Reading word from address {:#x}", address);
-        self.check_address(address);
-        let pointer = unsafe { self.pointer.offset(address as isize) };
-        unsafe { pointer.cast::<u32>().read() }
+        u32::from_le_bytes(self.read_bytes(address))
     }
 
     pub fn write_word(&mut self, address: u32, value: u32) {
         debug!("Writing word {:#x} to address {:#x}", value, address);
-        self.check_address(address);
-        let pointer = unsafe { self.pointer.offset(address as isize) };
-        unsafe {
-            pointer.cast::<u32>().write(value);
-        }
-        self.alloc = self.alloc.max(address + 4);
+        self.write_bytes(address, &value.to_le_bytes());
     }
 
     pub fn read_quad(&self, address: u32) -> u64 {
         trace!("Reading quad from address {:#x}", address);
-        self.check_address(address);
-        let pointer = unsafe { self.pointer.offset(address as isize) };
-        unsafe { pointer.cast::<u64>().read() }
+        u64::from_le_bytes(self.read_bytes(address))
     }
 
     pub fn write_quad(&mut self, address: u32, value: u64) {
         debug!("Writing quad {:#x} to address {:#x}", value, address);
-        self.check_address(address);
-        let pointer = unsafe { self.pointer.offset(address as isize) };
-        unsafe {
-            pointer.cast::<u64>().write(value);
-        }
-        self.alloc = self.alloc.max(address + 8);
+        self.write_bytes(address, &value.to_le_bytes());
     }
 
     pub fn read_cstring(&self, address: u32) -> String {
         trace!("Reading cstring from address {:#x}", address);
-        self.check_address(address);
         let mut result = String::new();
         let mut index = address;
         loop {
             let byte: u8 = self.read_byte(index);
-            if byte == "\0".as_bytes()[0] {
+            if byte == 0 {
                 break;
             }
             result.push(byte as char);
-            index += 1;
+            index = index.wrapping_add(1);
         }
         result
     }
 
     pub fn write_cstring(&mut self, address: u32, value: &str) {
         debug!("Writing cstring {:?} to address {:#x}", value, address);
-        self.check_address(address);
-        let bytes = value.as_bytes();
-        let mut index = address;
-        for byte in bytes {
-            self.write_byte(index, *byte);
-            index += 1;
+        self.write_bytes(address, value.as_bytes());
+        self.write_byte(address.wrapping_add(value.len() as u32), 0);
+    }
+
+    /// Reads `N` consecutive bytes, wrapping around the top of the address space
+    fn read_bytes<const N: usize>(&self, address: u32) -> [u8; N] {
+        let mut bytes = [0; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.read_byte(address.wrapping_add(i as u32));
+        }
+        bytes
+    }
+
+    fn write_bytes(&mut self, address: u32, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            let address = address.wrapping_add(i as u32);
+            if self.journal.is_some() {
+                let previous = self.read_byte(address);
+                if let Some(journal) = self.journal.as_mut() {
+                    journal.push((address, previous));
+                }
+            }
+            let page = self
+                .pages
+                .entry(Self::page_number(address))
+                .or_insert_with(|| Box::new([0; PAGE_SIZE as usize]));
+            page[Self::page_offset(address)] = *byte;
+        }
+    }
+
+    fn page_number(address: u32) -> u32 {
+        address / PAGE_SIZE
+    }
+
+    fn page_offset(address: u32) -> usize {
+        (address % PAGE_SIZE) as usize
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Memory {
+    /// Dumps the allocated pages overlapping `start..=end`
+    fn fmt_range(&self, f: &mut Formatter<'_>, start: u32, end: u32) -> std::fmt::Result {
+        let pages = self
+            .pages
+            .range(Self::page_number(start)..=Self::page_number(end));
+        for (number, page) in pages {
+            let base = number * PAGE_SIZE;
+            // Only dump up to the last word that holds something
+            let used = match page.iter().rposition(|byte| *byte != 0) {
+                Some(last) => (last as u32 / 4) + 1,
+                None => continue
+            };
+            let first = base.max(start);
+            let last = (base + (used * 4 - 1)).min(end);
+            if first <= last {
+                write!(f, "{}", MemoryDump::new(self, first, last - first + 1, Size::Word))?;
+            }
         }
-        self.write_byte(index, "\0".as_bytes()[0]);
-        self.alloc = self.alloc.max(index + 1);
+        Ok(())
     }
+}
 
-    fn check_address(&self, address: u32) {
-        if address > self.capacity || self.capacity == 0 {
-            panic!("Address {} out of range", address);
+impl Display for RomError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RomError::TooLarge { bytes } => write!(
+                f,
+                "ROM image of {} bytes does not fit in the {} byte boot ROM",
+                bytes, ROM_SIZE
+            )
         }
     }
 }
 
+impl std::error::Error for RomError {}
+
 impl Display for Memory {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "Memory: {} bytes", self.capacity)?;
+        writeln!(
+            f,
+            "Memory: {} pages allocated ({} bytes)",
+            self.pages.len(),
+            self.pages.len() as u32 * PAGE_SIZE
+        )?;
         writeln!(f, "Stack Pointer: {:#x}", self.stack_pointer)?;
-        writeln!(f, "Allocated: {:#x}", self.alloc)?;
-        for i in 0..(self.alloc / 4) {
-            let index = i * 4;
-            let byte = self.read_word(index);
-            write!(f, "    {:#04x}: {:#010x}    |    ", index, byte)?;
-            for j in 0..4 {
-                let byte = self.read_byte(index + j);
-                write!(f, "{:#04x} ", byte)?;
+        writeln!(f, "Program Break: {:#x}", self.program_break)?;
+        for segment in &self.segments {
+            if segment.is_empty() {
+                continue;
             }
-            writeln!(f)?;
+            if self.devices.contains(segment.start) {
+                writeln!(f, "Device {}", segment)?;
+                continue;
+            }
+            writeln!(f, "Segment {}:", segment)?;
+            self.fmt_range(f, segment.start, segment.end)?;
         }
         Ok(())
     }
 }
 
+#[allow(non_snake_case)]
 pub mod DataMemory {
-    use log::{debug, info, trace};
+    use log::info;
     use num_traits::FromPrimitive;
     use crate::processor::alu::OpCode;
     use crate::processor::buffer::{EXMEMBuffer, MEMWBBuffer};
-    use crate::processor::memory::Memory;
+    use crate::processor::memory::{Memory, Size};
+    use crate::processor::segment::{Access, MemoryFault};
+    use crate::processor::watchpoint::{WatchKind, WatchTarget, WatchpointHit};
+
+    /// Address, size and kind of the access the instruction in EX/MEM makes, if any
+    pub fn access(exmem: &EXMEMBuffer) -> Option<(u32, Size, Access)> {
+        let opcode = OpCode::from_u8(exmem.instruction?.opcode)?;
+        let (size, access) = match opcode {
+            OpCode::Lbu => (Size::Byte, Access::Read),
+            OpCode::Lhu => (Size::Halfword, Access::Read),
+            OpCode::Lw => (Size::Word, Access::Read),
+            OpCode::Sb => (Size::Byte, Access::Write),
+            OpCode::Sh => (Size::Halfword, Access::Write),
+            OpCode::Sw => (Size::Word, Access::Write),
+            _ => return None
+        };
+        Some((exmem.alu_result, size, access))
+    }
 
-    pub fn execute(exmem: &EXMEMBuffer, memwb: &mut MEMWBBuffer, memory: &mut Memory) {
+    pub fn execute(
+        exmem: &EXMEMBuffer,
+        memwb: &mut MEMWBBuffer,
+        memory: &mut Memory
+    ) -> Result<(), MemoryFault> {
         info!("Executing memory stage");
-        let instruction = exmem.instruction;
-        if instruction.is_none() {
-            return;
-        }
-        let instruction = instruction.unwrap();
-        let opcode = OpCode::from_u8(instruction.opcode).unwrap();
-        match opcode {
+        memwb.instruction = exmem.instruction;
+        memwb.pc = exmem.pc;
+        memwb.address = exmem.address;
+        memwb.data = exmem.alu_result;
+        let instruction = match exmem.instruction {
+            Some(instruction) => instruction,
+            None => return Ok(())
+        };
+        let opcode = match OpCode::from_u8(instruction.opcode) {
+            Some(opcode) => opcode,
+            None => return Ok(())
+        };
+        let address = exmem.alu_result;
+        let (size, access, value) = match opcode {
             OpCode::Lbu => {
-                let address = exmem.alu_result;
-                let value = memory.read_byte(address);
-                memwb.data = value as u32;
+                memwb.data = memory.load(address, Size::Byte)? as u32;
+                (Size::Byte, WatchKind::Read, memwb.data)
             }
             OpCode::Lhu => {
-                let address = exmem.alu_result;
-                let value = memory.read_halfword(address);
-                memwb.data = value as u32;
+                memwb.data = memory.load(address, Size::Halfword)? as u32;
+                (Size::Halfword, WatchKind::Read, memwb.data)
             }
             OpCode::Lw => {
-                let address = exmem.alu_result;
-                let value = memory.read_word(address);
-                memwb.data = value;
+                memwb.data = memory.load(address, Size::Word)? as u32;
+                (Size::Word, WatchKind::Read, memwb.data)
             }
             OpCode::Sb => {
-                let address = exmem.alu_result;
-                let value = exmem.data_2 as u8;
-                memory.write_byte(address, value);
+                memory.store(address, Size::Byte, exmem.data_2 as u64)?;
+                (Size::Byte, WatchKind::Write, exmem.data_2 as u8 as u32)
             }
             OpCode::Sh => {
-                let address = exmem.alu_result;
-                let value = exmem.data_2 as u16;
-                memory.write_halfword(address, value);
+                memory.store(address, Size::Halfword, exmem.data_2 as u64)?;
+                (Size::Halfword, WatchKind::Write, exmem.data_2 as u16 as u32)
             }
             OpCode::Sw => {
-                let address = exmem.alu_result;
-                let value = exmem.data_2;
-                memory.write_word(address, value);
+                memory.store(address, Size::Word, exmem.data_2 as u64)?;
+                (Size::Word, WatchKind::Write, exmem.data_2)
             }
-            _ => {}
+            _ => return Ok(())
+        };
+        if memory.is_watched(address, size.bytes(), access) {
+            memory.watchpoint_hit = Some(WatchpointHit {
+                pc: exmem.address,
+                instruction,
+                target: WatchTarget::Memory(address),
+                access,
+                value
+            });
         }
+        Ok(())
     }
 }
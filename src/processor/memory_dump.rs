@@ -0,0 +1,129 @@
+use std::fmt::{Display, Formatter};
+use crate::processor::instruction::Instruction;
+use crate::processor::memory::{Memory, Size};
+use crate::processor::symbols::SymbolTable;
+
+/// Bytes shown on a full row of the hex view
+const ROW_BYTES: u32 = 16;
+
+/// An `xxd` style view of a range of memory:
+///
+/// ```text
+/// <message>:
+/// 0x10010000: 6c6c6548 77202c6f 646c726f 00000a21  Hello, world!...
+/// ```
+///
+/// Bytes are grouped into bytes, halfwords or words, each group printed as
+/// the little-endian value it holds, followed by the printable ASCII of the
+/// row. Rows break at every known label so each label starts a line. Ranges
+/// in an executable segment are shown one word per line with disassembly
+/// instead. The length is rounded up to a whole group.
+pub struct MemoryDump<'a> {
+    memory: &'a Memory,
+    symbols: Option<&'a SymbolTable>,
+    start: u32,
+    length: u32,
+    grouping: Size
+}
+
+impl<'a> MemoryDump<'a> {
+    pub fn new(memory: &'a Memory, start: u32, length: u32, grouping: Size) -> Self {
+        Self {
+            memory,
+            symbols: None,
+            start,
+            length,
+            grouping
+        }
+    }
+
+    /// Labels addresses found in `symbols`
+    pub fn new_with_symbols(
+        memory: &'a Memory,
+        symbols: &'a SymbolTable,
+        start: u32,
+        length: u32,
+        grouping: Size
+    ) -> Self {
+        Self {
+            memory,
+            symbols: Some(symbols),
+            start,
+            length,
+            grouping
+        }
+    }
+
+    fn label(&self, address: u32) -> Option<&str> {
+        self.symbols.and_then(|symbols| symbols.name(address))
+    }
+
+    fn is_text(&self, address: u32) -> bool {
+        self.memory
+            .segment(address)
+            .is_some_and(|segment| segment.permissions.execute)
+    }
+
+    /// Writes one row starting at `address` and returns how many bytes it covered
+    fn fmt_row(
+        &self,
+        f: &mut Formatter<'_>,
+        address: u32,
+        remaining: u32
+    ) -> Result<u32, std::fmt::Error> {
+        if let Some(label) = self.label(address) {
+            writeln!(f, "<{}>:", label)?;
+        }
+        if self.is_text(address) {
+            let instruction = Instruction::load(self.memory.peek_word(address));
+            writeln!(
+                f,
+                "{:#010x}: {:08x}  {}",
+                address,
+                instruction.word,
+                instruction.disassemble()
+            )?;
+            return Ok(4);
+        }
+
+        // The row ends early at the next label, but never inside a group
+        let group = self.grouping.bytes();
+        let mut size = group;
+        while size < ROW_BYTES.min(remaining) &&
+            self.label(address.wrapping_add(size)).is_none()
+        {
+            size += group;
+        }
+
+        write!(f, "{:#010x}:", address)?;
+        for offset in (0..size).step_by(group as usize) {
+            let value = self.memory.peek(address.wrapping_add(offset), self.grouping);
+            write!(f, " {:0width$x}", value, width = group as usize * 2)?;
+        }
+        // Pad short rows so the ASCII column lines up
+        let missing = (ROW_BYTES - size) / group;
+        write!(f, "{:width$}  ", "", width = (missing * (group * 2 + 1)) as usize)?;
+        for offset in 0..size {
+            let byte = self.memory.peek_byte(address.wrapping_add(offset));
+            let character = match byte {
+                0x20..=0x7E => byte as char,
+                _ => '.'
+            };
+            write!(f, "{}", character)?;
+        }
+        writeln!(f)?;
+        Ok(size)
+    }
+}
+
+impl Display for MemoryDump<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let group = self.grouping.bytes();
+        let length = self.length.div_ceil(group) * group;
+        let mut offset = 0;
+        while offset < length {
+            offset += self.fmt_row(f, self.start.wrapping_add(offset), length - offset)?;
+        }
+        Ok(())
+    }
+}
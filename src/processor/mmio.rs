@@ -0,0 +1,170 @@
+use crate::processor::console::Console;
+use crate::processor::device::Device;
+use crate::processor::memory::Size;
+
+/// Where MARS maps its keyboard and display simulator
+pub const MMIO_BASE: u32 = 0xFFFF_0000;
+/// Cycles the display stays busy after a character by default
+pub const TRANSMIT_DELAY: u32 = 5;
+
+/// Receiver control, bit 0 is set while a key is waiting
+pub const RECEIVER_CONTROL: u32 = 0x0;
+/// Receiver data, the last key in the low byte. Reading it clears the ready bit.
+pub const RECEIVER_DATA: u32 = 0x4;
+/// Transmitter control, bit 0 is set while the display can take a character
+pub const TRANSMITTER_CONTROL: u32 = 0x8;
+/// Transmitter data, storing a character shows it if the display is ready
+pub const TRANSMITTER_DATA: u32 = 0xC;
+
+/// Control register bit that reports the device is ready
+pub const READY: u32 = 0x1;
+/// Control register bit the program sets to be interrupted when ready
+pub const INTERRUPT_ENABLE: u32 = 0x2;
+
+/// Line the keyboard interrupts on, Cause bit 8 like MARS
+pub const KEYBOARD_INTERRUPT: u8 = 0;
+/// Line the display interrupts on, Cause bit 9 like MARS
+pub const DISPLAY_INTERRUPT: u8 = 1;
+
+/// The MARS "Keyboard and Display MMIO Simulator": a receiver for keys typed
+/// on a [`Console`] and a transmitter that writes characters back to it, each
+/// with a control and a data register. Keys are picked up without blocking,
+/// from the host terminal with a [`StdConsole`] or from the script of a
+/// [`ScriptedConsole`].
+///
+/// [`StdConsole`]: crate::processor::console::StdConsole
+/// [`ScriptedConsole`]: crate::processor::console::ScriptedConsole
+pub struct KeyboardDisplay {
+    console: Box<dyn Console>,
+    /// Last key received, meaningful while `key_ready` is set
+    key: u8,
+    key_ready: bool,
+    keyboard_interrupts: bool,
+    /// Cycles the display stays busy after each character
+    delay: u32,
+    /// Cycles left until the display takes the next character
+    busy: u32,
+    display_interrupts: bool
+}
+
+impl KeyboardDisplay {
+    pub fn new(console: Box<dyn Console>) -> Self {
+        Self::new_with_delay(console, TRANSMIT_DELAY)
+    }
+
+    /// The display stays busy for `delay` cycles after each character, zero
+    /// keeps it ready all the time
+    pub fn new_with_delay(console: Box<dyn Console>, delay: u32) -> Self {
+        Self {
+            console,
+            key: 0,
+            key_ready: false,
+            keyboard_interrupts: false,
+            delay,
+            busy: 0,
+            display_interrupts: false
+        }
+    }
+}
+
+/// Value of a control register
+fn control(ready: bool, interrupts: bool) -> u32 {
+    let mut value = 0;
+    if ready {
+        value |= READY;
+    }
+    if interrupts {
+        value |= INTERRUPT_ENABLE;
+    }
+    value
+}
+
+impl Device for KeyboardDisplay {
+    fn name(&self) -> &'static str {
+        "mmio"
+    }
+
+    fn size(&self) -> u32 {
+        16
+    }
+
+    fn read(&mut self, offset: u32, size: Size) -> u32 {
+        let value = match offset & !3 {
+            RECEIVER_CONTROL => control(self.key_ready, self.keyboard_interrupts),
+            RECEIVER_DATA => {
+                self.key_ready = false;
+                self.key as u32
+            }
+            TRANSMITTER_CONTROL => control(self.busy == 0, self.display_interrupts),
+            // The transmitter data register is write only
+            _ => 0
+        };
+        let value = value >> ((offset & 3) * 8);
+        match size {
+            Size::Byte => value & 0xFF,
+            Size::Halfword => value & 0xFFFF,
+            Size::Word | Size::Quad => value
+        }
+    }
+
+    fn write(&mut self, offset: u32, _size: Size, value: u32) {
+        let value = value << ((offset & 3) * 8);
+        match offset & !3 {
+            // Only the interrupt enable bits are writable
+            RECEIVER_CONTROL => self.keyboard_interrupts = value & INTERRUPT_ENABLE != 0,
+            TRANSMITTER_CONTROL => self.display_interrupts = value & INTERRUPT_ENABLE != 0,
+            // Characters sent while the display is busy are lost
+            TRANSMITTER_DATA if self.busy == 0 => {
+                self.console.write(&(value as u8 as char).to_string());
+                self.busy = self.delay;
+            }
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self) {
+        self.busy = self.busy.saturating_sub(1);
+        if !self.key_ready {
+            if let Some(key) = self.console.poll_char() {
+                self.key = key as u8;
+                self.key_ready = true;
+            }
+        }
+    }
+
+    fn interrupts(&self) -> u8 {
+        let mut lines = 0;
+        if self.key_ready && self.keyboard_interrupts {
+            lines |= 1 << KEYBOARD_INTERRUPT;
+        }
+        if self.busy == 0 && self.display_interrupts {
+            lines |= 1 << DISPLAY_INTERRUPT;
+        }
+        lines
+    }
+
+    /// The key, ready and interrupt enable flags and the cycles the display
+    /// stays busy. The delay belongs to how the device was built.
+    fn save(&self) -> Vec<u8> {
+        let mut state = vec![
+            self.key,
+            self.key_ready as u8,
+            self.keyboard_interrupts as u8,
+            self.display_interrupts as u8
+        ];
+        state.extend(self.busy.to_le_bytes());
+        state
+    }
+
+    fn restore(&mut self, state: &[u8]) -> bool {
+        if state.len() != 8 || state[1..4].iter().any(|flag| *flag > 1) {
+            return false;
+        }
+        self.key = state[0];
+        self.key_ready = state[1] != 0;
+        self.keyboard_interrupts = state[2] != 0;
+        self.display_interrupts = state[3] != 0;
+        self.busy = u32::from_le_bytes([state[4], state[5], state[6], state[7]]);
+        true
+    }
+}
@@ -0,0 +1,100 @@
+use std::fmt::{Display, Formatter};
+
+/// Why the pipeline inserted a bubble instead of moving forward
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StallCause {
+    /// Decode waited on a register an earlier instruction has not written yet
+    DataHazard,
+    /// Fetch waited for a branch in decode to resolve
+    Control,
+    /// Fetch waited on an instruction cache miss
+    InstructionCache,
+    /// MEM waited on a data cache miss, holding every stage behind it
+    DataCache
+}
+
+/// Cycle, instruction and stall counts since the processor was created
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PipelineStats {
+    pub cycles: u64,
+    /// Instructions that reached writeback
+    pub instructions: u64,
+    pub data_hazard_stalls: u64,
+    pub control_stalls: u64,
+    pub instruction_cache_stalls: u64,
+    pub data_cache_stalls: u64
+}
+
+impl StallCause {
+    /// Short name used in traces
+    pub fn name(&self) -> &'static str {
+        match self {
+            StallCause::DataHazard => "data_hazard",
+            StallCause::Control => "control",
+            StallCause::InstructionCache => "instruction_cache",
+            StallCause::DataCache => "data_cache"
+        }
+    }
+}
+
+impl PipelineStats {
+    pub(crate) fn record(&mut self, stall: Option<StallCause>, committed: bool) {
+        self.cycles += 1;
+        if committed {
+            self.instructions += 1;
+        }
+        match stall {
+            Some(StallCause::DataHazard) => self.data_hazard_stalls += 1,
+            Some(StallCause::Control) => self.control_stalls += 1,
+            Some(StallCause::InstructionCache) => self.instruction_cache_stalls += 1,
+            Some(StallCause::DataCache) => self.data_cache_stalls += 1,
+            None => {}
+        }
+    }
+
+    pub fn stalls(&self) -> u64 {
+        self.data_hazard_stalls +
+            self.control_stalls +
+            self.instruction_cache_stalls +
+            self.data_cache_stalls
+    }
+
+    /// Cycles per committed instruction, zero before the first commit
+    pub fn cpi(&self) -> f64 {
+        match self.instructions {
+            0 => 0.0,
+            instructions => self.cycles as f64 / instructions as f64
+        }
+    }
+}
+
+impl Display for StallCause {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StallCause::DataHazard => write!(f, "data hazard"),
+            StallCause::Control => write!(f, "branch"),
+            StallCause::InstructionCache => write!(f, "instruction cache miss"),
+            StallCause::DataCache => write!(f, "data cache miss")
+        }
+    }
+}
+
+impl Display for PipelineStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} cycles, {} instructions, CPI {:.2}",
+            self.cycles,
+            self.instructions,
+            self.cpi()
+        )?;
+        writeln!(
+            f,
+            "Stalls: {} data hazard, {} branch, {} instruction cache, {} data cache",
+            self.data_hazard_stalls,
+            self.control_stalls,
+            self.instruction_cache_stalls,
+            self.data_cache_stalls
+        )
+    }
+}
@@ -1,4 +1,4 @@
-use log::{info, trace};
+use log::info;
 
 pub struct ProgramCounter {
     pc: u32
@@ -22,3 +22,9 @@ impl ProgramCounter {
         self.pc += 4;
     }
 }
+
+impl Default for ProgramCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
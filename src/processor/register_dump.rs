@@ -0,0 +1,158 @@
+use std::fmt::{Display, Formatter};
+use num_traits::FromPrimitive;
+use crate::processor::registers::Register;
+
+/// How register values are printed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegisterView {
+    Hex,
+    Signed,
+    Unsigned,
+    /// Hex, signed and unsigned side by side
+    All
+}
+
+/// Values of the general purpose registers, HI, LO and the program counter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RegisterState {
+    pub registers: [u32; 32],
+    pub hi: u32,
+    pub lo: u32,
+    pub pc: u32
+}
+
+/// Multi-column register listing. With a previous state it only lists what
+/// changed since then.
+pub struct RegisterDump {
+    state: RegisterState,
+    previous: Option<RegisterState>,
+    view: RegisterView
+}
+
+impl RegisterView {
+    /// Parses `hex`, `signed`, `unsigned` or `all`, or their first letter
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "x" | "hex" => Some(RegisterView::Hex),
+            "d" | "signed" => Some(RegisterView::Signed),
+            "u" | "unsigned" => Some(RegisterView::Unsigned),
+            "a" | "all" => Some(RegisterView::All),
+            _ => None
+        }
+    }
+
+    fn format(&self, value: u32) -> String {
+        match self {
+            RegisterView::Hex => format!("{:#010x}", value),
+            RegisterView::Signed => format!("{:>11}", value as i32),
+            RegisterView::Unsigned => format!("{:>10}", value),
+            RegisterView::All => format!("{:#010x} {:>11} {:>10}", value, value as i32, value)
+        }
+    }
+
+    /// Registers per row, fewer when each value takes more room
+    fn columns(&self) -> usize {
+        match self {
+            RegisterView::All => 2,
+            _ => 4
+        }
+    }
+}
+
+impl RegisterDump {
+    pub fn new(state: RegisterState, view: RegisterView) -> Self {
+        Self {
+            state,
+            previous: None,
+            view
+        }
+    }
+
+    /// Lists only the registers that differ from `previous`, with their old values
+    pub fn new_diff(state: RegisterState, previous: RegisterState, view: RegisterView) -> Self {
+        Self {
+            state,
+            previous: Some(previous),
+            view
+        }
+    }
+
+    fn fmt_full(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        fmt_grid(f, &self.state.registers, self.view)?;
+        writeln!(
+            f,
+            "{:<7} {}  {:<7} {}  {:<7} {:#010x}",
+            "hi",
+            self.view.format(self.state.hi),
+            "lo",
+            self.view.format(self.state.lo),
+            "pc",
+            self.state.pc
+        )
+    }
+
+    fn fmt_diff(&self, f: &mut Formatter<'_>, previous: &RegisterState) -> std::fmt::Result {
+        let mut changed = false;
+        for index in 0..32 {
+            let (old, new) = (previous.registers[index], self.state.registers[index]);
+            if old != new {
+                changed = true;
+                writeln!(
+                    f,
+                    "{:<7} {} -> {}",
+                    label(index),
+                    self.view.format(old),
+                    self.view.format(new)
+                )?;
+            }
+        }
+        for (name, old, new) in [
+            ("hi", previous.hi, self.state.hi),
+            ("lo", previous.lo, self.state.lo)
+        ] {
+            if old != new {
+                changed = true;
+                writeln!(f, "{:<7} {} -> {}", name, self.view.format(old), self.view.format(new))?;
+            }
+        }
+        if !changed {
+            writeln!(f, "No registers changed")?;
+        }
+        writeln!(f, "{:<7} {:#010x} -> {:#010x}", "pc", previous.pc, self.state.pc)
+    }
+}
+
+/// Writes the 32 registers in columns, filled downwards so each column holds
+/// a register group
+pub(crate) fn fmt_grid(
+    f: &mut Formatter<'_>,
+    registers: &[u32; 32],
+    view: RegisterView
+) -> std::fmt::Result {
+    let columns = view.columns();
+    let rows = 32 / columns;
+    for row in 0..rows {
+        let cells: Vec<String> = (0..columns)
+            .map(|column| {
+                let index = column * rows + row;
+                format!("{:<7} {}", label(index), view.format(registers[index]))
+            })
+            .collect();
+        writeln!(f, "{}", cells.join("  "))?;
+    }
+    Ok(())
+}
+
+/// `$t0/8` style label with both the ABI name and the number
+fn label(index: usize) -> String {
+    format!("${}/{}", Register::from_usize(index).unwrap().name(), index)
+}
+
+impl Display for RegisterDump {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.previous {
+            Some(previous) => self.fmt_diff(f, previous),
+            None => self.fmt_full(f)
+        }
+    }
+}
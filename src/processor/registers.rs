@@ -3,10 +3,17 @@ use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 use crate::processor::alu::{FunctionCode, OpCode};
 use crate::processor::buffer::{IDEXBuffer, IFIDBuffer, MEMWBBuffer};
-use crate::processor::instruction::InstructionType;
+use crate::processor::float_registers::Cop1Operation;
+use crate::processor::instruction::{Instruction, InstructionType};
+use crate::processor::register_dump::{fmt_grid, RegisterView};
+use crate::processor::watchpoint::{RegisterWatchpoint, WatchKind, WatchTarget, WatchpointHit};
 
 pub struct Registers {
-    r: [u32; 32]
+    r: [u32; 32],
+    watchpoints: Vec<RegisterWatchpoint>,
+    watchpoint_hit: Option<WatchpointHit>,
+    /// Previous value of every register written while journaling
+    journal: Option<Vec<(Register, u32)>>
 }
 
 #[repr(usize)]
@@ -78,17 +85,122 @@ pub enum Register {
     Ra = 31
 }
 
+/// ABI names indexed by register number
+const NAMES: [&str; 32] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6",
+    "t7", "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp",
+    "fp", "ra"
+];
+
+impl Register {
+    /// ABI name without the leading `$`
+    pub fn name(&self) -> &'static str {
+        NAMES[*self as usize]
+    }
+
+    /// Parses `$t0`, `t0`, `$8` or `8`, `$s8` is accepted as an alias of `$fp`
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.strip_prefix('$').unwrap_or(name);
+        if let Ok(index) = name.parse::<usize>() {
+            return Register::from_usize(index);
+        }
+        let name = name.to_lowercase();
+        if name == "s8" {
+            return Some(Register::Fp);
+        }
+        NAMES
+            .iter()
+            .position(|candidate| *candidate == name)
+            .and_then(Register::from_usize)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialOrd, PartialEq)]
 pub enum DecodeReturn {
     Jump(u32),
     Syscall,
+    /// A coprocessor 0 instruction the processor has to carry out
+    Cop0,
+    /// A move to or from a coprocessor 1 register
+    Cop1,
+    /// An opcode or function code no instruction uses
+    Reserved,
     None,
     Stall
 }
 
 impl Registers {
     pub fn new() -> Self {
-        Self { r: [0; 32] }
+        Self {
+            r: [0; 32],
+            watchpoints: Vec::new(),
+            watchpoint_hit: None,
+            journal: None
+        }
+    }
+
+    /// Starts recording the previous value of every register written
+    pub fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+    }
+
+    /// Stops recording and returns what was overwritten, oldest first
+    pub fn take_journal(&mut self) -> Vec<(Register, u32)> {
+        self.journal.take().unwrap_or_default()
+    }
+
+    /// Restores registers recorded by the journal, newest first
+    pub fn undo(&mut self, journal: &[(Register, u32)]) {
+        for (register, value) in journal.iter().rev() {
+            self.r[*register as usize] = *value;
+        }
+    }
+
+    pub fn add_watchpoint(&mut self, register: Register, kind: WatchKind) {
+        self.watchpoints
+            .push(RegisterWatchpoint { register, kind });
+    }
+
+    /// Removes every watchpoint on `register`, returns whether any existed
+    pub fn remove_watchpoint(&mut self, register: Register) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints
+            .retain(|watchpoint| watchpoint.register != register);
+        self.watchpoints.len() != count
+    }
+
+    pub fn get_watchpoints(&self) -> &[RegisterWatchpoint] {
+        &self.watchpoints
+    }
+
+    /// Takes the watchpoint hit recorded by writeback or decode this cycle
+    pub fn take_watchpoint_hit(&mut self) -> Option<WatchpointHit> {
+        self.watchpoint_hit.take()
+    }
+
+    /// Records a hit if `register` is watched for `access` and nothing was hit yet
+    fn watch(&mut self, register: Register, access: WatchKind, pc: u32, instruction: Instruction) {
+        if self.watchpoint_hit.is_some() {
+            return;
+        }
+        let watched = self.watchpoints.iter().any(|watchpoint| {
+            watchpoint.register == register && watchpoint.kind.matches(access)
+        });
+        if watched {
+            self.watchpoint_hit = Some(WatchpointHit {
+                // Buffers carry the already incremented PC
+                pc: pc.wrapping_sub(4),
+                instruction,
+                target: WatchTarget::Register(register),
+                access,
+                value: self.get(register)
+            });
+        }
+    }
+
+    /// Every register's value, indexed by register number
+    pub fn get_all(&self) -> [u32; 32] {
+        self.r
     }
 
     pub fn get(&self, reg: Register) -> u32 {
@@ -101,6 +213,9 @@ impl Registers {
         if (reg as usize) == 0 {
             return;
         }
+        if let Some(journal) = self.journal.as_mut() {
+            journal.push((reg, self.r[reg as usize]));
+        }
         self.r[reg as usize] = value;
     }
 
@@ -112,11 +227,12 @@ impl Registers {
     ) -> DecodeReturn {
         info!("Executing register stage");
         debug!("Executing write back");
-        let instruction = memwb.instruction;
-        if instruction.is_some() {
-            let instruction = instruction.unwrap();
-            let rd = Register::from_u8(instruction.rd.unwrap()).unwrap();
-            self.set(rd, memwb.data);
+        if let Some(instruction) = memwb.instruction {
+            if let Some(destination) = instruction.destination() {
+                let register = Register::from_u8(destination).unwrap();
+                self.set(register, memwb.data);
+                self.watch(register, WatchKind::Write, memwb.pc, instruction);
+            }
         }
 
         debug!("Executing decode");
@@ -130,10 +246,17 @@ impl Registers {
             return DecodeReturn::None;
         }
         let instruction = instruction.unwrap();
-        println!("Instruction: {}", instruction);
+        debug!("Decoding instruction:\n{}", instruction);
+        for source in instruction.sources() {
+            let register = Register::from_u8(source).unwrap();
+            self.watch(register, WatchKind::Read, ifid.pc, instruction);
+        }
         match instruction.instruction_type {
             InstructionType::R => {
-                let funct = FunctionCode::from_u8(instruction.funct.unwrap()).unwrap();
+                let funct = match instruction.funct.and_then(FunctionCode::from_u8) {
+                    Some(funct) => funct,
+                    None => return DecodeReturn::Reserved
+                };
                 match funct {
                     FunctionCode::Syscall => DecodeReturn::Syscall,
                     FunctionCode::Jr => {
@@ -150,7 +273,10 @@ impl Registers {
             }
             // Load instructions might cause a hazard?
             InstructionType::I => {
-                let opcode = OpCode::from_u8(instruction.opcode).unwrap();
+                let opcode = match OpCode::from_u8(instruction.opcode) {
+                    Some(opcode) => opcode,
+                    None => return DecodeReturn::Reserved
+                };
                 idex.data_1 = self.get(Register::from_u8(instruction.rs.unwrap()).unwrap());
                 idex.data_2 = self.get(Register::from_u8(instruction.rt.unwrap()).unwrap());
                 idex.sign_extended = instruction.imm.unwrap();
@@ -158,6 +284,11 @@ impl Registers {
                     OpCode::Beq | OpCode::Bne => {
                         DecodeReturn::Stall
                     }
+                    OpCode::Cop0 => DecodeReturn::Cop0,
+                    OpCode::Cop1 => match Cop1Operation::decode(instruction.word) {
+                        Some(_) => DecodeReturn::Cop1,
+                        None => DecodeReturn::Reserved
+                    },
                     _ => {
                         DecodeReturn::None
                     }
@@ -168,19 +299,22 @@ impl Registers {
                     // NOTE: it is unclear if the pc should be set to pc + 8 or + 4
                     self.set(Register::Ra, ifid.pc + 4);
                 }
-                DecodeReturn::Jump(ifid.pc + instruction.addr.unwrap() << 2)
+                DecodeReturn::Jump((ifid.pc & 0xF000_0000) | (instruction.addr.unwrap() << 2))
             }
             _ => DecodeReturn::None
         }
     }
 }
 
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl std::fmt::Display for Registers {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         writeln!(f, "Registers:")?;
-        for (i, j) in self.r.iter().enumerate() {
-            writeln!(f, "    {:?}: {:#x}", Register::from_usize(i).unwrap(), j)?;
-        }
-        Ok(())
+        fmt_grid(f, &self.r, RegisterView::Hex)
     }
 }
@@ -0,0 +1,228 @@
+use std::fmt::{Display, Formatter};
+
+/// Start of the heap and initial program break
+pub const HEAP_BASE: u32 = 0x1004_0000;
+/// Lowest address the stack is allowed to grow down to
+pub const STACK_LIMIT: u32 = 0x7F00_0000;
+/// Physical address of the boot ROM, kseg1 puts it at the reset vector
+pub const ROM_BASE: u32 = 0x1FC0_0000;
+/// Bytes of boot ROM, up to the end of what kseg1 reaches
+pub const ROM_SIZE: u32 = 0x0040_0000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub name: &'static str,
+    pub start: u32,
+    /// Inclusive so a segment can reach the top of the address space
+    pub end: u32,
+    pub permissions: Permissions,
+    /// Only reachable in kernel mode
+    pub kernel: bool
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryFault {
+    /// The address does not belong to any segment
+    Unmapped { address: u32, access: Access },
+    /// The segment does not allow this kind of access
+    Permission {
+        address: u32,
+        access: Access,
+        segment: &'static str
+    },
+    /// User mode touched a kernel segment
+    Privileged { address: u32, access: Access },
+    /// No TLB entry maps the page
+    TlbMiss { address: u32, access: Access },
+    /// The TLB entry for the page is not valid
+    TlbInvalid { address: u32, access: Access },
+    /// A store to a page whose TLB entry is not dirty
+    TlbModified { address: u32 }
+}
+
+impl Permissions {
+    pub const READ_WRITE: Self = Self {
+        read: true,
+        write: true,
+        execute: false
+    };
+    pub const READ_EXECUTE: Self = Self {
+        read: true,
+        write: false,
+        execute: true
+    };
+    pub const ALL: Self = Self {
+        read: true,
+        write: true,
+        execute: true
+    };
+
+    pub fn allows(&self, access: Access) -> bool {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute
+        }
+    }
+}
+
+impl Segment {
+    pub fn new(name: &'static str, start: u32, end: u32, permissions: Permissions) -> Self {
+        Self {
+            name,
+            start,
+            end,
+            permissions,
+            kernel: false
+        }
+    }
+
+    /// A segment user mode cannot reach
+    pub fn new_kernel(
+        name: &'static str,
+        start: u32,
+        end: u32,
+        permissions: Permissions
+    ) -> Self {
+        Self {
+            kernel: true,
+            ..Self::new(name, start, end, permissions)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end < self.start
+    }
+
+    pub fn contains(&self, address: u32) -> bool {
+        self.start <= address && address <= self.end
+    }
+
+    /// The SPIM user and kernel address space layout, the heap starts out
+    /// empty and is grown by [`crate::processor::memory::Memory::sbrk`]
+    pub fn default_map() -> Vec<Segment> {
+        vec![
+            Segment::new("text", 0x0040_0000, 0x0FFF_FFFF, Permissions::READ_EXECUTE),
+            Segment::new("data", 0x1000_0000, 0x1003_FFFF, Permissions::READ_WRITE),
+            Segment::new("heap", HEAP_BASE, HEAP_BASE - 1, Permissions::READ_WRITE),
+            Segment::new("stack", STACK_LIMIT, 0x7FFF_FFFF, Permissions::READ_WRITE),
+            Segment::new_kernel("ktext", 0x8000_0000, 0x8FFF_FFFF, Permissions::READ_EXECUTE),
+            Segment::new_kernel("kdata", 0x9000_0000, 0xFFFE_FFFF, Permissions::READ_WRITE)
+        ]
+    }
+
+    /// Physical memory of a bare-metal machine: `ram_size` bytes of RAM from
+    /// address zero, at least a page and stopping short of the boot ROM
+    pub fn bare_metal_map(ram_size: u32) -> Vec<Segment> {
+        let ram_size = ram_size.clamp(0x1000, ROM_BASE);
+        vec![
+            Segment::new("ram", 0, ram_size - 1, Permissions::ALL),
+            Segment::new("rom", ROM_BASE, ROM_BASE + ROM_SIZE - 1, Permissions::READ_EXECUTE)
+        ]
+    }
+}
+
+impl MemoryFault {
+    pub fn address(&self) -> u32 {
+        match self {
+            MemoryFault::Unmapped { address, .. } => *address,
+            MemoryFault::Permission { address, .. } => *address,
+            MemoryFault::Privileged { address, .. } => *address,
+            MemoryFault::TlbMiss { address, .. } => *address,
+            MemoryFault::TlbInvalid { address, .. } => *address,
+            MemoryFault::TlbModified { address } => *address
+        }
+    }
+
+    pub fn access(&self) -> Access {
+        match self {
+            MemoryFault::Unmapped { access, .. } => *access,
+            MemoryFault::Permission { access, .. } => *access,
+            MemoryFault::Privileged { access, .. } => *access,
+            MemoryFault::TlbMiss { access, .. } => *access,
+            MemoryFault::TlbInvalid { access, .. } => *access,
+            MemoryFault::TlbModified { .. } => Access::Write
+        }
+    }
+
+    /// Whether the TLB raised the fault
+    pub fn is_tlb(&self) -> bool {
+        matches!(
+            self,
+            MemoryFault::TlbMiss { .. } |
+                MemoryFault::TlbInvalid { .. } |
+                MemoryFault::TlbModified { .. }
+        )
+    }
+}
+
+impl Display for Permissions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            if self.read { "r" } else { "-" },
+            if self.write { "w" } else { "-" },
+            if self.execute { "x" } else { "-" }
+        )
+    }
+}
+
+impl Display for Segment {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} [{:#010x}-{:#010x}] {}{}",
+            self.name,
+            self.start,
+            self.end,
+            self.permissions,
+            if self.kernel { " kernel" } else { "" }
+        )
+    }
+}
+
+impl Display for MemoryFault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemoryFault::Unmapped { address, access } => {
+                write!(f, "{:?} of unmapped address {:#010x}", access, address)
+            }
+            MemoryFault::Permission {
+                address,
+                access,
+                segment
+            } => write!(
+                f,
+                "{:?} of address {:#010x} not permitted in {} segment",
+                access, address, segment
+            ),
+            MemoryFault::Privileged { address, access } => {
+                write!(f, "{:?} of kernel address {:#010x} in user mode", access, address)
+            }
+            MemoryFault::TlbMiss { address, access } => {
+                write!(f, "{:?} of address {:#010x} missed the TLB", access, address)
+            }
+            MemoryFault::TlbInvalid { address, access } => {
+                write!(f, "{:?} of address {:#010x} hit an invalid TLB entry", access, address)
+            }
+            MemoryFault::TlbModified { address } => {
+                write!(f, "Write of address {:#010x} hit a clean TLB entry", address)
+            }
+        }
+    }
+}
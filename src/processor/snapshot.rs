@@ -0,0 +1,608 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use log::info;
+use num_traits::FromPrimitive;
+use crate::processor::buffer::{EXMEMBuffer, IDEXBuffer, IFIDBuffer, MEMWBBuffer};
+use crate::processor::exception::{Exception, ExceptionCode, ExceptionHandler};
+use crate::processor::instruction::Instruction;
+use crate::processor::memory::PAGE_SIZE;
+use crate::processor::registers::Register;
+use crate::processor::segment::{Access, MemoryFault, Segment};
+use crate::processor::tlb::{Tlb, TlbEntry, TLB_ENTRIES};
+use crate::processor::{Processor, Status};
+
+/// Identifies a snapshot file
+const MAGIC: &[u8; 8] = b"MIPSSNAP";
+
+/// Snapshot layout, version 6. Every integer is little-endian.
+///
+/// ```text
+/// magic "MIPSSNAP", version u32
+/// pc u32, registers 32 x u32, hi u32, lo u32
+/// float registers 32 x u32, cp0 registers 32 x u32
+/// stall u8, cycles u64, status, program break u32
+/// exception handler u8, user mode u8
+/// TLB present u8, then its ASID u32 and 64 x EntryHi u32 and EntryLo u32
+/// bare metal u8, RAM size u32
+/// external interrupt lines u8, asserted interrupt lines u8
+/// device count u32, then per device its state length u32 and state
+/// IF/ID, ID/EX, EX/MEM and MEM/WB buffers
+/// page count u32, then per page its base address u32 and 4096 bytes
+/// ```
+///
+/// Instructions are stored as a present flag u8 and their encoded word. The
+/// status is a tag u8: 0 running, 1 exited with an i32 code, 5 exception
+/// followed by the code u8, EPC u32 and the bad address as a present flag u8
+/// and u32, or a fault followed by the address u32 and access u8. Faults are
+/// 2 unmapped, 3 permission, 4 privileged, 6 TLB miss, 7 invalid TLB entry
+/// and 8 TLB modified. The exception handler is 0 halt, 1 builtin and 2
+/// vector. The EX/MEM and MEM/WB buffers end with the address of their
+/// instruction.
+///
+/// Version 1 has no exception handler or user mode and only status tags 0 to
+/// 3. Restoring it keeps the processor's handler and takes the mode from
+/// Status. Versions 1 and 2 have no TLB, restoring them keeps the
+/// processor's. Versions before 4 predate bare-metal machines. Versions
+/// before 5 take the address of an instruction in EX/MEM or MEM/WB from its
+/// PC, which is wrong for a jump or taken branch. Versions before 6 have no
+/// interrupt lines or device state, restoring them keeps the processor's
+/// lines and is refused while devices are mapped.
+///
+/// A snapshot only restores onto a machine with the same memory map, bare
+/// metal with the same amount of RAM or hosted, and the same number of
+/// devices.
+///
+/// Bumped whenever the layout changes. Older versions stay readable.
+pub const SNAPSHOT_VERSION: u32 = 6;
+
+/// Why a snapshot could not be restored
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    /// The data does not start with the snapshot magic
+    NotASnapshot,
+    /// Written by a newer simulator than this one
+    UnsupportedVersion(u32),
+    /// A field holds a value no snapshot could contain
+    Corrupt(&'static str),
+    /// Taken on a machine with a different memory map
+    WrongMachine,
+    /// Predates device state while the processor has devices mapped
+    NoDeviceState
+}
+
+/// Everything a snapshot holds, read in full before any of it is applied so a
+/// bad file leaves the processor untouched
+struct MachineState {
+    program_counter: u32,
+    registers: [u32; 32],
+    hi: u32,
+    lo: u32,
+    float_registers: [u32; 32],
+    cp0: [u32; 32],
+    stall: bool,
+    cycles: u64,
+    status: Status,
+    program_break: u32,
+    /// Missing from version 1
+    exception_handler: Option<ExceptionHandler>,
+    user_mode: Option<bool>,
+    /// Missing before version 3, then whether there is a TLB and its state
+    tlb: Option<Option<Tlb>>,
+    bare_metal: bool,
+    /// Zero on a hosted machine
+    ram_size: u32,
+    /// Missing before version 6, then the external and asserted interrupt
+    /// lines and the state of every device
+    devices: Option<(u8, u8, Vec<Vec<u8>>)>,
+    if_id_buffer: IFIDBuffer,
+    id_ex_buffer: IDEXBuffer,
+    ex_mem_buffer: EXMEMBuffer,
+    mem_wb_buffer: MEMWBBuffer,
+    pages: Vec<(u32, Vec<u8>)>
+}
+
+impl Processor {
+    /// Writes the architectural and pipeline state. Symbols, watchpoints, open
+    /// files and the reverse execution history are not part of a snapshot.
+    pub fn save_snapshot<W: Write>(&self, writer: W) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(writer);
+        writer.write_all(MAGIC)?;
+        write_u32(&mut writer, SNAPSHOT_VERSION)?;
+        write_u32(&mut writer, self.program_counter.get())?;
+        for index in 0..32 {
+            write_u32(&mut writer, self.registers.get(Register::from_usize(index).unwrap()))?;
+        }
+        write_u32(&mut writer, self.alu.get_hi())?;
+        write_u32(&mut writer, self.alu.get_lo())?;
+        for index in 0..32 {
+            write_u32(&mut writer, self.float_registers.get(index))?;
+        }
+        for index in 0..32 {
+            write_u32(&mut writer, self.cp0.get(index))?;
+        }
+        writer.write_all(&[self.stall as u8])?;
+        writer.write_all(&self.cycles.to_le_bytes())?;
+        write_status(&mut writer, self.status)?;
+        write_u32(&mut writer, self.memory.get_program_break())?;
+        let handler = match self.exception_handler {
+            ExceptionHandler::Halt => 0,
+            ExceptionHandler::Builtin => 1,
+            ExceptionHandler::Vector => 2
+        };
+        writer.write_all(&[handler, self.memory.is_user_mode() as u8])?;
+        match self.memory.get_tlb() {
+            Some(tlb) => {
+                writer.write_all(&[1])?;
+                write_u32(&mut writer, tlb.get_asid())?;
+                for entry in tlb.get_entries() {
+                    write_u32(&mut writer, entry.entry_hi)?;
+                    write_u32(&mut writer, entry.entry_lo)?;
+                }
+            }
+            None => writer.write_all(&[0])?
+        }
+        writer.write_all(&[self.bare_metal as u8])?;
+        write_u32(&mut writer, self.ram_size())?;
+        writer.write_all(&[self.interrupts.get_external(), self.interrupts.get_asserted()])?;
+        let devices = self.memory.get_devices().save();
+        write_u32(&mut writer, devices.len() as u32)?;
+        for state in devices {
+            write_u32(&mut writer, state.len() as u32)?;
+            writer.write_all(&state)?;
+        }
+
+        write_instruction(&mut writer, self.if_id_buffer.instruction)?;
+        write_u32(&mut writer, self.if_id_buffer.pc)?;
+        write_instruction(&mut writer, self.id_ex_buffer.instruction)?;
+        write_u32(&mut writer, self.id_ex_buffer.data_1)?;
+        write_u32(&mut writer, self.id_ex_buffer.data_2)?;
+        write_u32(&mut writer, self.id_ex_buffer.sign_extended)?;
+        write_u32(&mut writer, self.id_ex_buffer.pc)?;
+        write_instruction(&mut writer, self.ex_mem_buffer.instruction)?;
+        write_u32(&mut writer, self.ex_mem_buffer.alu_result)?;
+        write_u32(&mut writer, self.ex_mem_buffer.data_2)?;
+        write_u32(&mut writer, self.ex_mem_buffer.pc)?;
+        write_u32(&mut writer, self.ex_mem_buffer.address)?;
+        write_instruction(&mut writer, self.mem_wb_buffer.instruction)?;
+        write_u32(&mut writer, self.mem_wb_buffer.data)?;
+        write_u32(&mut writer, self.mem_wb_buffer.pc)?;
+        write_u32(&mut writer, self.mem_wb_buffer.address)?;
+
+        // Pages that were written back to all zeroes read the same as missing ones
+        let pages: Vec<(u32, &[u8])> = self
+            .memory
+            .get_pages()
+            .filter(|(_, page)| page.iter().any(|byte| *byte != 0))
+            .collect();
+        write_u32(&mut writer, pages.len() as u32)?;
+        for (base, page) in pages {
+            write_u32(&mut writer, base)?;
+            writer.write_all(page)?;
+        }
+        writer.flush()
+    }
+
+    /// Replaces the machine state with a snapshot written by [`Processor::save_snapshot`]
+    pub fn restore_snapshot<R: Read>(&mut self, reader: R) -> Result<(), SnapshotError> {
+        let state = read_state(&mut BufReader::new(reader))?;
+        if state.bare_metal != self.bare_metal || state.ram_size != self.ram_size() {
+            return Err(SnapshotError::WrongMachine);
+        }
+        match &state.devices {
+            Some((_, _, devices)) if devices.len() != self.memory.get_devices().len() => {
+                return Err(SnapshotError::WrongMachine);
+            }
+            Some((external, asserted, devices)) => {
+                if !self.memory.get_devices_mut().restore(devices) {
+                    return Err(SnapshotError::Corrupt("device state"));
+                }
+                self.interrupts.set_lines(*external, *asserted);
+            }
+            None if !self.memory.get_devices().is_empty() => {
+                return Err(SnapshotError::NoDeviceState);
+            }
+            None => {}
+        }
+        self.program_counter.set(state.program_counter);
+        for (index, value) in state.registers.iter().enumerate().skip(1) {
+            self.registers.set(Register::from_usize(index).unwrap(), *value);
+        }
+        self.alu.set_hi(state.hi);
+        self.alu.set_lo(state.lo);
+        for (index, value) in state.float_registers.iter().enumerate() {
+            self.float_registers.set(index, *value);
+        }
+        for (index, value) in state.cp0.iter().enumerate() {
+            self.cp0.set(index, *value);
+        }
+        self.stall = state.stall;
+        self.cycles = state.cycles;
+        self.status = state.status;
+        self.last_commit = None;
+        // Cache misses in flight are not saved, the access is simply retried
+        self.fetch_wait = None;
+        self.memory_wait = None;
+        self.stall_cause = None;
+        self.if_id_buffer = state.if_id_buffer;
+        self.id_ex_buffer = state.id_ex_buffer;
+        self.ex_mem_buffer = state.ex_mem_buffer;
+        self.mem_wb_buffer = state.mem_wb_buffer;
+        self.memory.clear();
+        self.memory.set_program_break(state.program_break);
+        if let Some(handler) = state.exception_handler {
+            self.exception_handler = handler;
+        }
+        match state.user_mode {
+            Some(user_mode) => self.memory.set_user_mode(user_mode),
+            None => self.sync_mode()
+        }
+        match state.tlb {
+            Some(Some(tlb)) => self.memory.set_tlb(tlb),
+            Some(None) => self.memory.clear_tlb(),
+            None => {}
+        }
+        for (base, page) in state.pages {
+            for (offset, byte) in page.into_iter().enumerate() {
+                if byte != 0 {
+                    self.memory.write_byte(base + offset as u32, byte);
+                }
+            }
+        }
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
+        }
+        info!("Restored snapshot at cycle {}", self.cycles);
+        Ok(())
+    }
+
+    /// Bytes of RAM of a bare-metal machine, zero when hosted
+    fn ram_size(&self) -> u32 {
+        if !self.bare_metal {
+            return 0;
+        }
+        self.memory
+            .get_segments()
+            .iter()
+            .find(|segment| segment.name == "ram")
+            .map_or(0, |segment| segment.end - segment.start + 1)
+    }
+
+    pub fn save_snapshot_file<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        self.save_snapshot(File::create(path)?)
+    }
+
+    pub fn restore_snapshot_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), SnapshotError> {
+        self.restore_snapshot(File::open(path)?)
+    }
+}
+
+fn read_state<R: Read>(reader: &mut R) -> Result<MachineState, SnapshotError> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(SnapshotError::NotASnapshot);
+    }
+    let version = read_u32(reader)?;
+    if version == 0 || version > SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    let program_counter = read_u32(reader)?;
+    let registers = read_words(reader)?;
+    let hi = read_u32(reader)?;
+    let lo = read_u32(reader)?;
+    let float_registers = read_words(reader)?;
+    let cp0 = read_words(reader)?;
+    let stall = read_u8(reader)? != 0;
+    let mut cycles = [0; 8];
+    reader.read_exact(&mut cycles)?;
+    let cycles = u64::from_le_bytes(cycles);
+    let status = read_status(reader, version)?;
+    let program_break = read_u32(reader)?;
+    let (exception_handler, user_mode) = match version {
+        1 => (None, None),
+        _ => {
+            let handler = match read_u8(reader)? {
+                0 => ExceptionHandler::Halt,
+                1 => ExceptionHandler::Builtin,
+                2 => ExceptionHandler::Vector,
+                _ => return Err(SnapshotError::Corrupt("exception handler"))
+            };
+            let user_mode = match read_u8(reader)? {
+                0 => false,
+                1 => true,
+                _ => return Err(SnapshotError::Corrupt("user mode flag"))
+            };
+            (Some(handler), Some(user_mode))
+        }
+    };
+    let tlb = match version {
+        1 | 2 => None,
+        _ => Some(read_tlb(reader)?)
+    };
+    let (bare_metal, ram_size) = match version {
+        1..=3 => (false, 0),
+        _ => {
+            let bare_metal = match read_u8(reader)? {
+                0 => false,
+                1 => true,
+                _ => return Err(SnapshotError::Corrupt("bare metal flag"))
+            };
+            (bare_metal, read_u32(reader)?)
+        }
+    };
+    let devices = match version {
+        1..=5 => None,
+        _ => Some(read_devices(reader)?)
+    };
+
+    let if_id_buffer = IFIDBuffer {
+        instruction: read_instruction(reader)?,
+        pc: read_u32(reader)?
+    };
+    let id_ex_buffer = IDEXBuffer {
+        instruction: read_instruction(reader)?,
+        data_1: read_u32(reader)?,
+        data_2: read_u32(reader)?,
+        sign_extended: read_u32(reader)?,
+        pc: read_u32(reader)?
+    };
+    let mut ex_mem_buffer = EXMEMBuffer {
+        instruction: read_instruction(reader)?,
+        alu_result: read_u32(reader)?,
+        data_2: read_u32(reader)?,
+        pc: read_u32(reader)?,
+        address: 0
+    };
+    ex_mem_buffer.address = read_address(reader, version, ex_mem_buffer.pc)?;
+    let mut mem_wb_buffer = MEMWBBuffer {
+        instruction: read_instruction(reader)?,
+        data: read_u32(reader)?,
+        pc: read_u32(reader)?,
+        address: 0
+    };
+    mem_wb_buffer.address = read_address(reader, version, mem_wb_buffer.pc)?;
+
+    let page_count = read_u32(reader)?;
+    let mut pages = Vec::new();
+    for _ in 0..page_count {
+        let base = read_u32(reader)?;
+        if base % PAGE_SIZE != 0 {
+            return Err(SnapshotError::Corrupt("page base address"));
+        }
+        let mut page = vec![0; PAGE_SIZE as usize];
+        reader.read_exact(&mut page)?;
+        pages.push((base, page));
+    }
+    Ok(MachineState {
+        program_counter,
+        registers,
+        hi,
+        lo,
+        float_registers,
+        cp0,
+        stall,
+        cycles,
+        status,
+        program_break,
+        exception_handler,
+        user_mode,
+        tlb,
+        bare_metal,
+        ram_size,
+        devices,
+        if_id_buffer,
+        id_ex_buffer,
+        ex_mem_buffer,
+        mem_wb_buffer,
+        pages
+    })
+}
+
+/// Interrupt lines and device states, a state longer than a page is corrupt
+fn read_devices<R: Read>(reader: &mut R) -> Result<(u8, u8, Vec<Vec<u8>>), SnapshotError> {
+    let external = read_u8(reader)?;
+    let asserted = read_u8(reader)?;
+    let count = read_u32(reader)?;
+    let mut devices = Vec::new();
+    for _ in 0..count {
+        let length = read_u32(reader)?;
+        if length > PAGE_SIZE {
+            return Err(SnapshotError::Corrupt("device state length"));
+        }
+        let mut state = vec![0; length as usize];
+        reader.read_exact(&mut state)?;
+        devices.push(state);
+    }
+    Ok((external, asserted, devices))
+}
+
+/// The address of a buffered instruction, guessed from its PC before version 5
+fn read_address<R: Read>(reader: &mut R, version: u32, pc: u32) -> std::io::Result<u32> {
+    match version {
+        1..=4 => Ok(pc.wrapping_sub(4)),
+        _ => read_u32(reader)
+    }
+}
+
+fn read_tlb<R: Read>(reader: &mut R) -> Result<Option<Tlb>, SnapshotError> {
+    match read_u8(reader)? {
+        0 => return Ok(None),
+        1 => {}
+        _ => return Err(SnapshotError::Corrupt("TLB flag"))
+    }
+    let mut tlb = Tlb::new();
+    tlb.set_asid(read_u32(reader)?);
+    for index in 0..TLB_ENTRIES {
+        let entry_hi = read_u32(reader)?;
+        let entry_lo = read_u32(reader)?;
+        tlb.set(index, TlbEntry::new(entry_hi, entry_lo));
+    }
+    Ok(Some(tlb))
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> std::io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> std::io::Result<u8> {
+    let mut byte = [0; 1];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_words<R: Read>(reader: &mut R) -> std::io::Result<[u32; 32]> {
+    let mut words = [0; 32];
+    for word in words.iter_mut() {
+        *word = read_u32(reader)?;
+    }
+    Ok(words)
+}
+
+fn write_instruction<W: Write>(writer: &mut W, instruction: Option<Instruction>) -> std::io::Result<()> {
+    match instruction {
+        Some(instruction) => {
+            writer.write_all(&[1])?;
+            write_u32(writer, instruction.word)
+        }
+        None => {
+            writer.write_all(&[0])?;
+            write_u32(writer, 0)
+        }
+    }
+}
+
+fn read_instruction<R: Read>(reader: &mut R) -> Result<Option<Instruction>, SnapshotError> {
+    let present = read_u8(reader)?;
+    let word = read_u32(reader)?;
+    match present {
+        0 => Ok(None),
+        1 => Ok(Some(Instruction::load(word))),
+        _ => Err(SnapshotError::Corrupt("instruction flag"))
+    }
+}
+
+fn write_status<W: Write>(writer: &mut W, status: Status) -> std::io::Result<()> {
+    let (tag, fault) = match status {
+        // A watchpoint hit never outlives the cycle that reported it
+        Status::Running | Status::Watchpoint(_) => return writer.write_all(&[0]),
+        Status::Exited(code) => {
+            writer.write_all(&[1])?;
+            return writer.write_all(&code.to_le_bytes());
+        }
+        Status::Faulted(fault @ MemoryFault::Unmapped { .. }) => (2, fault),
+        Status::Faulted(fault @ MemoryFault::Permission { .. }) => (3, fault),
+        Status::Faulted(fault @ MemoryFault::Privileged { .. }) => (4, fault),
+        Status::Faulted(fault @ MemoryFault::TlbMiss { .. }) => (6, fault),
+        Status::Faulted(fault @ MemoryFault::TlbInvalid { .. }) => (7, fault),
+        Status::Faulted(fault @ MemoryFault::TlbModified { .. }) => (8, fault),
+        Status::Exception(exception) => {
+            writer.write_all(&[5, exception.code as u8])?;
+            write_u32(writer, exception.epc)?;
+            writer.write_all(&[exception.bad_address.is_some() as u8])?;
+            return write_u32(writer, exception.bad_address.unwrap_or(0));
+        }
+    };
+    let access = match fault.access() {
+        Access::Read => 0,
+        Access::Write => 1,
+        Access::Execute => 2
+    };
+    writer.write_all(&[tag])?;
+    write_u32(writer, fault.address())?;
+    writer.write_all(&[access])
+}
+
+fn read_status<R: Read>(reader: &mut R, version: u32) -> Result<Status, SnapshotError> {
+    let tag = read_u8(reader)?;
+    match tag {
+        0 => return Ok(Status::Running),
+        1 => return Ok(Status::Exited(read_u32(reader)? as i32)),
+        // Version 1 only had the first two faults
+        2 | 3 => {}
+        _ if version == 1 => return Err(SnapshotError::Corrupt("status")),
+        4 | 6..=8 => {}
+        5 => return read_exception(reader),
+        _ => return Err(SnapshotError::Corrupt("status"))
+    }
+    let address = read_u32(reader)?;
+    let access = match read_u8(reader)? {
+        0 => Access::Read,
+        1 => Access::Write,
+        2 => Access::Execute,
+        _ => return Err(SnapshotError::Corrupt("fault access"))
+    };
+    match tag {
+        2 => return Ok(Status::Faulted(MemoryFault::Unmapped { address, access })),
+        4 => return Ok(Status::Faulted(MemoryFault::Privileged { address, access })),
+        6 => return Ok(Status::Faulted(MemoryFault::TlbMiss { address, access })),
+        7 => return Ok(Status::Faulted(MemoryFault::TlbInvalid { address, access })),
+        8 => return Ok(Status::Faulted(MemoryFault::TlbModified { address })),
+        _ => {}
+    }
+    // Segment names are fixed by the default map, so look it up again
+    let segment = Segment::default_map()
+        .into_iter()
+        .find(|segment| segment.contains(address))
+        .map_or("unknown", |segment| segment.name);
+    Ok(Status::Faulted(MemoryFault::Permission {
+        address,
+        access,
+        segment
+    }))
+}
+
+fn read_exception<R: Read>(reader: &mut R) -> Result<Status, SnapshotError> {
+    let code = ExceptionCode::from_u8(read_u8(reader)?)
+        .ok_or(SnapshotError::Corrupt("exception code"))?;
+    let epc = read_u32(reader)?;
+    let present = read_u8(reader)?;
+    let address = read_u32(reader)?;
+    let bad_address = match present {
+        0 => None,
+        1 => Some(address),
+        _ => return Err(SnapshotError::Corrupt("bad address flag"))
+    };
+    Ok(Status::Exception(Exception {
+        code,
+        epc,
+        bad_address
+    }))
+}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(error: std::io::Error) -> Self {
+        SnapshotError::Io(error)
+    }
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Io(error) => write!(f, "{}", error),
+            SnapshotError::NotASnapshot => write!(f, "not a snapshot file"),
+            SnapshotError::UnsupportedVersion(version) => write!(
+                f,
+                "snapshot version {} is not supported, this build reads up to {}",
+                version, SNAPSHOT_VERSION
+            ),
+            SnapshotError::Corrupt(field) => write!(f, "corrupt snapshot: bad {}", field),
+            SnapshotError::WrongMachine => {
+                write!(f, "snapshot was taken on a machine with a different memory map")
+            }
+            SnapshotError::NoDeviceState => {
+                write!(f, "snapshot predates device state and this machine has devices")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
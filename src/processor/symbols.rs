@@ -0,0 +1,49 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Labels known for the loaded program, looked up by name or by address
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    by_name: HashMap<String, u32>,
+    by_address: BTreeMap<u32, String>
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a label, replacing whatever `name` or `address` had before. An
+    /// address holds a single label, the last one added.
+    pub fn insert(&mut self, name: &str, address: u32) {
+        if let Some(previous) = self.by_name.insert(name.to_string(), address) {
+            self.by_address.remove(&previous);
+        }
+        if let Some(replaced) = self.by_address.insert(address, name.to_string()) {
+            if replaced != name {
+                self.by_name.remove(&replaced);
+            }
+        }
+    }
+
+    pub fn address(&self, name: &str) -> Option<u32> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn name(&self, address: u32) -> Option<&str> {
+        self.by_address.get(&address).map(String::as_str)
+    }
+
+    /// The closest label at or below `address` with the offset from it
+    pub fn nearest(&self, address: u32) -> Option<(&str, u32)> {
+        self.by_address
+            .range(..=address)
+            .next_back()
+            .map(|(base, name)| (name.as_str(), address - base))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.by_address
+            .iter()
+            .map(|(address, name)| (*address, name.as_str()))
+    }
+}
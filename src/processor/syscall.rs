@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::str::FromStr;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{debug, warn};
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use crate::processor::alu::FunctionCode;
+use crate::processor::instruction::InstructionType;
+use crate::processor::memory::Size;
+use crate::processor::registers::Register;
+use crate::processor::segment::MemoryFault;
+use crate::processor::watchpoint::{WatchKind, WatchTarget, WatchpointHit};
+use crate::processor::{Processor, Status};
+
+/// Syscall numbers as used by SPIM and MARS, passed in `$v0`
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, FromPrimitive, PartialEq)]
+pub enum SyscallCode {
+    PrintInt = 1,
+    PrintFloat = 2,
+    PrintDouble = 3,
+    PrintString = 4,
+    ReadInt = 5,
+    ReadFloat = 6,
+    ReadDouble = 7,
+    ReadString = 8,
+    Sbrk = 9,
+    Exit = 10,
+    PrintChar = 11,
+    ReadChar = 12,
+    Open = 13,
+    Read = 14,
+    Write = 15,
+    Close = 16,
+    Exit2 = 17,
+    Time = 30,
+    Sleep = 32,
+    PrintHex = 34,
+    PrintBinary = 35,
+    PrintUnsigned = 36,
+    SetSeed = 40,
+    RandomInt = 41,
+    RandomIntRange = 42,
+    RandomFloat = 43,
+    RandomDouble = 44
+}
+
+/// MARS open flags
+const OPEN_READ: u32 = 0;
+const OPEN_WRITE: u32 = 1;
+const OPEN_APPEND: u32 = 9;
+/// Descriptors 0 to 2 are the host's standard streams
+const FIRST_FILE_DESCRIPTOR: u32 = 3;
+/// Most bytes one read or write syscall moves, longer ones come up short
+const MAX_TRANSFER: u32 = 0x10_0000;
+
+/// Host side state kept between syscalls
+pub struct SyscallState {
+    sandbox: PathBuf,
+    files: HashMap<u32, File>,
+    next_descriptor: u32,
+    generators: HashMap<u32, StdRng>
+}
+
+impl SyscallState {
+    pub fn new() -> Self {
+        Self {
+            sandbox: PathBuf::from("."),
+            files: HashMap::new(),
+            next_descriptor: FIRST_FILE_DESCRIPTOR,
+            generators: HashMap::new()
+        }
+    }
+
+    /// Resolves a program supplied path inside the sandbox directory. Absolute
+    /// paths, paths climbing out with `..` and symlinks leading out of the
+    /// sandbox are rejected.
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let path = Path::new(path);
+        let escapes = path
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+        if escapes || path.as_os_str().is_empty() {
+            return None;
+        }
+        let sandbox = self.sandbox.canonicalize().ok()?;
+        let joined = sandbox.join(path);
+        let resolved = match joined.canonicalize() {
+            Ok(resolved) => resolved,
+            // A file about to be created, unless it is a dangling symlink
+            Err(_) if joined.symlink_metadata().is_err() => {
+                let parent = joined.parent()?.canonicalize().ok()?;
+                parent.join(joined.file_name()?)
+            }
+            Err(_) => return None
+        };
+        resolved.starts_with(&sandbox).then_some(resolved)
+    }
+
+    fn open(&mut self, path: &str, flags: u32) -> Option<u32> {
+        let path = self.resolve(path)?;
+        let mut options = OpenOptions::new();
+        match flags {
+            OPEN_READ => options.read(true),
+            OPEN_WRITE => options.write(true).create(true).truncate(true),
+            OPEN_APPEND => options.append(true).create(true),
+            _ => return None
+        };
+        let file = options.open(&path).ok()?;
+        let descriptor = self.next_descriptor;
+        self.next_descriptor += 1;
+        debug!("Opened {:?} as descriptor {}", path, descriptor);
+        self.files.insert(descriptor, file);
+        Some(descriptor)
+    }
+
+    fn generator(&mut self, id: u32) -> &mut StdRng {
+        self.generators
+            .entry(id)
+            .or_insert_with(StdRng::from_os_rng)
+    }
+}
+
+impl Default for SyscallState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Processor {
+    /// Sets the directory the file syscalls are confined to
+    pub fn set_sandbox<P: Into<PathBuf>>(&mut self, path: P) {
+        self.syscalls.sandbox = path.into();
+    }
+
+    /// Whether decode is about to run a syscall that reads program input from
+    /// the console, so front ends feeding the console can wait for the user
+    pub fn wants_input(&self) -> bool {
+        let syscall = self.if_id_buffer.instruction.is_some_and(|instruction| {
+            instruction.instruction_type == InstructionType::R &&
+                instruction.funct == Some(FunctionCode::Syscall as u8)
+        });
+        if !syscall || self.bare_metal {
+            return false;
+        }
+        match SyscallCode::from_u32(self.decoded_register(Register::V0)) {
+            Some(
+                SyscallCode::ReadInt |
+                SyscallCode::ReadFloat |
+                SyscallCode::ReadDouble |
+                SyscallCode::ReadString |
+                SyscallCode::ReadChar
+            ) => true,
+            Some(SyscallCode::Read) => self.decoded_register(Register::A0) == 0,
+            _ => false
+        }
+    }
+
+    /// Value decode sees for `register` next cycle, after writeback
+    fn decoded_register(&self, register: Register) -> u32 {
+        let written = self
+            .mem_wb_buffer
+            .instruction
+            .and_then(|instruction| instruction.destination());
+        if written == Some(register as u8) {
+            self.mem_wb_buffer.data
+        } else {
+            self.registers.get(register)
+        }
+    }
+
+    pub(crate) fn syscall(&mut self) {
+        let syscall_code = self.registers.get(Register::V0);
+        debug!("Syscall code: {:#x}", syscall_code);
+        let code = match SyscallCode::from_u32(syscall_code) {
+            Some(code) => code,
+            None => {
+                warn!("Unknown syscall code {}", syscall_code);
+                return;
+            }
+        };
+        let a0 = self.registers.get(Register::A0);
+        let a1 = self.registers.get(Register::A1);
+        let a2 = self.registers.get(Register::A2);
+        match code {
+            SyscallCode::PrintInt => self.console.write(&(a0 as i32).to_string()),
+            SyscallCode::PrintFloat => {
+                let value = self.float_registers.get_single(12);
+                self.console.write(&value.to_string())
+            }
+            SyscallCode::PrintDouble => {
+                let value = self.float_registers.get_double(12);
+                self.console.write(&value.to_string())
+            }
+            SyscallCode::PrintString => {
+                let text = self.memory.peek_cstring(a0);
+                self.console.write(&text)
+            }
+            SyscallCode::ReadInt => {
+                let num: i32 = self.read_number();
+                self.registers.set(Register::V0, num as u32);
+            }
+            SyscallCode::ReadFloat => {
+                let num: f32 = self.read_number();
+                self.float_registers.set_single(0, num);
+            }
+            SyscallCode::ReadDouble => {
+                let num: f64 = self.read_number();
+                self.float_registers.set_double(0, num);
+            }
+            SyscallCode::ReadString => {
+                let line = self.console.read_line().unwrap_or_default();
+                if let Err(fault) = self.read_string(a0, a1, &line) {
+                    self.syscall_exception(fault);
+                }
+            }
+            SyscallCode::Sbrk => {
+                let increment = a0 as i32;
+                let stack_pointer = self.registers.get(Register::Sp);
+                let address = match self.memory.sbrk_below(increment, stack_pointer) {
+                    Ok(address) => address,
+                    Err(error) => {
+                        // Like Unix sbrk, -1 signals that the heap could not grow
+                        warn!("sbrk of {} bytes failed: {:?}", increment, error);
+                        u32::MAX
+                    }
+                };
+                self.registers.set(Register::V0, address);
+            }
+            SyscallCode::Exit => self.status = Status::Exited(0),
+            SyscallCode::PrintChar => self.console.write(&(a0 as u8 as char).to_string()),
+            SyscallCode::ReadChar => {
+                let character = self.console.read_char().unwrap_or('\0');
+                self.registers.set(Register::V0, character as u32);
+            }
+            SyscallCode::Open => {
+                let path = self.memory.peek_cstring(a0);
+                let descriptor = match self.syscalls.open(&path, a1) {
+                    Some(descriptor) => descriptor,
+                    None => {
+                        warn!("Failed to open {:?} with flags {}", path, a1);
+                        u32::MAX
+                    }
+                };
+                self.registers.set(Register::V0, descriptor);
+            }
+            SyscallCode::Read => {
+                let mut buffer = vec![0; self.transfer_length(a1, a2)];
+                let count = match a0 {
+                    0 => {
+                        let mut line = self.console.read_line().unwrap_or_default();
+                        line.push('\n');
+                        let bytes = line.as_bytes();
+                        let count = bytes.len().min(buffer.len());
+                        buffer[..count].copy_from_slice(&bytes[..count]);
+                        Some(count)
+                    }
+                    _ => match self.syscalls.files.get_mut(&a0) {
+                        Some(file) => file.read(&mut buffer).ok(),
+                        None => None
+                    }
+                };
+                let result = match count {
+                    Some(count) => {
+                        if let Err(fault) = self.write_buffer(a1, &buffer[..count]) {
+                            self.syscall_exception(fault);
+                            return;
+                        }
+                        count as u32
+                    }
+                    None => u32::MAX
+                };
+                self.registers.set(Register::V0, result);
+            }
+            SyscallCode::Write => {
+                let buffer = match self.read_buffer(a1, self.transfer_length(a1, a2)) {
+                    Ok(buffer) => buffer,
+                    Err(fault) => {
+                        self.syscall_exception(fault);
+                        return;
+                    }
+                };
+                let written = match a0 {
+                    1 => {
+                        self.console.write(&String::from_utf8_lossy(&buffer));
+                        Some(buffer.len())
+                    }
+                    2 => {
+                        self.console.write_error(&String::from_utf8_lossy(&buffer));
+                        Some(buffer.len())
+                    }
+                    _ => match self.syscalls.files.get_mut(&a0) {
+                        Some(file) => write_flush(file, &buffer),
+                        None => None
+                    }
+                };
+                self.registers
+                    .set(Register::V0, written.map_or(u32::MAX, |count| count as u32));
+            }
+            SyscallCode::Close => {
+                if self.syscalls.files.remove(&a0).is_none() {
+                    warn!("Closing unknown file descriptor {}", a0);
+                }
+            }
+            SyscallCode::Exit2 => self.status = Status::Exited(a0 as i32),
+            SyscallCode::Time => {
+                let millis = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_millis() as u64);
+                self.registers.set(Register::A0, millis as u32);
+                self.registers.set(Register::A1, (millis >> 32) as u32);
+            }
+            SyscallCode::Sleep => std::thread::sleep(Duration::from_millis(a0 as u64)),
+            SyscallCode::PrintHex => self.console.write(&format!("{:#010x}", a0)),
+            SyscallCode::PrintBinary => self.console.write(&format!("{:032b}", a0)),
+            SyscallCode::PrintUnsigned => self.console.write(&a0.to_string()),
+            SyscallCode::SetSeed => {
+                self.syscalls
+                    .generators
+                    .insert(a0, StdRng::seed_from_u64(a1 as u64));
+            }
+            SyscallCode::RandomInt => {
+                let value: u32 = self.syscalls.generator(a0).random();
+                self.registers.set(Register::A0, value);
+            }
+            SyscallCode::RandomIntRange => {
+                if (a1 as i32) <= 0 {
+                    warn!("Random range upper bound {} is not positive", a1 as i32);
+                    return;
+                }
+                let value = self.syscalls.generator(a0).random_range(0..a1);
+                self.registers.set(Register::A0, value);
+            }
+            SyscallCode::RandomFloat => {
+                let value: f32 = self.syscalls.generator(a0).random();
+                self.float_registers.set_single(0, value);
+            }
+            SyscallCode::RandomDouble => {
+                let value: f64 = self.syscalls.generator(a0).random();
+                self.float_registers.set_double(0, value);
+            }
+        }
+    }
+
+    /// Bytes a read or write of `length` bytes at `address` actually moves,
+    /// no more than are left in the segment holding the buffer. A buffer
+    /// outside every segment keeps its length and faults on the first byte.
+    fn transfer_length(&self, address: u32, length: u32) -> usize {
+        let left = self
+            .memory
+            .segment(address)
+            .map_or(length, |segment| segment.end.wrapping_sub(address).saturating_add(1));
+        length.min(left).min(MAX_TRANSFER) as usize
+    }
+
+    /// Reads a line and parses it, falling back to zero on bad input like SPIM
+    fn read_number<T: FromStr + Default>(&mut self) -> T {
+        let line = self.console.read_line().unwrap_or_default();
+        match line.trim().parse() {
+            Ok(value) => value,
+            Err(_) => {
+                warn!("Could not parse {:?} as a number", line);
+                T::default()
+            }
+        }
+    }
+
+    /// Copies at most `length - 1` bytes of the line followed by the newline
+    /// when it fits and a null terminator, like fgets
+    fn read_string(&mut self, address: u32, length: u32, line: &str) -> Result<(), MemoryFault> {
+        if length == 0 {
+            return Ok(());
+        }
+        let mut bytes = line.as_bytes().to_vec();
+        bytes.push(b'\n');
+        bytes.truncate(length as usize - 1);
+        bytes.push(0);
+        self.write_buffer(address, &bytes)
+    }
+
+    /// Stores `bytes` into the program's buffer at `address` the way `sb`
+    /// would, stopping at the first byte that faults
+    fn write_buffer(&mut self, address: u32, bytes: &[u8]) -> Result<(), MemoryFault> {
+        for (i, byte) in bytes.iter().enumerate() {
+            let address = address.wrapping_add(i as u32);
+            self.memory.store(address, Size::Byte, *byte as u64)?;
+            self.watch_buffer(address, WatchKind::Write, *byte);
+        }
+        Ok(())
+    }
+
+    /// Loads `length` bytes of the program's buffer at `address` the way
+    /// `lbu` would, stopping at the first byte that faults
+    fn read_buffer(&mut self, address: u32, length: usize) -> Result<Vec<u8>, MemoryFault> {
+        let mut bytes = Vec::with_capacity(length);
+        for i in 0..length as u32 {
+            let address = address.wrapping_add(i);
+            let byte = self.memory.load(address, Size::Byte)? as u8;
+            self.watch_buffer(address, WatchKind::Read, byte);
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+
+    /// Reports a syscall touching a watched byte as the syscall's own access
+    fn watch_buffer(&mut self, address: u32, access: WatchKind, value: u8) {
+        let instruction = match self.id_ex_buffer.instruction {
+            Some(instruction) => instruction,
+            None => return
+        };
+        if self.memory.is_watched(address, 1, access) {
+            self.memory.record_watchpoint_hit(WatchpointHit {
+                // Buffers carry the already incremented PC
+                pc: self.id_ex_buffer.pc.wrapping_sub(4),
+                instruction,
+                target: WatchTarget::Memory(address),
+                access,
+                value: value as u32
+            });
+        }
+    }
+}
+
+fn write_flush<W: Write>(writer: &mut W, buffer: &[u8]) -> Option<usize> {
+    writer.write_all(buffer).ok()?;
+    writer.flush().ok()?;
+    Some(buffer.len())
+}
@@ -0,0 +1,236 @@
+use std::fmt::{Display, Formatter};
+use crate::processor::cp0::{Cop0Operation, ENTRY_HI, ENTRY_LO, INDEX, INDEX_ENTRY, INDEX_P, RANDOM};
+use crate::processor::exception::{Exception, ExceptionCode};
+use crate::processor::segment::{Access, MemoryFault};
+use crate::processor::Processor;
+
+/// Number of entries in the R3000 TLB
+pub const TLB_ENTRIES: usize = 64;
+/// Entries below this index are never picked by `tlbwr`, the R3000 keeps
+/// them for mappings the kernel wants to stay put
+pub const TLB_WIRED: usize = 8;
+/// Size of a mapped page
+pub const TLB_PAGE_SIZE: u32 = 0x1000;
+
+/// Start of kseg0, the cached unmapped kernel segment
+pub const KSEG0: u32 = 0x8000_0000;
+/// Start of kseg1, the uncached unmapped kernel segment
+pub const KSEG1: u32 = 0xA000_0000;
+/// Start of kseg2, the mapped kernel segment
+pub const KSEG2: u32 = 0xC000_0000;
+
+/// EntryHi virtual page number
+pub const ENTRY_HI_VPN: u32 = 0xFFFF_F000;
+/// EntryHi address space identifier
+pub const ENTRY_HI_ASID: u32 = 0x0000_0FC0;
+/// EntryLo physical frame number
+pub const ENTRY_LO_PFN: u32 = 0xFFFF_F000;
+/// EntryLo bit that bypasses the cache
+pub const ENTRY_LO_N: u32 = 0x800;
+/// EntryLo bit that allows stores to the page
+pub const ENTRY_LO_D: u32 = 0x400;
+/// EntryLo bit that marks the entry as usable
+pub const ENTRY_LO_V: u32 = 0x200;
+/// EntryLo bit that matches the entry under every ASID
+pub const ENTRY_LO_G: u32 = 0x100;
+
+/// One TLB entry, stored in the EntryHi and EntryLo layout `tlbr` and
+/// `tlbwi` move it in
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TlbEntry {
+    pub entry_hi: u32,
+    pub entry_lo: u32
+}
+
+/// An R3000 style software managed TLB. It maps kuseg and kseg2 a page at a
+/// time for the current ASID, kseg0 and kseg1 always map straight onto the
+/// first 512 MiB of physical memory. Misses are left to the program's
+/// refill handler.
+#[derive(Clone, Debug)]
+pub struct Tlb {
+    entries: [TlbEntry; TLB_ENTRIES],
+    /// ASID of the running program, EntryHi's ASID field
+    asid: u32
+}
+
+impl TlbEntry {
+    pub fn new(entry_hi: u32, entry_lo: u32) -> Self {
+        Self {
+            entry_hi: entry_hi & (ENTRY_HI_VPN | ENTRY_HI_ASID),
+            entry_lo: entry_lo & (ENTRY_LO_PFN | ENTRY_LO_N | ENTRY_LO_D | ENTRY_LO_V | ENTRY_LO_G)
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.entry_lo & ENTRY_LO_V != 0
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.entry_lo & ENTRY_LO_D != 0
+    }
+
+    pub fn is_global(&self) -> bool {
+        self.entry_lo & ENTRY_LO_G != 0
+    }
+
+    /// Whether the entry maps the page holding `address` under `asid`
+    pub fn matches(&self, address: u32, asid: u32) -> bool {
+        self.entry_hi & ENTRY_HI_VPN == address & ENTRY_HI_VPN &&
+            (self.is_global() || self.entry_hi & ENTRY_HI_ASID == asid & ENTRY_HI_ASID)
+    }
+}
+
+impl Tlb {
+    pub fn new() -> Self {
+        Self {
+            entries: [TlbEntry::default(); TLB_ENTRIES],
+            asid: 0
+        }
+    }
+
+    pub fn get(&self, index: usize) -> TlbEntry {
+        self.entries[index % TLB_ENTRIES]
+    }
+
+    pub fn set(&mut self, index: usize, entry: TlbEntry) {
+        self.entries[index % TLB_ENTRIES] = entry;
+    }
+
+    pub fn get_entries(&self) -> &[TlbEntry] {
+        &self.entries
+    }
+
+    pub fn get_asid(&self) -> u32 {
+        self.asid
+    }
+
+    /// Sets the ASID lookups match against, in EntryHi's layout
+    pub fn set_asid(&mut self, asid: u32) {
+        self.asid = asid & ENTRY_HI_ASID;
+    }
+
+    /// Index of the entry matching EntryHi's page and ASID, what `tlbp` looks for
+    pub fn probe(&self, entry_hi: u32) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.matches(entry_hi, entry_hi))
+    }
+
+    /// Whether accesses to `address` may go through the caches: kseg1 and
+    /// pages whose entry has the N bit set bypass them
+    pub fn is_cached(&self, address: u32) -> bool {
+        if (KSEG0..KSEG1).contains(&address) {
+            return true;
+        }
+        if (KSEG1..KSEG2).contains(&address) {
+            return false;
+        }
+        self.entries
+            .iter()
+            .find(|entry| entry.matches(address, self.asid))
+            .is_none_or(|entry| entry.entry_lo & ENTRY_LO_N == 0)
+    }
+
+    /// Physical address of a pipeline access to `address`. Kernel segments
+    /// are an address error in user mode.
+    pub fn translate(
+        &self,
+        address: u32,
+        access: Access,
+        user_mode: bool
+    ) -> Result<u32, MemoryFault> {
+        if user_mode && address >= KSEG0 {
+            return Err(MemoryFault::Privileged { address, access });
+        }
+        if (KSEG0..KSEG2).contains(&address) {
+            return Ok(address & 0x1FFF_FFFF);
+        }
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.matches(address, self.asid))
+            .ok_or(MemoryFault::TlbMiss { address, access })?;
+        if !entry.is_valid() {
+            return Err(MemoryFault::TlbInvalid { address, access });
+        }
+        if access == Access::Write && !entry.is_dirty() {
+            return Err(MemoryFault::TlbModified { address });
+        }
+        Ok(entry.entry_lo & ENTRY_LO_PFN | address & (TLB_PAGE_SIZE - 1))
+    }
+}
+
+impl Processor {
+    /// Carries out a TLB instruction, without a TLB they are reserved
+    pub(crate) fn execute_tlb(&mut self, operation: Cop0Operation) {
+        let tlb = match self.memory.get_tlb_mut() {
+            Some(tlb) => tlb,
+            None => {
+                // Buffers carry the already incremented PC
+                let epc = self.id_ex_buffer.pc.wrapping_sub(4);
+                self.instruction_exception(Exception::new(ExceptionCode::Ri, epc));
+                return;
+            }
+        };
+        let entry = TlbEntry::new(self.cp0.get(ENTRY_HI), self.cp0.get(ENTRY_LO));
+        let index = ((self.cp0.get(INDEX) & INDEX_ENTRY) >> 8) as usize;
+        match operation {
+            Cop0Operation::Tlbr => {
+                let entry = tlb.get(index);
+                self.cp0.set(ENTRY_HI, entry.entry_hi);
+                self.cp0.set(ENTRY_LO, entry.entry_lo);
+            }
+            Cop0Operation::Tlbwi => tlb.set(index, entry),
+            Cop0Operation::Tlbwr => {
+                let random = ((self.cp0.get(RANDOM) & INDEX_ENTRY) >> 8) as usize;
+                tlb.set(random, entry);
+            }
+            Cop0Operation::Tlbp => {
+                let index = match tlb.probe(entry.entry_hi) {
+                    Some(found) => (found as u32) << 8,
+                    None => INDEX_P | self.cp0.get(INDEX) & INDEX_ENTRY
+                };
+                self.cp0.set(INDEX, index);
+            }
+            Cop0Operation::Mfc0 | Cop0Operation::Mtc0 | Cop0Operation::Eret => {}
+        }
+    }
+}
+
+impl Default for Tlb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display for TlbEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:#010x} -> {:#010x} asid {:2} {}{}{}{}",
+            self.entry_hi & ENTRY_HI_VPN,
+            self.entry_lo & ENTRY_LO_PFN,
+            (self.entry_hi & ENTRY_HI_ASID) >> 6,
+            if self.entry_lo & ENTRY_LO_N != 0 { "N" } else { "-" },
+            if self.is_dirty() { "D" } else { "-" },
+            if self.is_valid() { "V" } else { "-" },
+            if self.is_global() { "G" } else { "-" }
+        )
+    }
+}
+
+impl Display for Tlb {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut empty = true;
+        for (index, entry) in self.entries.iter().enumerate() {
+            if entry.is_valid() {
+                writeln!(f, "{:2}: {}", index, entry)?;
+                empty = false;
+            }
+        }
+        if empty {
+            writeln!(f, "No valid entries")?;
+        }
+        Ok(())
+    }
+}
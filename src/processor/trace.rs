@@ -0,0 +1,211 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use log::warn;
+use crate::processor::buffer::{EXMEMBuffer, IDEXBuffer, IFIDBuffer};
+use crate::processor::history::CycleWrites;
+use crate::processor::instruction::Instruction;
+use crate::processor::{Processor, Status};
+
+/// Pipeline buffers as they were when a cycle started, which tells which
+/// instruction each stage worked on
+pub(crate) struct CycleStart {
+    program_counter: u32,
+    if_id_buffer: IFIDBuffer,
+    id_ex_buffer: IDEXBuffer,
+    ex_mem_buffer: EXMEMBuffer
+}
+
+impl Processor {
+    /// Writes one JSON object per cycle to `writer`, one per line:
+    ///
+    /// ```text
+    /// {"cycle":5,"pc":4194324,
+    ///  "stages":{"if":{..},"id":{..},"ex":{..},"mem":{..},"wb":{..}},
+    ///  "stall":false,"stall_cause":null,"flush":false,
+    ///  "registers":[{"register":"t0","value":85}],
+    ///  "memory":[{"address":268501000,"size":4,"value":85}],
+    ///  "status":"running"}
+    /// ```
+    ///
+    /// `pc` is where the cycle fetched from. Each stage is `null` when it held
+    /// a bubble, otherwise `{"pc":..,"word":..,"instruction":"..."}` with the
+    /// address of the instruction. Writes list the values stored this cycle
+    /// in the order they happened, float registers are named `f0` to `f31`.
+    /// `stall_cause` is `"data_hazard"`, `"control"`, `"instruction_cache"`
+    /// or `"data_cache"` when the cycle inserted a bubble for that reason.
+    /// A stopped processor adds `"exit_code"`, `"fault"` or `"exception"` next
+    /// to its status, the last two describing what went wrong as a string.
+    pub fn set_trace_writer(&mut self, writer: Box<dyn Write>) {
+        self.trace = Some(writer);
+    }
+
+    pub fn set_trace_file<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        self.trace = Some(Box::new(BufWriter::new(File::create(path)?)));
+        Ok(())
+    }
+
+    /// Stops tracing, flushing whatever is still buffered
+    pub fn clear_trace(&mut self) {
+        if let Some(mut trace) = self.trace.take() {
+            let _ = trace.flush();
+        }
+    }
+
+    pub(crate) fn begin_trace(&self) -> Option<CycleStart> {
+        self.trace.as_ref()?;
+        Some(CycleStart {
+            program_counter: self.program_counter.get(),
+            if_id_buffer: self.if_id_buffer,
+            id_ex_buffer: self.id_ex_buffer,
+            ex_mem_buffer: self.ex_mem_buffer
+        })
+    }
+
+    pub(crate) fn end_trace(&mut self, start: CycleStart, writes: &CycleWrites) {
+        let record = self.trace_record(&start, writes);
+        if let Some(trace) = self.trace.as_mut() {
+            if let Err(error) = writeln!(trace, "{}", record) {
+                warn!("Stopping the trace after a write failed: {}", error);
+                self.trace = None;
+            }
+        }
+    }
+
+    /// Formats a cycle as a single line of JSON
+    fn trace_record(&self, start: &CycleStart, writes: &CycleWrites) -> String {
+        let stages = [
+            ("if", stage(self.if_id_buffer.instruction, self.if_id_buffer.pc)),
+            ("id", stage(start.if_id_buffer.instruction, start.if_id_buffer.pc)),
+            ("ex", stage(start.id_ex_buffer.instruction, start.id_ex_buffer.pc)),
+            (
+                "mem",
+                start
+                    .ex_mem_buffer
+                    .instruction
+                    .map(|instruction| (start.ex_mem_buffer.address, instruction))
+            ),
+            (
+                "wb",
+                self.last_commit
+                    .map(|commit| (commit.address, commit.instruction))
+            )
+        ];
+        let stages: Vec<String> = stages
+            .iter()
+            .map(|(name, stage)| match stage {
+                Some((pc, instruction)) => format!(
+                    "\"{}\":{{\"pc\":{},\"word\":{},\"instruction\":{}}}",
+                    name, pc, instruction.word,
+                    json_string(&instruction.disassemble())
+                ),
+                None => format!("\"{}\":null", name)
+            })
+            .collect();
+
+        let mut registers: Vec<String> = Vec::new();
+        let mut seen = Vec::new();
+        for (register, _) in &writes.registers {
+            if !seen.contains(register) {
+                seen.push(*register);
+                registers.push(register_write(register.name(), self.registers.get(*register)));
+            }
+        }
+        let mut seen = Vec::new();
+        for (index, _) in &writes.float_registers {
+            if !seen.contains(index) {
+                seen.push(*index);
+                registers.push(register_write(
+                    &format!("f{}", index),
+                    self.float_registers.get(*index)
+                ));
+            }
+        }
+
+        let memory: Vec<String> = memory_runs(&writes.memory)
+            .iter()
+            .map(|(address, size)| {
+                let mut value: u64 = 0;
+                for i in (0..*size).rev() {
+                    value = (value << 8) | self.memory.read_byte(address.wrapping_add(i)) as u64;
+                }
+                format!(
+                    "{{\"address\":{},\"size\":{},\"value\":{}}}",
+                    address, size, value
+                )
+            })
+            .collect();
+
+        let status = match self.status {
+            Status::Running | Status::Watchpoint(_) => "\"running\"".to_string(),
+            Status::Exited(code) => format!("\"exited\",\"exit_code\":{}", code),
+            Status::Faulted(fault) => {
+                format!("\"faulted\",\"fault\":{}", json_string(&fault.to_string()))
+            }
+            Status::Exception(exception) => {
+                format!("\"exception\",\"exception\":{}", json_string(&exception.to_string()))
+            }
+        };
+        format!(
+            "{{\"cycle\":{},\"pc\":{},\"stages\":{{{}}},\"stall\":{},\"stall_cause\":{},\
+             \"flush\":{},\"registers\":[{}],\"memory\":[{}],\"status\":{}}}",
+            self.cycles,
+            start.program_counter,
+            stages.join(","),
+            self.stall,
+            self.stall_cause
+                .map_or("null".to_string(), |cause| json_string(cause.name())),
+            self.flush,
+            registers.join(","),
+            memory.join(","),
+            status
+        )
+    }
+}
+
+fn stage(instruction: Option<Instruction>, pc: u32) -> Option<(u32, Instruction)> {
+    // Buffers carry the already incremented PC
+    instruction.map(|instruction| (pc.wrapping_sub(4), instruction))
+}
+
+fn register_write(name: &str, value: u32) -> String {
+    format!("{{\"register\":\"{}\",\"value\":{}}}", name, value)
+}
+
+/// Groups the bytes written in a cycle into runs of consecutive addresses, so
+/// a word store shows up as one four byte write
+pub(crate) fn memory_runs(writes: &[(u32, u8)]) -> Vec<(u32, u32)> {
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for (address, _) in writes {
+        let extends = runs.last().is_some_and(|(start, size)| {
+            start.wrapping_add(*size) == *address && *size < 8
+        });
+        let repeated = runs.iter().any(|(start, size)| {
+            address.wrapping_sub(*start) < *size
+        });
+        if extends {
+            runs.last_mut().unwrap().1 += 1;
+        } else if !repeated {
+            runs.push((*address, 1));
+        }
+    }
+    runs
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for character in text.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            character if (character as u32) < 0x20 => {
+                escaped.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            character => escaped.push(character)
+        }
+    }
+    escaped.push('"');
+    escaped
+}
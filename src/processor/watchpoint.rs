@@ -0,0 +1,96 @@
+use std::fmt::{Display, Formatter};
+use crate::processor::instruction::Instruction;
+use crate::processor::registers::Register;
+
+/// Which accesses trigger a watchpoint
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access
+}
+
+/// A watched memory range
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryWatchpoint {
+    pub start: u32,
+    pub length: u32,
+    pub kind: WatchKind
+}
+
+/// A watched register
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RegisterWatchpoint {
+    pub register: Register,
+    pub kind: WatchKind
+}
+
+/// The location an access touched
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchTarget {
+    Memory(u32),
+    Register(Register)
+}
+
+/// Reported by [`crate::processor::Processor::cycle`] when an instruction
+/// touches a watched location
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WatchpointHit {
+    /// Address of the instruction that made the access
+    pub pc: u32,
+    pub instruction: Instruction,
+    pub target: WatchTarget,
+    /// Either `Read` or `Write`, never `Access`
+    pub access: WatchKind,
+    pub value: u32
+}
+
+impl WatchKind {
+    /// Whether a watchpoint of this kind fires on `access`
+    pub fn matches(&self, access: WatchKind) -> bool {
+        *self == WatchKind::Access || *self == access
+    }
+}
+
+impl MemoryWatchpoint {
+    /// Whether an access of `length` bytes at `address` overlaps the watched range
+    pub fn overlaps(&self, address: u32, length: u32) -> bool {
+        let end = self.start as u64 + self.length as u64;
+        let access_end = address as u64 + length as u64;
+        (address as u64) < end && (self.start as u64) < access_end
+    }
+}
+
+impl Display for WatchKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WatchKind::Read => write!(f, "read"),
+            WatchKind::Write => write!(f, "write"),
+            WatchKind::Access => write!(f, "access")
+        }
+    }
+}
+
+impl Display for WatchTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WatchTarget::Memory(address) => write!(f, "{:#010x}", address),
+            WatchTarget::Register(register) => write!(f, "${}", register.name())
+        }
+    }
+}
+
+impl Display for WatchpointHit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of {:#x} {} {} by {:#010x}: {}",
+            self.access,
+            self.value,
+            if self.access == WatchKind::Write { "to" } else { "from" },
+            self.target,
+            self.pc,
+            self.instruction.disassemble()
+        )
+    }
+}
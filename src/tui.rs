@@ -0,0 +1,541 @@
+use std::time::Duration;
+use num_traits::FromPrimitive;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::Frame;
+use crate::debugger::Debugger;
+use crate::processor::cache_hierarchy::CacheLevel;
+use crate::processor::cache_view::format_line;
+use crate::processor::console::ScriptedConsole;
+use crate::processor::instruction::Instruction;
+use crate::processor::registers::Register;
+use crate::processor::Status;
+
+/// Cycles run between redraws while running freely
+const CYCLES_PER_FRAME: u64 = 1000;
+/// Instructions shown above the program counter in the disassembly pane
+const DISASSEMBLY_CONTEXT: u32 = 6;
+/// Rows of 16 bytes in the memory pane
+const MEMORY_ROWS: u32 = 12;
+
+/// Recent accesses listed under the sets in a cache pane
+const CACHE_LOG_ROWS: usize = 4;
+
+const HELP: &str = "s step  n next instruction  c run/pause  r step back  b breakpoint  i input  \
+                    v memory/caches  q quit";
+
+/// Full screen front end showing the pipeline, registers, memory, disassembly
+/// and program output side by side
+pub struct Tui {
+    debugger: Debugger,
+    console: ScriptedConsole,
+    /// Register values before the last step, to highlight what it changed
+    previous_registers: [u32; 32],
+    running: bool,
+    /// Text typed for the program's next read, while in input mode
+    input: Option<String>,
+    /// Whether the program was running when it stopped to wait for input
+    resume_on_input: bool,
+    /// Cache shown in place of the memory pane
+    cache_pane: Option<CacheLevel>,
+    message: String,
+    quit: bool
+}
+
+impl Tui {
+    /// `console` must be the console the debugger's processor was created with,
+    /// the TUI shows its output and feeds it typed input
+    pub fn new(debugger: Debugger, console: ScriptedConsole) -> Self {
+        let previous_registers = read_registers(&debugger);
+        Self {
+            debugger,
+            console,
+            previous_registers,
+            running: false,
+            input: None,
+            resume_on_input: false,
+            cache_pane: None,
+            message: String::new(),
+            quit: false
+        }
+    }
+
+    pub fn get_debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    /// Takes over the terminal until the user quits
+    pub fn run(&mut self) -> std::io::Result<()> {
+        let mut terminal = ratatui::init();
+        let result = self.event_loop(&mut terminal);
+        ratatui::restore();
+        result
+    }
+
+    fn event_loop(&mut self, terminal: &mut ratatui::DefaultTerminal) -> std::io::Result<()> {
+        while !self.quit {
+            terminal.draw(|frame| self.draw(frame))?;
+            let timeout = if self.running {
+                Duration::ZERO
+            } else {
+                Duration::from_millis(250)
+            };
+            if event::poll(timeout)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.handle_key(key);
+                    }
+                }
+            } else if self.running {
+                self.run_frame();
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies a key press, public so front ends can be driven without a terminal
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if let Some(input) = self.input.as_mut() {
+            match key.code {
+                KeyCode::Enter => {
+                    let line = self.input.take().unwrap();
+                    self.console.push_input(&format!("{}\n", line));
+                    self.message = format!("Queued input {:?}", line);
+                    if self.resume_on_input {
+                        self.resume_on_input = false;
+                        self.running = true;
+                    }
+                }
+                KeyCode::Esc => {
+                    self.input = None;
+                    self.resume_on_input = false;
+                    self.message.clear();
+                }
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(character) => input.push(character),
+                _ => {}
+            }
+            return;
+        }
+        // Any key pauses a running program
+        if self.running {
+            self.running = false;
+            self.message = "Paused".to_string();
+            return;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Char('s') => {
+                self.remember_registers();
+                if self.starved() {
+                    return self.wait_for_input();
+                }
+                let status = self.debugger.get_processor_mut().cycle();
+                self.report(status);
+            }
+            KeyCode::Char('n') => {
+                self.remember_registers();
+                self.next_instruction();
+            }
+            KeyCode::Char('c') => {
+                self.remember_registers();
+                self.running = true;
+                self.message = "Running, press any key to pause".to_string();
+            }
+            KeyCode::Char('r') => {
+                self.remember_registers();
+                let processor = self.debugger.get_processor_mut();
+                self.message = match processor.step_back() {
+                    Some(_) => "Stepped back one cycle".to_string(),
+                    None if processor.is_history_cut_by_device() => {
+                        "Cannot step back over a device access".to_string()
+                    }
+                    None => "Reached the start of the recorded history".to_string()
+                };
+            }
+            KeyCode::Char('b') => {
+                let pc = self.debugger.get_processor().get_program_counter();
+                if self.debugger.remove_breakpoint(pc) {
+                    self.message = format!("Removed breakpoint at {:#010x}", pc);
+                } else {
+                    self.debugger.add_breakpoint(pc);
+                    self.message = format!("Breakpoint at {:#010x}", pc);
+                }
+            }
+            KeyCode::Char('i') => {
+                self.input = Some(String::new());
+                self.message = "Type a line of program input, enter queues it".to_string();
+            }
+            KeyCode::Char('v') => self.next_pane(),
+            _ => self.message = HELP.to_string()
+        }
+    }
+
+    /// Moves the right pane on to the next cache present, then back to memory
+    fn next_pane(&mut self) {
+        let caches = self.debugger.get_processor().get_caches();
+        let start = match self.cache_pane {
+            Some(level) => CacheLevel::ALL.iter().position(|other| *other == level).unwrap() + 1,
+            None => 0
+        };
+        self.cache_pane = CacheLevel::ALL[start..]
+            .iter()
+            .copied()
+            .find(|level| caches.get(*level).is_some());
+        self.message = match self.cache_pane {
+            Some(level) => format!("Showing the {}", level),
+            None => "Showing memory".to_string()
+        };
+    }
+
+    /// Runs a batch of cycles while running freely, stopping at breakpoints
+    /// and reads nothing was typed for yet
+    fn run_frame(&mut self) {
+        for _ in 0..CYCLES_PER_FRAME {
+            if self.starved() {
+                return self.wait_for_input();
+            }
+            match self.debugger.resume_for(1) {
+                (_, Some(number)) => {
+                    self.running = false;
+                    self.message = format!("Breakpoint {} reached", number);
+                    return;
+                }
+                (Status::Running, None) => {}
+                (status, None) => {
+                    self.running = false;
+                    return self.report(status);
+                }
+            }
+        }
+    }
+
+    /// Cycles until an instruction commits like [`Debugger::step_instruction`],
+    /// stopping early on a read nothing was typed for yet
+    fn next_instruction(&mut self) {
+        for _ in 0..CYCLES_PER_FRAME {
+            if self.starved() {
+                return self.wait_for_input();
+            }
+            let status = self.debugger.get_processor_mut().cycle();
+            let committed = self.debugger.get_processor().get_last_commit().is_some();
+            if status != Status::Running || committed {
+                return self.report(status);
+            }
+        }
+    }
+
+    /// Whether the next cycle would run a read syscall with no input queued
+    fn starved(&self) -> bool {
+        self.debugger.get_processor().wants_input() && !self.console.has_input()
+    }
+
+    /// Pauses and opens the input line, picking up where it left off once a
+    /// line is entered
+    fn wait_for_input(&mut self) {
+        self.resume_on_input = self.running;
+        self.running = false;
+        self.input = Some(String::new());
+        self.message = "The program is waiting for input".to_string();
+    }
+
+    fn remember_registers(&mut self) {
+        self.previous_registers = read_registers(&self.debugger);
+    }
+
+    fn report(&mut self, status: Status) {
+        self.message = match status {
+            Status::Running => String::new(),
+            Status::Exited(code) => format!("Program exited with status {}", code),
+            Status::Faulted(fault) => format!("Program faulted: {}", fault),
+            Status::Exception(exception) => format!("Program stopped on {}", exception),
+            Status::Watchpoint(hit) => format!("Watchpoint hit: {}", hit)
+        };
+    }
+
+    pub fn draw(&self, frame: &mut Frame) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(7),
+                Constraint::Min(18),
+                Constraint::Length(8),
+                Constraint::Length(1)
+            ])
+            .split(frame.area());
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([
+                Constraint::Percentage(35),
+                Constraint::Percentage(30),
+                Constraint::Percentage(35)
+            ])
+            .split(rows[1]);
+        self.draw_pipeline(frame, rows[0]);
+        self.draw_disassembly(frame, columns[0]);
+        self.draw_registers(frame, columns[1]);
+        match self.cache_pane {
+            Some(level) => self.draw_cache(frame, columns[2], level),
+            None => self.draw_memory(frame, columns[2])
+        }
+        self.draw_console(frame, rows[2]);
+        let status = match &self.input {
+            Some(input) => format!("input> {}", input),
+            None if self.message.is_empty() => HELP.to_string(),
+            None => self.message.clone()
+        };
+        frame.render_widget(
+            Paragraph::new(status).style(Style::default().add_modifier(Modifier::REVERSED)),
+            rows[3]
+        );
+    }
+
+    fn draw_pipeline(&self, frame: &mut Frame, area: Rect) {
+        let processor = self.debugger.get_processor();
+        // Each buffer holds what the stage before it produced this cycle, and
+        // buffers carry the already incremented PC
+        let buffered = |instruction: Option<Instruction>, pc: u32| {
+            instruction.map(|instruction| (pc.wrapping_sub(4), instruction))
+        };
+        let if_id = processor.get_if_id_buffer();
+        let id_ex = processor.get_id_ex_buffer();
+        let ex_mem = processor.get_ex_mem_buffer();
+        let mem_wb = processor.get_mem_wb_buffer();
+        let stages = [
+            ("IF", buffered(if_id.instruction, if_id.pc)),
+            ("ID", buffered(id_ex.instruction, id_ex.pc)),
+            ("EX", ex_mem.instruction.map(|instruction| (ex_mem.address, instruction))),
+            ("MEM", mem_wb.instruction.map(|instruction| (mem_wb.address, instruction))),
+            (
+                "WB",
+                processor
+                    .get_last_commit()
+                    .map(|commit| (commit.address, commit.instruction))
+            )
+        ];
+        let lines: Vec<Line> = stages
+            .iter()
+            .map(|(name, stage)| {
+                let name = Span::styled(format!("{:<4}", name), Style::default().fg(Color::Yellow));
+                match stage {
+                    Some((address, instruction)) => Line::from(vec![
+                        name,
+                        Span::raw(format!("{:#010x}  {}", address, instruction.disassemble()))
+                    ]),
+                    None => Line::from(vec![
+                        name,
+                        Span::styled("bubble", Style::default().fg(Color::DarkGray))
+                    ])
+                }
+            })
+            .collect();
+        let title = format!(
+            " Pipeline  cycle {}{} ",
+            processor.get_cycle_count(),
+            match processor.get_stall_cause() {
+                Some(cause) => format!("  stall: {}", cause),
+                None => String::new()
+            }
+        );
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)),
+            area
+        );
+    }
+
+    fn draw_disassembly(&self, frame: &mut Frame, area: Rect) {
+        let processor = self.debugger.get_processor();
+        let pc = processor.get_program_counter();
+        let rows = area.height.saturating_sub(2) as u32;
+        let start = pc.wrapping_sub(DISASSEMBLY_CONTEXT.min(rows / 2) * 4);
+        let mut lines = Vec::new();
+        let mut address = start;
+        while (lines.len() as u32) < rows {
+            if let Some(name) = processor.get_symbols().name(address) {
+                lines.push(Line::styled(format!("{}:", name), Style::default().fg(Color::Cyan)));
+                if lines.len() as u32 == rows {
+                    break;
+                }
+            }
+            let instruction = Instruction::load(processor.get_memory().peek_word(address));
+            let marker = if self.debugger.get_breakpoints().contains(&address) {
+                "*"
+            } else {
+                " "
+            };
+            let arrow = if address == pc { "=>" } else { "  " };
+            let text = format!(
+                "{}{} {:#010x}  {}",
+                marker,
+                arrow,
+                address,
+                instruction.disassemble()
+            );
+            let style = if address == pc {
+                Style::default()
+                    .fg(Color::Black)
+                    .bg(Color::Green)
+            } else if marker == "*" {
+                Style::default().fg(Color::Red)
+            } else {
+                Style::default()
+            };
+            lines.push(Line::styled(text, style));
+            address = address.wrapping_add(4);
+        }
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(Block::default().borders(Borders::ALL).title(" Disassembly ")),
+            area
+        );
+    }
+
+    fn draw_registers(&self, frame: &mut Frame, area: Rect) {
+        let processor = self.debugger.get_processor();
+        let current = read_registers(&self.debugger);
+        let cell = |index: usize| {
+            let name = Register::from_usize(index).unwrap().name();
+            let style = if current[index] != self.previous_registers[index] {
+                Style::default()
+                    .fg(Color::Red)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            Span::styled(format!("${:<4} {:08x}  ", name, current[index]), style)
+        };
+        let mut lines: Vec<Line> = (0..16)
+            .map(|row| Line::from(vec![cell(row), cell(row + 16)]))
+            .collect();
+        lines.push(Line::raw(format!(
+            "hi    {:08x}  lo    {:08x}",
+            processor.get_hi(),
+            processor.get_lo()
+        )));
+        lines.push(Line::raw(format!("pc    {:08x}", processor.get_program_counter())));
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(Block::default().borders(Borders::ALL).title(" Registers ")),
+            area
+        );
+    }
+
+    fn draw_memory(&self, frame: &mut Frame, area: Rect) {
+        let processor = self.debugger.get_processor();
+        let sp = processor.get_register(Register::Sp);
+        let start = (sp & !0xF).wrapping_sub(MEMORY_ROWS / 2 * 16);
+        let memory = processor.get_memory();
+        let lines: Vec<Line> = (0..MEMORY_ROWS)
+            .map(|row| {
+                let base = start.wrapping_add(row * 16);
+                let mut spans = vec![Span::raw(format!("{:08x} ", base))];
+                for column in 0..16 {
+                    let address = base.wrapping_add(column);
+                    let text = format!("{:02x}", memory.peek_byte(address));
+                    // Highlight the word $sp points at
+                    if address.wrapping_sub(sp) < 4 {
+                        spans.push(Span::styled(text, highlight()));
+                    } else {
+                        spans.push(Span::raw(text));
+                    }
+                    if column % 4 == 3 {
+                        spans.push(Span::raw(" "));
+                    }
+                }
+                Line::from(spans)
+            })
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(Block::default().borders(Borders::ALL).title(" Memory around $sp ")),
+            area
+        );
+    }
+
+    fn draw_cache(&self, frame: &mut Frame, area: Rect, level: CacheLevel) {
+        let cache = match self.debugger.get_processor().get_caches().get(level) {
+            Some(cache) => cache,
+            None => return self.draw_memory(frame, area)
+        };
+        let config = cache.get_config();
+        let ways = config.associativity as usize;
+        let sets: Vec<usize> = (0..config.sets() as usize)
+            .filter(|set| (0..ways).any(|way| cache.get_line(*set, way).valid))
+            .collect();
+        // Keep the touched set in view
+        let rows = (area.height.saturating_sub(2) as usize).saturating_sub(CACHE_LOG_ROWS + 1);
+        let touched = cache
+            .get_touched()
+            .and_then(|(set, _)| sets.iter().position(|other| *other == set))
+            .unwrap_or(0);
+        let first = touched.saturating_sub(rows / 2).min(sets.len().saturating_sub(rows));
+
+        let mut lines: Vec<Line> = sets[first..]
+            .iter()
+            .take(rows)
+            .map(|set| {
+                let mut spans = vec![Span::raw(format!("{:>3}", set))];
+                for way in 0..ways {
+                    let line = cache.get_line(*set, way);
+                    spans.push(Span::raw(" "));
+                    let text = format_line(&line);
+                    if line.touched {
+                        spans.push(Span::styled(text, highlight()));
+                    } else {
+                        spans.push(Span::raw(text));
+                    }
+                }
+                Line::from(spans)
+            })
+            .collect();
+        let empty = config.sets() as usize - sets.len();
+        lines.push(Line::raw(format!("{} of {} sets empty", empty, config.sets())));
+        let log: Vec<_> = cache.get_access_log().collect();
+        for access in &log[log.len().saturating_sub(CACHE_LOG_ROWS)..] {
+            lines.push(Line::raw(access.to_string()));
+        }
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(Block::default().borders(Borders::ALL).title(format!(" {} ", level))),
+            area
+        );
+    }
+
+    fn draw_console(&self, frame: &mut Frame, area: Rect) {
+        let output = self.console.output();
+        let rows = area.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = output
+            .lines()
+            .rev()
+            .take(rows)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .map(|line| Line::raw(line.to_string()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(" Console ")),
+            area
+        );
+    }
+}
+
+/// Marks the bytes and cache lines a pane draws attention to
+fn highlight() -> Style {
+    Style::default().fg(Color::Black).bg(Color::Cyan)
+}
+
+fn read_registers(debugger: &Debugger) -> [u32; 32] {
+    let mut registers = [0; 32];
+    for (index, value) in registers.iter_mut().enumerate() {
+        *value = debugger
+            .get_processor()
+            .get_register(Register::from_usize(index).unwrap());
+    }
+    registers
+}
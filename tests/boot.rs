@@ -0,0 +1,153 @@
+use mips_sim::processor::console::ScriptedConsole;
+use mips_sim::processor::cp0::{
+    BOOT_EXCEPTION_VECTOR,
+    RESET_VECTOR,
+    STATUS,
+    STATUS_BEV,
+    STATUS_IE
+};
+use mips_sim::processor::exception::ExceptionHandler;
+use mips_sim::processor::memory::RomError;
+use mips_sim::processor::registers::Register;
+use mips_sim::processor::segment::{Access, MemoryFault, ROM_BASE, ROM_SIZE};
+use mips_sim::processor::snapshot::SnapshotError;
+use mips_sim::processor::{Processor, Status};
+
+const RAM_SIZE: u32 = 0x10_0000;
+
+fn boot(rom: Vec<u32>) -> (Processor, ScriptedConsole) {
+    let console = ScriptedConsole::new("");
+    let processor = Processor::new_bare_metal(Box::new(console.clone()), rom, RAM_SIZE).unwrap();
+    (processor, console)
+}
+
+/// Stores 0x42 to RAM through kseg0 and loads it back through kseg1
+fn store_and_load() -> Vec<u32> {
+    vec![
+        // lui $t0, 0x8000
+        0x3C08_8000,
+        // ori $t1, $zero, 0x42
+        0x3409_0042,
+        0x0000_0000,
+        0x0000_0000,
+        // sw $t1, 0x100($t0)
+        0xAD09_0100,
+        // lui $t2, 0xa000
+        0x3C0A_A000,
+        0x0000_0000,
+        0x0000_0000,
+        // lw $t3, 0x100($t2)
+        0x8D4B_0100
+    ]
+}
+
+#[test]
+fn test_reset_state() {
+    let (processor, _) = boot(vec![0x1234_5678]);
+    assert!(processor.is_bare_metal());
+    assert_eq!(processor.get_program_counter(), RESET_VECTOR);
+    assert!(!processor.is_user_mode());
+    let status = processor.get_cp0().get(STATUS);
+    assert_ne!(status & STATUS_BEV, 0);
+    assert_eq!(status & STATUS_IE, 0);
+    assert_eq!(processor.get_register(Register::Sp), 0);
+    assert_eq!(processor.get_register(Register::Gp), 0);
+    assert_eq!(processor.get_exception_handler(), ExceptionHandler::Vector);
+    let memory = processor.get_memory();
+    assert_eq!(memory.read_word(ROM_BASE), 0x1234_5678);
+    assert_eq!(memory.segment(0).unwrap().name, "ram");
+    assert_eq!(memory.segment(RAM_SIZE - 1).unwrap().name, "ram");
+    assert!(memory.segment(RAM_SIZE).is_none());
+    assert_eq!(memory.segment(ROM_BASE).unwrap().name, "rom");
+}
+
+#[test]
+fn test_ram_through_kseg0_and_kseg1() {
+    let (mut processor, _) = boot(store_and_load());
+    assert_eq!(processor.run(20), Status::Running);
+    assert_eq!(processor.get_memory().read_word(0x100), 0x42);
+    assert_eq!(processor.get_register(Register::T3), 0x42);
+}
+
+#[test]
+fn test_syscall_goes_to_the_boot_vector() {
+    // syscall, then nops up to the boot exception vector
+    let mut rom = vec![0x0000_000C];
+    rom.resize(((BOOT_EXCEPTION_VECTOR - RESET_VECTOR) / 4) as usize, 0);
+    rom.extend([
+        // mfc0 $k0, $13
+        0x401A_6800,
+        // mfc0 $k1, $14
+        0x401B_7000
+    ]);
+    let (mut processor, console) = boot(rom);
+    assert_eq!(processor.run(20), Status::Running);
+    assert_eq!(processor.get_register(Register::K0) & 0x7C, 8 << 2);
+    assert_eq!(processor.get_register(Register::K1), RESET_VECTOR);
+    assert_eq!(console.output(), "");
+}
+
+#[test]
+fn test_rom_is_read_only() {
+    let (mut processor, _) = boot(vec![
+        // lui $t0, 0xbfc0
+        0x3C08_BFC0,
+        0x0000_0000,
+        0x0000_0000,
+        // sw $zero, 0x40($t0)
+        0xAD00_0040
+    ]);
+    processor.set_exception_handler(ExceptionHandler::Halt);
+    assert_eq!(
+        processor.run(20),
+        Status::Faulted(MemoryFault::Permission {
+            address: ROM_BASE + 0x40,
+            access: Access::Write,
+            segment: "rom"
+        })
+    );
+}
+
+#[test]
+fn test_rom_too_large() {
+    let rom = vec![0; ROM_SIZE as usize / 4 + 1];
+    let result = Processor::new_bare_metal(Box::new(ScriptedConsole::new("")), rom, RAM_SIZE);
+    assert_eq!(result.err(), Some(RomError::TooLarge { bytes: ROM_SIZE as usize + 4 }));
+    let rom = vec![0; ROM_SIZE as usize / 4];
+    assert!(Processor::new_bare_metal(Box::new(ScriptedConsole::new("")), rom, RAM_SIZE).is_ok());
+}
+
+#[test]
+fn test_bare_metal_snapshot() {
+    let (mut original, _) = boot(store_and_load());
+    original.run(6);
+    let mut snapshot = Vec::new();
+    original.save_snapshot(&mut snapshot).unwrap();
+
+    let (mut restored, _) = boot(Vec::new());
+    restored.restore_snapshot(snapshot.as_slice()).unwrap();
+    assert_eq!(restored.get_exception_handler(), ExceptionHandler::Vector);
+    assert_eq!(restored.run(14), Status::Running);
+    assert_eq!(restored.get_register(Register::T3), 0x42);
+
+    // Other memory maps are refused and left untouched
+    let mut hosted = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    assert!(matches!(
+        hosted.restore_snapshot(snapshot.as_slice()),
+        Err(SnapshotError::WrongMachine)
+    ));
+    assert!(!hosted.is_bare_metal());
+    let console = Box::new(ScriptedConsole::new(""));
+    let mut smaller = Processor::new_bare_metal(console, Vec::new(), RAM_SIZE / 2).unwrap();
+    assert!(matches!(
+        smaller.restore_snapshot(snapshot.as_slice()),
+        Err(SnapshotError::WrongMachine)
+    ));
+    let mut snapshot = Vec::new();
+    hosted.save_snapshot(&mut snapshot).unwrap();
+    assert!(matches!(
+        restored.restore_snapshot(snapshot.as_slice()),
+        Err(SnapshotError::WrongMachine)
+    ));
+    assert_eq!(restored.get_register(Register::T3), 0x42);
+}
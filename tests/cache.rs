@@ -0,0 +1,184 @@
+use mips_sim::processor::cache::{
+    Cache,
+    CacheConfig,
+    CacheConfigError,
+    ReplacementPolicy,
+    WritePolicy
+};
+use mips_sim::processor::pipeline_stats::StallCause;
+use mips_sim::processor::registers::Register;
+use mips_sim::processor::segment::Access;
+use mips_sim::processor::{Processor, Status};
+
+#[test]
+fn test_cache_replacement() {
+    // Two sets of two 16 byte lines, blocks 0x00, 0x20 and 0x40 share set 0
+    let mut lru = Cache::new(CacheConfig::new(64, 16, 2)).unwrap();
+    assert!(!lru.access(0x00, Access::Read).hit);
+    assert!(!lru.access(0x20, Access::Read).hit);
+    assert!(lru.access(0x04, Access::Read).hit);
+    assert!(!lru.access(0x40, Access::Read).hit);
+    // 0x20 was least recently used
+    assert!(lru.contains(0x00));
+    assert!(!lru.contains(0x20));
+    assert!(lru.contains(0x40));
+    assert_eq!(lru.get_stats().reads, 4);
+    assert_eq!(lru.get_stats().read_misses, 3);
+
+    let mut config = CacheConfig::new(64, 16, 2);
+    config.replacement = ReplacementPolicy::Fifo;
+    let mut fifo = Cache::new(config).unwrap();
+    for address in [0x00, 0x20, 0x04, 0x40] {
+        fifo.access(address, Access::Read);
+    }
+    // 0x00 was filled first even though it was used more recently
+    assert!(!fifo.contains(0x00));
+    assert!(fifo.contains(0x20));
+
+    // Other sets are untouched
+    assert!(!lru.access(0x10, Access::Execute).hit);
+    assert!(lru.contains(0x40));
+}
+
+#[test]
+fn test_cache_write_policies() {
+    let mut write_back = Cache::new(CacheConfig::new(32, 16, 1)).unwrap();
+    let outcome = write_back.access(0x100, Access::Write);
+    assert_eq!(outcome.fill, Some(0x100));
+    assert_eq!(outcome.write_through, None);
+    // Evicting the dirty line writes its block back
+    let outcome = write_back.access(0x120, Access::Read);
+    assert_eq!(outcome.fill, Some(0x120));
+    assert_eq!(outcome.writeback, Some(0x100));
+    assert_eq!(write_back.get_stats().writebacks, 1);
+
+    let mut config = CacheConfig::new(32, 16, 1);
+    config.write_policy = WritePolicy::WriteThrough;
+    config.write_allocate = false;
+    let mut write_through = Cache::new(config).unwrap();
+    let outcome = write_through.access(0x104, Access::Write);
+    assert!(!outcome.hit);
+    assert_eq!(outcome.fill, None);
+    assert_eq!(outcome.write_through, Some(0x104));
+    assert!(!write_through.contains(0x104));
+    write_through.access(0x100, Access::Read);
+    let outcome = write_through.access(0x108, Access::Write);
+    assert!(outcome.hit);
+    assert_eq!(outcome.write_through, Some(0x108));
+    // Clean lines are dropped without a writeback
+    assert_eq!(write_through.access(0x120, Access::Read).writeback, None);
+    assert_eq!(write_through.get_stats().write_misses, 1);
+}
+
+#[test]
+fn test_cache_config_parse() {
+    let config: CacheConfig = "4096,16,2,fifo,wt,nwa".parse().unwrap();
+    assert_eq!(config.sets(), 128);
+    assert_eq!(config.replacement, ReplacementPolicy::Fifo);
+    assert_eq!(config.write_policy, WritePolicy::WriteThrough);
+    assert!(!config.write_allocate);
+
+    assert_eq!(
+        "1000,16,2".parse::<CacheConfig>(),
+        Err(CacheConfigError::NotPowerOfTwo("size"))
+    );
+    assert_eq!("64,16,8".parse::<CacheConfig>(), Err(CacheConfigError::Geometry));
+    assert!(matches!(
+        "64,16".parse::<CacheConfig>(),
+        Err(CacheConfigError::Invalid(_))
+    ));
+    assert!(matches!(
+        "64,16,1,plru".parse::<CacheConfig>(),
+        Err(CacheConfigError::Invalid(_))
+    ));
+}
+
+#[test]
+fn test_processor_caches() {
+    let mut processor = Processor::new();
+    processor.set_instruction_cache(Cache::new(CacheConfig::new(256, 16, 1)).unwrap());
+    processor.set_data_cache(Cache::new(CacheConfig::new(256, 16, 1)).unwrap());
+    processor.load_program(vec![
+        // lui $t1, 0x1001
+        0x3C09_1001,
+        // ori $t0, $zero, 0x55
+        0x3408_0055,
+        0x0000_0000,
+        0x0000_0000,
+        // sw $t0, 0($t1)
+        0xAD28_0000,
+        // lw $t2, 4($t1)
+        0x8D2A_0004,
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    assert_eq!(processor.run(100), Status::Exited(0));
+
+    let instruction = processor.get_instruction_cache().unwrap().get_stats();
+    // One miss for every 16 byte block of the ten instruction program
+    assert_eq!(instruction.read_misses, 3);
+    assert_eq!(instruction.writes, 0);
+    let data = processor.get_data_cache().unwrap().get_stats();
+    assert_eq!(data.writes, 1);
+    assert_eq!(data.write_misses, 1);
+    assert_eq!(data.reads, 1);
+    assert_eq!(data.read_misses, 0);
+}
+
+#[test]
+fn test_cache_miss_stalls() {
+    let program = vec![
+        // lui $t1, 0x1001
+        0x3C09_1001,
+        // ori $t0, $zero, 0x55
+        0x3408_0055,
+        0x0000_0000,
+        0x0000_0000,
+        // sw $t0, 0($t1)
+        0xAD28_0000,
+        // lw $t2, 0($t1)
+        0x8D2A_0000,
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ];
+    let mut baseline = Processor::new();
+    baseline.load_program(program.clone());
+    assert_eq!(baseline.run(100), Status::Exited(0));
+    let baseline_cycles = baseline.get_pipeline_stats().cycles;
+    assert_eq!(baseline.get_pipeline_stats().instruction_cache_stalls, 0);
+
+    let mut processor = Processor::new();
+    let mut config = CacheConfig::new(256, 16, 1);
+    config.miss_penalty = 1;
+    processor.set_instruction_cache(Cache::new(config).unwrap());
+    processor.set_data_cache(Cache::new(config).unwrap());
+    processor.set_memory_latency(3);
+    processor.load_program(program);
+
+    let mut causes = Vec::new();
+    while processor.cycle() == Status::Running {
+        causes.push(processor.get_stall_cause());
+    }
+    assert_eq!(processor.get_status(), Status::Exited(0));
+    assert_eq!(processor.get_register(Register::T2), 0x55);
+    // The first fetch misses and waits out the penalty plus one transfer
+    assert_eq!(&causes[..4], &[Some(StallCause::InstructionCache); 4]);
+    assert_eq!(causes[4], None);
+
+    let stats = processor.get_pipeline_stats();
+    // Three instruction blocks and the store's fill each cost four cycles,
+    // the load hits the block the store brought in
+    assert_eq!(stats.instruction_cache_stalls, 12);
+    assert_eq!(stats.data_cache_stalls, 4);
+    assert_eq!(stats.cycles, baseline_cycles + 16);
+    assert_eq!(stats.instructions, baseline.get_pipeline_stats().instructions);
+    assert_eq!(processor.get_data_cache().unwrap().get_stats().read_misses, 0);
+}
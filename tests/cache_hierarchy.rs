@@ -0,0 +1,106 @@
+use mips_sim::processor::cache::{Cache, CacheConfig};
+use mips_sim::processor::cache_hierarchy::{CacheHierarchy, InclusionPolicy};
+use mips_sim::processor::segment::Access;
+use mips_sim::processor::{Processor, Status};
+
+/// Direct mapped L1s of two 16 byte lines over a two line L2 with 32 byte
+/// blocks, 4 cycles to reach the L2, 2 more on an L2 miss and 10 per memory
+/// transfer
+fn hierarchy(inclusion: InclusionPolicy) -> CacheHierarchy {
+    let mut hierarchy = CacheHierarchy::new();
+    let l1 = CacheConfig::new(32, 16, 1);
+    hierarchy.set_instruction_cache(Some(Cache::new(l1).unwrap()));
+    hierarchy.set_data_cache(Some(Cache::new(l1).unwrap()));
+    let mut l2 = CacheConfig::new(64, 32, 2);
+    l2.latency = 4;
+    l2.miss_penalty = 2;
+    hierarchy.set_l2_cache(Some(Cache::new(l2).unwrap()));
+    hierarchy.set_inclusion_policy(inclusion);
+    hierarchy.set_memory_latency(10);
+    hierarchy
+}
+
+#[test]
+fn test_inclusive_hierarchy() {
+    let mut caches = hierarchy(InclusionPolicy::Inclusive);
+    // Misses everywhere
+    assert_eq!(caches.access(0x000, Access::Read), 16);
+    assert_eq!(caches.access(0x004, Access::Read), 0);
+    // The other half of the L2 block is already in the L2
+    assert_eq!(caches.access(0x010, Access::Read), 4);
+    // Instructions share the L2
+    assert_eq!(caches.access(0x000, Access::Execute), 4);
+
+    // Dirty the L1 line, then evict it so it is written back into the L2
+    assert_eq!(caches.access(0x000, Access::Write), 0);
+    assert_eq!(caches.access(0x020, Access::Read), 4 + 16);
+    let l2 = caches.get_l2_cache().unwrap().get_stats();
+    assert_eq!(l2.writes, 1);
+    assert_eq!(l2.write_misses, 0);
+
+    // The write made block 0x000 the most recently used in the L2, so a third
+    // block evicts the clean 0x020 without touching the L1s
+    assert_eq!(caches.access(0x040, Access::Read), 16);
+    assert!(caches.get_instruction_cache().unwrap().contains(0x000));
+
+    // A fourth evicts the dirty block 0x000 from the L2 and so from both L1s
+    assert_eq!(caches.access(0x060, Access::Read), 16 + 10);
+    assert!(!caches.get_l2_cache().unwrap().contains(0x000));
+    assert!(!caches.get_instruction_cache().unwrap().contains(0x000));
+    assert!(!caches.get_data_cache().unwrap().contains(0x010));
+    let l2 = caches.get_l2_cache().unwrap().get_stats();
+    assert_eq!(l2.evictions, 2);
+    assert_eq!(l2.writebacks, 1);
+    assert_eq!(caches.get_instruction_cache().unwrap().get_stats().evictions, 1);
+}
+
+#[test]
+fn test_exclusive_hierarchy() {
+    let mut caches = hierarchy(InclusionPolicy::Exclusive);
+    // Misses go to memory without allocating in the L2
+    assert_eq!(caches.access(0x000, Access::Read), 16);
+    assert!(!caches.get_l2_cache().unwrap().contains(0x000));
+
+    // Evicting the L1 line moves it down into the L2
+    assert_eq!(caches.access(0x020, Access::Write), 16 + 4);
+    assert!(caches.get_l2_cache().unwrap().contains(0x000));
+    assert!(!caches.get_data_cache().unwrap().contains(0x000));
+
+    // Hitting it in the L2 moves it back up, the dirty line goes down
+    assert_eq!(caches.access(0x000, Access::Read), 4 + 4);
+    assert!(!caches.get_l2_cache().unwrap().contains(0x000));
+    assert!(caches.get_l2_cache().unwrap().contains(0x020));
+
+    // The dirty line comes back up dirty and is written back on eviction
+    assert_eq!(caches.access(0x020, Access::Read), 4 + 4);
+    assert_eq!(caches.access(0x000, Access::Read), 4 + 4);
+    let data = caches.get_data_cache().unwrap().get_stats();
+    assert_eq!(data.read_misses, 4);
+    assert_eq!(data.writebacks, 2);
+    let l2 = caches.get_l2_cache().unwrap().get_stats();
+    assert_eq!(l2.reads, 5);
+    assert_eq!(l2.read_misses, 2);
+}
+
+#[test]
+fn test_processor_l2() {
+    let mut processor = Processor::new();
+    processor.set_instruction_cache(Cache::new(CacheConfig::new(32, 16, 1)).unwrap());
+    let mut l2 = CacheConfig::new(1024, 32, 2);
+    l2.latency = 3;
+    processor.set_l2_cache(Cache::new(l2).unwrap());
+    processor.set_memory_latency(5);
+    processor.load_program(vec![
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    assert_eq!(processor.run(100), Status::Exited(0));
+    // One miss to memory through the L2 for the single block
+    assert_eq!(processor.get_pipeline_stats().instruction_cache_stalls, 8);
+    assert_eq!(processor.get_l2_cache().unwrap().get_stats().read_misses, 1);
+    assert!(processor.get_caches().to_string().contains("L2 cache (Inclusive)"));
+}
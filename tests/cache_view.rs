@@ -0,0 +1,155 @@
+use mips_sim::debugger::Debugger;
+use mips_sim::processor::cache::{AccessClass, Cache, CacheConfig};
+use mips_sim::processor::cache_view::CacheView;
+use mips_sim::processor::console::ScriptedConsole;
+use mips_sim::processor::segment::Access;
+use mips_sim::processor::{Processor, Status};
+use mips_sim::tui::Tui;
+use ratatui::backend::TestBackend;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::Terminal;
+
+/// Stores to 0x10010000 then loads from 0x10010004, with both L1 caches set
+fn processor(console: ScriptedConsole) -> Processor {
+    let mut processor = Processor::new_with_console(Box::new(console));
+    processor.set_instruction_cache(Cache::new(CacheConfig::new(256, 16, 1)).unwrap());
+    processor.set_data_cache(Cache::new(CacheConfig::new(256, 16, 1)).unwrap());
+    processor.load_program(vec![
+        // lui $t1, 0x1001
+        0x3C09_1001,
+        // ori $t0, $zero, 0x55
+        0x3408_0055,
+        0x0000_0000,
+        0x0000_0000,
+        // sw $t0, 0($t1)
+        0xAD28_0000,
+        // lw $t2, 4($t1)
+        0x8D2A_0004,
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    processor
+}
+
+#[test]
+fn test_miss_classification() {
+    // Four direct mapped 16 byte lines, blocks 0x00 and 0x40 share set 0
+    let mut cache = Cache::new(CacheConfig::new(64, 16, 1)).unwrap();
+    for address in [0x00, 0x40, 0x00, 0x04] {
+        cache.access(address, Access::Read);
+    }
+    let classes: Vec<AccessClass> = cache.get_access_log().map(|access| access.class).collect();
+    assert_eq!(
+        classes,
+        [AccessClass::Compulsory, AccessClass::Compulsory, AccessClass::Conflict, AccessClass::Hit]
+    );
+
+    // Five blocks do not fit in four lines however they are placed
+    let mut cache = Cache::new(CacheConfig::new(64, 16, 4)).unwrap();
+    for address in [0x00, 0x10, 0x20, 0x30, 0x40, 0x00] {
+        cache.access(address, Access::Read);
+    }
+    let last = cache.get_access_log().last().unwrap();
+    assert_eq!(last.class, AccessClass::Capacity);
+    assert_eq!(last.to_string(), "0x00000000 read  set 0 way 1 capacity miss");
+    let stats = cache.get_stats();
+    assert_eq!(stats.compulsory_misses, 5);
+    assert_eq!(stats.capacity_misses, 1);
+    assert_eq!(stats.conflict_misses, 0);
+}
+
+#[test]
+fn test_line_state() {
+    let mut cache = Cache::new(CacheConfig::new(64, 16, 2)).unwrap();
+    cache.access(0x00, Access::Write);
+    cache.access(0x20, Access::Read);
+    let first = cache.get_line(0, 0);
+    assert!(first.valid && first.dirty);
+    assert_eq!(first.tag, 0);
+    assert_eq!(first.lru, 1);
+    assert!(!first.touched);
+    let second = cache.get_line(0, 1);
+    assert!(second.valid && !second.dirty);
+    assert_eq!(second.tag, 1);
+    assert_eq!(second.lru, 0);
+    assert!(second.touched);
+    assert!(!cache.get_line(1, 0).valid);
+
+    assert_eq!(
+        CacheView::new(&cache).to_string(),
+        "set   way 0             way 1           \n  \
+           0   VD 0x00000000 1  [V- 0x00000001 0]\n\
+         1 of 2 sets empty\n"
+    );
+}
+
+#[test]
+fn test_touched_line() {
+    let mut processor = processor(ScriptedConsole::new(""));
+    let mut touched = Vec::new();
+    while processor.get_status() == Status::Running {
+        processor.cycle();
+        if let Some(line) = processor.get_data_cache().unwrap().get_touched() {
+            touched.push(line);
+        }
+    }
+    // The store and the load each touch the line for one cycle
+    assert_eq!(touched, [(0, 0), (0, 0)]);
+}
+
+#[test]
+fn test_info_cache() {
+    let mut debugger = Debugger::new(processor(ScriptedConsole::new("")));
+    debugger.execute("continue").unwrap();
+    let output = debugger.execute("info cache d").unwrap();
+    assert!(output.starts_with("L1 data cache:\n"), "{}", output);
+    assert!(output.contains("15 of 16 sets empty\n"), "{}", output);
+    assert!(output.contains("  0x10010000 write set 0 way 0 compulsory miss\n"), "{}", output);
+    assert!(output.contains("  0x10010004 read  set 0 way 0 hit\n"), "{}", output);
+
+    let output = debugger.execute("info cache").unwrap();
+    assert!(output.contains("L1 instruction cache:\n"));
+    assert!(output.contains("L1 data cache:\n"));
+    assert_eq!(debugger.execute("info cache l2"), Err("No L2 cache".to_string()));
+    assert!(debugger.execute("info cache x").is_err());
+}
+
+#[test]
+fn test_tui_cache_pane() {
+    let console = ScriptedConsole::new("");
+    let mut tui = Tui::new(Debugger::new(processor(console.clone())), console);
+    let press = |tui: &mut Tui, character| {
+        tui.handle_key(KeyEvent::new(KeyCode::Char(character), KeyModifiers::NONE));
+    };
+    for _ in 0..8 {
+        press(&mut tui, 's');
+    }
+    press(&mut tui, 'v');
+    press(&mut tui, 'v');
+
+    let mut terminal = Terminal::new(TestBackend::new(120, 40)).unwrap();
+    terminal.draw(|frame| tui.draw(frame)).unwrap();
+    let buffer = terminal.backend().buffer();
+    let mut screen = String::new();
+    for y in 0..buffer.area.height {
+        for x in 0..buffer.area.width {
+            screen += buffer[(x, y)].symbol();
+        }
+        screen += "\n";
+    }
+    assert!(screen.contains(" L1 data cache "), "{}", screen);
+    assert!(!screen.contains("Memory around $sp"));
+    assert!(screen.contains("VD 0x00100100 0"), "{}", screen);
+    assert!(screen.contains("0x10010000 write set 0 way 0 compulsory"), "{}", screen);
+
+    // Past the last cache the pane goes back to memory
+    press(&mut tui, 'v');
+    terminal.draw(|frame| tui.draw(frame)).unwrap();
+    let buffer = terminal.backend().buffer();
+    let top: String = (0..buffer.area.width).map(|x| buffer[(x, 7)].symbol()).collect();
+    assert!(top.contains("Memory around $sp"), "{}", top);
+}
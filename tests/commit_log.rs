@@ -0,0 +1,142 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+use mips_sim::processor::console::ScriptedConsole;
+use mips_sim::processor::tlb::{Tlb, TlbEntry, ENTRY_LO_D, ENTRY_LO_V};
+use mips_sim::processor::{Processor, Status};
+
+/// Collects log output where the test can still read it
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buffer)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_commit_log() {
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("42\n")));
+    processor.load_program(vec![
+        // ori $v0, $zero, 5
+        0x3402_0005,
+        // lui $t1, 0x1001
+        0x3C09_1001,
+        0x0000_0000,
+        // syscall
+        0x0000_000C,
+        0x0000_0000,
+        0x0000_0000,
+        // sb $v0, 3($t1)
+        0xA122_0003,
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    let buffer = SharedBuffer::default();
+    processor.set_commit_log_writer(Box::new(buffer.clone()));
+    assert_eq!(processor.run(100), Status::Exited(0));
+    let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "0x00400000 0x34020005 $v0=0x00000005 # ori $v0, $zero, 0x5",
+            "0x00400004 0x3c091001 $t1=0x10010000 # lui $t1, 0x1001",
+            "0x00400008 0x00000000 - # nop",
+            "0x0040000c 0x0000000c $v0=0x0000002a # syscall",
+            "0x00400010 0x00000000 - # nop",
+            "0x00400014 0x00000000 - # nop",
+            "0x00400018 0xa1220003 [0x10010003]=0x2a # sb $v0, 3($t1)",
+            "0x0040001c 0x3402000a $v0=0x0000000a # ori $v0, $zero, 0xa",
+            "0x00400020 0x00000000 - # nop",
+            // The second nop is still in the pipeline when exit halts it
+            "0x00400028 0x0000000c - # syscall"
+        ]
+    );
+}
+
+#[test]
+fn test_commit_log_jump_register() {
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.load_program(vec![
+        // lui $t0, 0x0040
+        0x3C08_0040,
+        0x0000_0000,
+        0x0000_0000,
+        // ori $t0, $t0, 0x20
+        0x3508_0020,
+        0x0000_0000,
+        0x0000_0000,
+        // jr $t0
+        0x0100_0008,
+        // ori $v0, $zero, 5
+        0x3402_0005,
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    let buffer = SharedBuffer::default();
+    processor.set_commit_log_writer(Box::new(buffer.clone()));
+    assert_eq!(processor.run(100), Status::Exited(0));
+    let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "0x00400000 0x3c080040 $t0=0x00400000 # lui $t0, 0x40",
+            "0x00400004 0x00000000 - # nop",
+            "0x00400008 0x00000000 - # nop",
+            "0x0040000c 0x35080020 $t0=0x00400020 # ori $t0, $t0, 0x20",
+            "0x00400010 0x00000000 - # nop",
+            "0x00400014 0x00000000 - # nop",
+            // The jump keeps its own address although the ALU replaced its PC
+            "0x00400018 0x01000008 - # jr $t0",
+            "0x00400020 0x3402000a $v0=0x0000000a # ori $v0, $zero, 0xa",
+            "0x00400024 0x00000000 - # nop",
+            "0x0040002c 0x0000000c - # syscall"
+        ]
+    );
+}
+
+#[test]
+fn test_commit_log_mapped_store() {
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.get_memory_mut().set_tlb(Tlb::new());
+    let tlb = processor.get_memory_mut().get_tlb_mut().unwrap();
+    tlb.set(0, TlbEntry::new(0x0040_0000, 0x0040_0000 | ENTRY_LO_V));
+    tlb.set(1, TlbEntry::new(0x1001_0000, 0x0020_0000 | ENTRY_LO_D | ENTRY_LO_V));
+    processor.load_program(vec![
+        // lui $t1, 0x1001
+        0x3C09_1001,
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        // sb $v0, 3($t1)
+        0xA122_0003,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    let buffer = SharedBuffer::default();
+    processor.set_commit_log_writer(Box::new(buffer.clone()));
+    assert_eq!(processor.run(100), Status::Exited(0));
+    assert_eq!(processor.get_memory().read_byte(0x0020_0003), 10);
+    let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+    // The store is logged once, at the address the program used
+    let store = "0x0040000c 0xa1220003 [0x10010003]=0x0a # sb $v0, 3($t1)";
+    assert_eq!(output.lines().filter(|line| line.starts_with("0x0040000c")).count(), 1);
+    assert!(output.lines().any(|line| line == store));
+}
@@ -0,0 +1,96 @@
+use mips_sim::debugger::Debugger;
+use mips_sim::processor::console::ScriptedConsole;
+use mips_sim::processor::memory::TEXT_BASE;
+use mips_sim::processor::registers::Register;
+use mips_sim::processor::{Processor, Status};
+
+fn debugger() -> Debugger {
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.load_program(vec![
+        // ori $t0, $zero, 7
+        0x3408_0007,
+        // ori $t1, $zero, 9
+        0x3409_0009,
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    processor.add_symbol("main", TEXT_BASE);
+    processor.add_symbol("exit", TEXT_BASE + 8);
+    Debugger::new(processor)
+}
+
+#[test]
+fn test_debugger_breakpoint_label() {
+    let mut debugger = debugger();
+    assert_eq!(
+        debugger.execute("break exit"),
+        Ok(format!("Breakpoint 1 at {:#010x}\n", TEXT_BASE + 8))
+    );
+    let output = debugger.execute("continue").unwrap();
+    assert!(output.starts_with("Breakpoint 1 reached"));
+    assert!(output.contains("<exit>: ori $v0, $zero, 0xa"));
+    assert_eq!(debugger.get_processor().get_program_counter(), TEXT_BASE + 8);
+    debugger.execute("delete 1").unwrap();
+    let output = debugger.execute("c").unwrap();
+    assert!(output.contains("Program exited with status 0"));
+    assert_eq!(debugger.get_processor().get_status(), Status::Exited(0));
+}
+
+#[test]
+fn test_debugger_stepi_and_registers() {
+    let mut debugger = debugger();
+    debugger.execute("stepi").unwrap();
+    assert_eq!(debugger.get_processor().get_register(Register::T0), 7);
+    assert_eq!(debugger.get_processor().get_register(Register::T1), 0);
+    debugger.execute("").unwrap();
+    assert_eq!(
+        debugger.execute("print $t1"),
+        Ok("$t1 = 0x00000009 (9)\n".to_string())
+    );
+    debugger.execute("set t1 -1").unwrap();
+    assert_eq!(debugger.get_processor().get_register(Register::T1), u32::MAX);
+    assert!(debugger.execute("print $nope").is_err());
+}
+
+#[test]
+fn test_debugger_examine() {
+    let mut debugger = debugger();
+    let output = debugger.execute("x main 8 dis").unwrap();
+    assert_eq!(
+        output,
+        "=> 0x00400000: 34080007  ori $t0, $zero, 0x7\n   0x00400004: 34090009  ori $t1, $zero, 0x9\n"
+    );
+}
+
+#[test]
+fn test_debugger_breakpoint_during_stall() {
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.load_program(vec![
+        // beq $t0, $t1, 1
+        0x1109_0001,
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    let mut debugger = Debugger::new(processor);
+    debugger.execute(&format!("break {:#x}", TEXT_BASE + 4)).unwrap();
+    let output = debugger.execute("continue").unwrap();
+    assert!(output.starts_with("Breakpoint 1 reached"));
+    // The branch holds the PC on the breakpoint for two more cycles
+    let output = debugger.execute("continue").unwrap();
+    assert!(output.contains("Program exited with status 0"));
+}
+
+#[test]
+fn test_debugger_examine_limit() {
+    let mut debugger = debugger();
+    let output = debugger.execute("x 0 0xffffffff ascii").unwrap();
+    assert_eq!(output.trim_end().len(), "0x00000000: ".len() + 4096);
+}
@@ -0,0 +1,179 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use mips_sim::debugger::Debugger;
+use mips_sim::processor::cache::{Cache, CacheConfig};
+use mips_sim::processor::console::ScriptedConsole;
+use mips_sim::processor::device::{Device, DeviceMapError};
+use mips_sim::processor::memory::{Memory, Size};
+use mips_sim::processor::registers::Register;
+use mips_sim::processor::{Processor, Status};
+
+const BASE: u32 = 0xFFFF_0000;
+
+/// Offset, size and value of every store a [`Counter`] saw
+type Writes = Rc<RefCell<Vec<(u32, Size, u32)>>>;
+
+/// Eight bytes of registers, reads return the number of cycles so far plus the
+/// offset and every store is recorded
+struct Counter {
+    cycles: u32,
+    writes: Writes
+}
+
+impl Device for Counter {
+    fn name(&self) -> &'static str {
+        "counter"
+    }
+
+    fn size(&self) -> u32 {
+        8
+    }
+
+    fn read(&mut self, offset: u32, _size: Size) -> u32 {
+        self.cycles + offset
+    }
+
+    fn write(&mut self, offset: u32, size: Size, value: u32) {
+        self.writes.borrow_mut().push((offset, size, value));
+    }
+
+    fn tick(&mut self) {
+        self.cycles += 1;
+    }
+}
+
+fn counter() -> (Box<dyn Device>, Writes) {
+    let writes = Rc::new(RefCell::new(Vec::new()));
+    let device = Counter {
+        cycles: 0,
+        writes: writes.clone()
+    };
+    (Box::new(device), writes)
+}
+
+fn processor() -> (Processor, Writes) {
+    let (device, writes) = counter();
+    let console = Box::new(ScriptedConsole::new(""));
+    let mut processor = Processor::new_with_devices(console, vec![(BASE, device)]).unwrap();
+    processor.load_program(vec![
+        // lui $t1, 0xffff
+        0x3C09_FFFF,
+        // ori $t0, $zero, 0x41
+        0x3408_0041,
+        0x0000_0000,
+        0x0000_0000,
+        // sw $t0, 0($t1)
+        0xAD28_0000,
+        // sb $t0, 5($t1)
+        0xA128_0005,
+        // lw $t2, 4($t1)
+        0x8D2A_0004,
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    (processor, writes)
+}
+
+#[test]
+fn test_device_routing() {
+    let (mut processor, writes) = processor();
+    processor.set_data_cache(Cache::new(CacheConfig::new(256, 16, 1)).unwrap());
+    assert_eq!(processor.run(100), Status::Exited(0));
+
+    assert_eq!(*writes.borrow(), [(0, Size::Word, 0x41), (5, Size::Byte, 0x41)]);
+    // The load reached MEM in cycle 10
+    assert_eq!(processor.get_register(Register::T2), 10 + 4);
+    // Nothing was written to the memory underneath
+    assert_eq!(processor.get_memory().read_word(BASE), 0);
+    // Device registers bypass the cache
+    let stats = processor.get_data_cache().unwrap().get_stats();
+    assert_eq!(stats.reads + stats.writes, 0);
+}
+
+#[test]
+fn test_device_mapping() {
+    let mut memory = Memory::new();
+    assert_eq!(
+        memory.map_device(0x1001_0000, counter().0),
+        Err(DeviceMapError::Overlap {
+            device: "counter",
+            segment: "data"
+        })
+    );
+    // The heap could grow over it
+    assert_eq!(
+        memory.map_device(0x2000_0000, counter().0),
+        Err(DeviceMapError::Overlap {
+            device: "counter",
+            segment: "heap"
+        })
+    );
+    assert_eq!(
+        memory.map_device(0xFFFF_FFFC, counter().0),
+        Err(DeviceMapError::BadRange {
+            device: "counter",
+            start: 0xFFFF_FFFC
+        })
+    );
+
+    assert_eq!(memory.map_device(BASE, counter().0), Ok(()));
+    assert_eq!(
+        memory.map_device(BASE + 4, counter().0),
+        Err(DeviceMapError::Overlap {
+            device: "counter",
+            segment: "counter"
+        })
+    );
+    assert!(memory.is_device(BASE + 7));
+    assert!(!memory.is_device(BASE + 8));
+    assert_eq!(memory.segment(BASE).unwrap().name, "counter");
+    let devices: Vec<_> = memory
+        .get_devices()
+        .iter()
+        .map(|(start, device)| (start, device.name()))
+        .collect();
+    assert_eq!(devices, [(BASE, "counter")]);
+
+    memory.tick_devices();
+    assert_eq!(memory.load(BASE, Size::Word), Ok(1));
+    // Unmapped space next to the device still faults
+    assert!(memory.load(BASE + 8, Size::Word).is_err());
+}
+
+#[test]
+fn test_device_memory_map() {
+    let (processor, _) = processor();
+    assert!(processor
+        .get_memory()
+        .to_string()
+        .contains("Device counter [0xffff0000-0xffff0007] rw-\n"));
+
+    let mut debugger = Debugger::new(processor);
+    let map = debugger.execute("info map").unwrap();
+    assert!(map.starts_with("text [0x00400000-0x0fffffff] r-x\n"), "{}", map);
+    assert!(map.ends_with("counter [0xffff0000-0xffff0007] rw- device\n"), "{}", map);
+}
+
+#[test]
+fn test_step_back_over_device_access() {
+    let (processor, writes) = processor();
+    let mut debugger = Debugger::new(processor);
+    debugger.execute("step 6").unwrap();
+    let output = debugger.execute("rs 6").unwrap();
+    assert!(!output.contains("Reached"), "{}", output);
+    assert_eq!(debugger.get_processor().get_cycle_count(), 0);
+
+    debugger.execute("step 12").unwrap();
+    let output = debugger.execute("rs 12").unwrap();
+    assert!(output.contains("it accessed a device"), "{}", output);
+    // The lw reading the counter was in MEM during cycle 10
+    assert_eq!(debugger.get_processor().get_cycle_count(), 10);
+    assert!(debugger.get_processor().is_history_cut_by_device());
+    let output = debugger.execute("rc").unwrap();
+    assert!(output.contains("it accessed a device"), "{}", output);
+    assert_eq!(writes.borrow().len(), 2);
+}
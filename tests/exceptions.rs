@@ -0,0 +1,174 @@
+use mips_sim::processor::console::ScriptedConsole;
+use mips_sim::processor::cp0::{BAD_VADDR, EPC, STATUS, STATUS_UM};
+use mips_sim::processor::exception::{Exception, ExceptionCode, ExceptionHandler};
+use mips_sim::processor::memory::TEXT_BASE;
+use mips_sim::processor::registers::Register;
+use mips_sim::processor::segment::{Access, MemoryFault};
+use mips_sim::processor::{Processor, Status};
+
+const MFC0_K0_CAUSE: u32 = 0x401A_6800;
+// lui $t0, 0x9000
+const LUI_T0_KDATA: u32 = 0x3C08_9000;
+// lw $t1, 0($t0)
+const LW_T1_T0: u32 = 0x8D09_0000;
+
+/// Counts itself in $s1, leaves Cause in $s0, loads the first kernel data
+/// word into $k0 and returns past the faulting instruction
+const HANDLER: [u32; 10] = [
+    // mfc0 $s0, $13
+    0x4010_6800,
+    // mfc0 $k1, $14
+    0x401B_7000,
+    // lui $k0, 0x9000
+    0x3C1A_9000,
+    // addiu $s1, $s1, 1
+    0x2631_0001,
+    // addiu $k1, $k1, 4
+    0x277B_0004,
+    0x0000_0000,
+    0x0000_0000,
+    // mtc0 $k1, $14
+    0x409B_7000,
+    // lw $k0, 0($k0), still in kernel mode when eret decodes
+    0x8F5A_0000,
+    // eret
+    0x4200_0018
+];
+
+fn exit() -> Vec<u32> {
+    vec![
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]
+}
+
+#[test]
+fn test_kernel_segments_are_privileged() {
+    let mut processor = Processor::new();
+    processor.load_program(vec![LUI_T0_KDATA, 0, 0, LW_T1_T0]);
+    processor.load_kernel_data(vec![42]);
+    assert!(processor.is_user_mode());
+    assert_eq!(
+        processor.run(20),
+        Status::Faulted(MemoryFault::Privileged {
+            address: 0x9000_0000,
+            access: Access::Read
+        })
+    );
+
+    let mut processor = Processor::new();
+    processor.load_program(vec![LUI_T0_KDATA, 0, 0, LW_T1_T0]);
+    processor.load_kernel_data(vec![42]);
+    let status = processor.get_cp0().get(STATUS);
+    processor
+        .get_cp0_mut()
+        .set(STATUS, status & !STATUS_UM);
+    assert_eq!(processor.run(10), Status::Running);
+    assert_eq!(processor.get_register(Register::T1), 42);
+}
+
+#[test]
+fn test_cop0_unusable_halts() {
+    let mut processor = Processor::new();
+    processor.load_program(vec![MFC0_K0_CAUSE]);
+    let exception = Exception::new(ExceptionCode::CpU, TEXT_BASE);
+    assert_eq!(processor.run(10), Status::Exception(exception));
+    assert_eq!(
+        exception.to_string(),
+        "exception 11 [Coprocessor unusable] at 0x00400000"
+    );
+
+    let mut snapshot = Vec::new();
+    processor.save_snapshot(&mut snapshot).unwrap();
+    let mut restored = Processor::new();
+    restored.restore_snapshot(snapshot.as_slice()).unwrap();
+    assert_eq!(restored.get_status(), Status::Exception(exception));
+}
+
+#[test]
+fn test_builtin_handler() {
+    let console = ScriptedConsole::new("");
+    let mut processor = Processor::new_with_console(Box::new(console.clone()));
+    processor.set_exception_handler(ExceptionHandler::Builtin);
+    let mut program = vec![
+        // lw $t1, 0($zero)
+        0x8C09_0000,
+        MFC0_K0_CAUSE
+    ];
+    program.extend(exit());
+    processor.load_program(program);
+    assert_eq!(processor.run(50), Status::Exited(0));
+    assert_eq!(
+        console.output(),
+        "Exception occurred at PC=0x00400000\n  \
+         Bad address: 0x00000000\n  \
+         Exception 7  [Bad data address]  occurred and ignored\n\
+         Exception occurred at PC=0x00400004\n  \
+         Exception 11  [Coprocessor unusable]  occurred and ignored\n"
+    );
+    assert_eq!(processor.get_register(Register::K0), 0);
+}
+
+#[test]
+fn test_vectored_handler() {
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.load_kernel_program(HANDLER.to_vec());
+    processor.load_kernel_data(vec![0x1234]);
+    assert_eq!(processor.get_exception_handler(), ExceptionHandler::Vector);
+    let mut program = vec![MFC0_K0_CAUSE, LUI_T0_KDATA, 0, 0, LW_T1_T0];
+    program.extend(exit());
+    processor.load_program(program);
+
+    assert_eq!(processor.run(200), Status::Exited(0));
+    assert_eq!(processor.get_register(Register::S1), 2);
+    // The second exception was the load from kernel data
+    assert_eq!(processor.get_register(Register::S0) & 0x7C, 4 << 2);
+    assert_eq!(processor.get_cp0().get(BAD_VADDR), 0x9000_0000);
+    assert_eq!(processor.get_cp0().get(EPC), TEXT_BASE + 0x14);
+    assert_eq!(processor.get_register(Register::K0), 0x1234);
+    assert_eq!(processor.get_register(Register::T1), 0);
+    assert!(processor.is_user_mode());
+}
+
+#[test]
+fn test_reserved_instructions() {
+    let undefined = || {
+        let mut program = vec![
+            // Opcodes 0x3f and 1 (bltz) and function code 0x3f are not implemented
+            0xFC00_0000,
+            0x0400_0001,
+            0x0000_003F
+        ];
+        program.extend(exit());
+        program
+    };
+
+    let mut processor = Processor::new();
+    processor.load_program(undefined());
+    let exception = Exception::new(ExceptionCode::Ri, TEXT_BASE);
+    assert_eq!(processor.run(20), Status::Exception(exception));
+
+    let console = ScriptedConsole::new("");
+    let mut processor = Processor::new_with_console(Box::new(console.clone()));
+    processor.set_exception_handler(ExceptionHandler::Builtin);
+    processor.load_program(undefined());
+    assert_eq!(processor.run(50), Status::Exited(0));
+    let output = console.output();
+    for address in ["0x00400000", "0x00400004", "0x00400008"] {
+        assert!(output.contains(&format!("Exception occurred at PC={}\n", address)), "{}", output);
+    }
+    assert_eq!(output.matches("Exception 10  [Reserved instruction]").count(), 3, "{}", output);
+
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.load_kernel_program(HANDLER.to_vec());
+    processor.load_program(undefined());
+    assert_eq!(processor.run(200), Status::Exited(0));
+    assert_eq!(processor.get_register(Register::S1), 3);
+    assert_eq!(processor.get_register(Register::S0) & 0x7C, 10 << 2);
+    // The handler moved EPC past the last one before returning
+    assert_eq!(processor.get_cp0().get(EPC), TEXT_BASE + 12);
+}
@@ -0,0 +1,187 @@
+use std::io::{Cursor, Read, Write};
+use mips_sim::debugger::Debugger;
+use mips_sim::gdb::{target_description, Connection, GdbStub};
+use mips_sim::processor::console::ScriptedConsole;
+use mips_sim::processor::memory::TEXT_BASE;
+use mips_sim::processor::registers::Register;
+use mips_sim::processor::tlb::{Tlb, TlbEntry, ENTRY_LO_V};
+use mips_sim::processor::watchpoint::WatchKind;
+use mips_sim::processor::Processor;
+
+/// Feeds GDB's side of the conversation from a buffer and collects the replies
+struct Session {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>
+}
+
+impl Read for Session {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        self.input.read(buffer)
+    }
+}
+
+impl Write for Session {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        self.output.write(buffer)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for Session {}
+
+fn packet(data: &str) -> String {
+    let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    format!("${}#{:02x}", data, checksum)
+}
+
+/// Runs the packets through a stub and returns the reply payloads in order
+fn converse(packets: &[&str]) -> (Vec<String>, Debugger) {
+    let input: String = packets.iter().map(|data| packet(data)).collect();
+    serve(program(), input.into_bytes())
+}
+
+fn program() -> Processor {
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.load_program(vec![
+        // ori $t0, $zero, 7
+        0x3408_0007,
+        // ori $t1, $zero, 9
+        0x3409_0009,
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    processor
+}
+
+/// Serves raw `input` to a stub driving `processor`
+fn serve(processor: Processor, input: Vec<u8>) -> (Vec<String>, Debugger) {
+    let session = Session {
+        input: Cursor::new(input),
+        output: Vec::new()
+    };
+    let mut stub = GdbStub::new(Debugger::new(processor), session);
+    stub.serve().unwrap();
+    let output = String::from_utf8(stub.get_session().output.clone()).unwrap();
+    let replies = output
+        .split('$')
+        .skip(1)
+        .map(|reply| reply.split('#').next().unwrap().to_string())
+        .collect();
+    (replies, stub.into_debugger())
+}
+
+#[test]
+fn test_gdb_registers() {
+    let (replies, debugger) = converse(&["?", "g", "p25", "P9=44332211", "P25=0c004000", "p9"]);
+    assert_eq!(replies[0], "S05");
+    // 75 registers of 8 hex digits each
+    assert_eq!(replies[1].len(), 75 * 8);
+    // $sp is register 29, the pc register 37
+    assert_eq!(&replies[1][29 * 8..30 * 8], "fcffff7f");
+    assert_eq!(&replies[1][37 * 8..38 * 8], "00004000");
+    assert_eq!(replies[2], "00004000");
+    assert_eq!(replies[3], "OK");
+    assert_eq!(replies[5], "44332211");
+    let processor = debugger.get_processor();
+    assert_eq!(processor.get_register(Register::T1), 0x1122_3344);
+    assert_eq!(processor.get_program_counter(), TEXT_BASE + 12);
+}
+
+#[test]
+fn test_gdb_memory() {
+    let (replies, _) = converse(&["m400000,4", "M10010000,3:616263", "m10010000,4"]);
+    assert_eq!(replies[0], "07000834");
+    assert_eq!(replies[1], "OK");
+    assert_eq!(replies[2], "61626300");
+}
+
+#[test]
+fn test_gdb_mapped_memory() {
+    let mut processor = program();
+    processor.get_memory_mut().set_tlb(Tlb::new());
+    let tlb = processor.get_memory_mut().get_tlb_mut().unwrap();
+    tlb.set(0, TlbEntry::new(0x1001_0000, 0x0020_0000 | ENTRY_LO_V));
+    processor.get_memory_mut().write_word(0x0020_0ffc, 0x0403_0201);
+    let packets = ["m10010ffc,8", "M10010000,2:6162", "M10010fff,2:6162", "m10011000,4"];
+    let input: String = packets.iter().map(|data| packet(data)).collect();
+    let (replies, debugger) = serve(processor, input.into_bytes());
+    // Reads stop at the end of the mapped page
+    assert_eq!(replies[0], "01020304");
+    assert_eq!(replies[1], "OK");
+    assert_eq!(replies[2], "E14");
+    assert_eq!(replies[3], "E14");
+    let memory = debugger.get_processor().get_memory();
+    assert_eq!(memory.read_halfword(0x0020_0000), 0x6261);
+    assert_eq!(memory.read_byte(0x0020_0fff), 0x04);
+}
+
+#[test]
+fn test_gdb_breakpoints_and_stepping() {
+    let (replies, debugger) = converse(&["Z0,400008,4", "c", "p25", "z0,400008,4", "s", "c"]);
+    assert_eq!(replies[0], "OK");
+    assert_eq!(replies[1], "S05");
+    assert_eq!(replies[2], "08004000");
+    assert_eq!(replies[3], "OK");
+    assert_eq!(replies[4], "S05");
+    // exit syscall with status 0
+    assert_eq!(replies[5], "W00");
+    assert!(debugger.get_breakpoints().is_empty());
+}
+
+#[test]
+fn test_gdb_target_description() {
+    let (replies, _) = converse(&["qSupported:xmlRegisters=mips", "qXfer:features:read:target.xml:0,fff"]);
+    assert!(replies[0].contains("qXfer:features:read+"));
+    let description = target_description();
+    assert_eq!(replies[1], format!("l{}", description));
+    for name in ["org.gnu.gdb.mips.cp0", "\"hi\"", "\"lo\"", "\"status\"", "\"cause\""] {
+        assert!(description.contains(name), "missing {}", name);
+    }
+}
+
+#[test]
+fn test_gdb_bad_packets() {
+    let (replies, debugger) = converse(&[
+        "M10010000,2:0\u{e9}0",
+        "M10010000,2:+1+1",
+        "G0\u{e9}0",
+        "P9=zz",
+        "m400000,100000"
+    ]);
+    assert_eq!(replies[..4], ["E01", "E01", "E01", "E01"]);
+    // Cut short to fit a packet
+    assert_eq!(replies[4].len(), 0x1000);
+    assert_eq!(debugger.get_processor().get_memory().read_word(0x1001_0000), 0);
+}
+
+#[test]
+fn test_gdb_interrupt() {
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.load_program(vec![
+        // loop: j loop
+        0x0810_0000,
+        0x0000_0000
+    ]);
+    let mut input = packet("c").into_bytes();
+    input.push(0x03);
+    input.extend(packet("?").into_bytes());
+    let (replies, debugger) = serve(processor, input);
+    assert_eq!(replies, ["S02", "S02"]);
+    assert!(debugger.get_processor().get_cycle_count() >= 10_000);
+}
+
+#[test]
+fn test_gdb_watchpoint_kinds() {
+    let (replies, debugger) = converse(&["Z2,10010000,4", "Z3,10010000,4", "z2,10010000,4"]);
+    assert_eq!(replies, ["OK", "OK", "OK"]);
+    let watchpoints = debugger.get_processor().get_memory().get_watchpoints();
+    assert_eq!(watchpoints.len(), 1);
+    assert_eq!(watchpoints[0].kind, WatchKind::Read);
+}
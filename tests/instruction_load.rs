@@ -1,10 +1,8 @@
-use num_traits::FromPrimitive;
-use mips_sim;
-
 #[test]
+#[allow(clippy::unusual_byte_groupings)]
 fn test_instruction_load() {
     let test_instruction = 0b001101_00000_00010_0000000000000100;
-    let mut memory = mips_sim::processor::memory::Memory::new_with_capacity(256);
+    let mut memory = mips_sim::processor::memory::Memory::new();
     memory.write_word(0, test_instruction);
     let loaded_instruction = memory.read_word(0);
     assert_eq!(loaded_instruction, test_instruction);
@@ -20,3 +18,14 @@ fn test_instruction_load() {
     assert_eq!(instruction.addr, None);
 }
 
+#[test]
+fn test_instruction_disassemble() {
+    let disassemble =
+        |word| mips_sim::processor::instruction::Instruction::load(word).disassemble();
+    assert_eq!(disassemble(0x0000_0000), "nop");
+    assert_eq!(disassemble(0x3402_000A), "ori $v0, $zero, 0xa");
+    assert_eq!(disassemble(0x012A_4020), "add $t0, $t1, $t2");
+    assert_eq!(disassemble(0x8FBF_FFFC), "lw $ra, -4($sp)");
+    assert_eq!(disassemble(0x0C10_0004), "jal 0x00400010");
+    assert_eq!(disassemble(0xFC00_0000), ".word 0xfc000000");
+}
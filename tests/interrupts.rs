@@ -0,0 +1,228 @@
+use mips_sim::processor::console::ScriptedConsole;
+use mips_sim::processor::cp0::{
+    CAUSE,
+    COUNT,
+    EPC,
+    EXCEPTION_VECTOR,
+    STATUS,
+    STATUS_CU0,
+    STATUS_EXL,
+    STATUS_IE
+};
+use mips_sim::processor::instruction::Instruction;
+use mips_sim::processor::memory::TEXT_BASE;
+use mips_sim::processor::mmio::{KeyboardDisplay, MMIO_BASE};
+use mips_sim::processor::registers::Register;
+use mips_sim::processor::Processor;
+
+const MFC0_K0_CAUSE: u32 = 0x401A_6800;
+const MFC0_K1_EPC: u32 = 0x401B_7000;
+const MTC0_T0_COMPARE: u32 = 0x4088_5800;
+const MTC0_ZERO_COMPARE: u32 = 0x4080_5800;
+const ERET: u32 = 0x4200_0018;
+
+fn load_handler(processor: &mut Processor, handler: &[u32]) {
+    for (index, word) in handler.iter().enumerate() {
+        processor
+            .get_memory_mut()
+            .write_word(EXCEPTION_VECTOR + index as u32 * 4, *word);
+    }
+}
+
+#[test]
+fn test_cop0_disassembly() {
+    assert_eq!(Instruction::load(MFC0_K0_CAUSE).disassemble(), "mfc0 $k0, $13");
+    assert_eq!(Instruction::load(MTC0_T0_COMPARE).disassemble(), "mtc0 $t0, $11");
+    assert_eq!(Instruction::load(ERET).disassemble(), "eret");
+    assert_eq!(Instruction::load(MFC0_K0_CAUSE).destination(), Some(26));
+    assert_eq!(Instruction::load(MTC0_T0_COMPARE).sources(), [8]);
+}
+
+#[test]
+fn test_timer_interrupt() {
+    let mut processor = Processor::new();
+    processor.load_program(vec![
+        // ori $t0, $zero, 20
+        0x3408_0014,
+        0x0000_0000,
+        0x0000_0000,
+        MTC0_T0_COMPARE,
+        // loop: addiu $s0, $s0, 1
+        0x2610_0001,
+        0x0000_0000,
+        0x0000_0000,
+        // j loop
+        0x0810_0004
+    ]);
+    load_handler(
+        &mut processor,
+        &[
+            MFC0_K0_CAUSE,
+            MFC0_K1_EPC,
+            MTC0_ZERO_COMPARE,
+            // addiu $s1, $s1, 1
+            0x2631_0001,
+            ERET
+        ]
+    );
+    // The program sets Compare itself from user mode
+    let status = processor.get_cp0().get(STATUS);
+    processor
+        .get_cp0_mut()
+        .set(STATUS, status | STATUS_CU0);
+
+    processor.run(19);
+    assert_eq!(processor.get_cp0().get(COUNT), 19);
+    assert_eq!(processor.get_pending_interrupts(), 0);
+    processor.cycle();
+    // Count reached Compare, the fetch went to the exception vector
+    assert_eq!(processor.get_program_counter(), EXCEPTION_VECTOR + 4);
+    assert_ne!(processor.get_cp0().get(STATUS) & STATUS_EXL, 0);
+
+    processor.run(40);
+    let cause = processor.get_register(Register::K0);
+    assert_eq!(cause & 0xFF7C, 0x8000);
+    let epc = processor.get_register(Register::K1);
+    assert!((TEXT_BASE + 0x10..=TEXT_BASE + 0x1C).contains(&epc), "{:#x}", epc);
+    // Taken exactly once, writing Compare acknowledged it
+    assert_eq!(processor.get_register(Register::S1), 1);
+    assert_eq!(processor.get_cause() & 0x8000, 0);
+    assert_eq!(processor.get_cp0().get(STATUS) & STATUS_EXL, 0);
+    assert!(processor.get_program_counter() < EXCEPTION_VECTOR);
+}
+
+#[test]
+fn test_precise_interrupt() {
+    let mut processor = Processor::new();
+    // ori $sN, $zero, N for N from 0 to 7
+    let program: Vec<u32> = (0..8).map(|n| 0x3410_0000 | n << 16 | n).collect();
+    processor.load_program(program);
+    load_handler(&mut processor, &[ERET]);
+
+    let mut commits = Vec::new();
+    for cycle in 0..30 {
+        if cycle == 6 {
+            processor.raise_interrupt(3);
+        }
+        processor.cycle();
+        if processor.get_program_counter() == EXCEPTION_VECTOR + 4 {
+            processor.lower_interrupt(3);
+        }
+        if let Some(commit) = processor.get_last_commit() {
+            if commit.address < EXCEPTION_VECTOR {
+                commits.push(commit.address);
+            }
+        }
+    }
+    // Every instruction before EPC finished once, every one after it ran once
+    // after the handler returned
+    let expected: Vec<u32> = (0..8).map(|n| TEXT_BASE + n * 4).collect();
+    assert_eq!(commits[..8], expected[..]);
+    let epc = processor.get_cp0().get(EPC);
+    assert!((TEXT_BASE..TEXT_BASE + 32).contains(&epc), "{:#x}", epc);
+    for n in 0..8 {
+        let register = Register::from_name(&format!("s{}", n)).unwrap();
+        assert_eq!(processor.get_register(register), n);
+    }
+    assert_eq!(processor.get_cp0().get(CAUSE) & 0x7C, 0);
+}
+
+#[test]
+fn test_interrupt_masking() {
+    let mut processor = Processor::new();
+    processor.load_program(vec![0; 16]);
+    let status = processor.get_cp0().get(STATUS);
+    processor
+        .get_cp0_mut()
+        .set(STATUS, status & !STATUS_IE);
+    processor.raise_interrupt(2);
+    processor.cycle();
+    assert_eq!(processor.get_cause() & 0xFF00, 0x400);
+    assert_eq!(processor.get_pending_interrupts(), 0);
+
+    processor
+        .get_cp0_mut()
+        .set(STATUS, status & !0x400);
+    processor.cycle();
+    assert_eq!(processor.get_pending_interrupts(), 0);
+    assert!(processor.get_program_counter() < EXCEPTION_VECTOR);
+
+    processor.get_cp0_mut().set(STATUS, status);
+    assert_eq!(processor.get_pending_interrupts(), 0x400);
+    processor.cycle();
+    assert_eq!(processor.get_program_counter(), EXCEPTION_VECTOR + 4);
+    // Nothing else gets in while the first one is being handled
+    assert_eq!(processor.get_pending_interrupts(), 0);
+    processor.lower_interrupt(2);
+    processor.cycle();
+    assert_eq!(processor.get_cause() & 0xFF00, 0);
+}
+
+#[test]
+fn test_keyboard_interrupt() {
+    let console = ScriptedConsole::new("z");
+    let device = KeyboardDisplay::new(Box::new(console.clone()));
+    let mut processor =
+        Processor::new_with_devices(Box::new(console), vec![(MMIO_BASE, Box::new(device))])
+            .unwrap();
+    processor.load_program(vec![
+        // lui $t0, 0xffff
+        0x3C08_FFFF,
+        // ori $t1, $zero, 2
+        0x3409_0002,
+        0x0000_0000,
+        0x0000_0000,
+        // sw $t1, 0($t0), enabling keyboard interrupts
+        0xAD09_0000,
+        // loop: j loop
+        0x0810_0005
+    ]);
+    load_handler(
+        &mut processor,
+        &[
+            // lw $k0, 4($t0)
+            0x8D1A_0004,
+            MFC0_K1_EPC,
+            ERET
+        ]
+    );
+    processor.run(40);
+    assert_eq!(processor.get_register(Register::K0), 'z' as u32);
+    assert_eq!(processor.get_register(Register::K1), TEXT_BASE + 0x14);
+    assert_eq!(processor.get_pending_interrupts(), 0);
+}
+
+#[test]
+fn test_step_back_restores_interrupts() {
+    let mut processor = Processor::new();
+    processor.load_program(vec![0; 16]);
+    load_handler(&mut processor, &[ERET]);
+    processor.enable_history(100);
+    let state = |processor: &Processor| {
+        (
+            *processor.get_interrupt_controller(),
+            processor.get_cause(),
+            processor.get_program_counter()
+        )
+    };
+    // What each cycle started from, lines raised or lowered between cycles
+    // included
+    let mut states = Vec::new();
+    for cycle in 0..12 {
+        if cycle == 4 {
+            processor.raise_interrupt(3);
+        }
+        if cycle == 8 {
+            processor.lower_interrupt(3);
+        }
+        states.push(state(&processor));
+        processor.cycle();
+    }
+    assert!(states.iter().any(|(controller, ..)| controller.get_asserted() != 0));
+    assert!(processor.get_cp0().get(EPC) != 0);
+    while let Some(expected) = states.pop() {
+        processor.step_back();
+        assert_eq!(state(&processor), expected);
+    }
+    assert_eq!(processor.get_interrupt_controller().get_asserted(), 0);
+}
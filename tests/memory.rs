@@ -0,0 +1,93 @@
+use mips_sim::processor::memory::{Memory, SbrkError, Size, DATA_BASE, PAGE_SIZE, STACK_POINTER, TEXT_BASE};
+use mips_sim::processor::segment::{Access, MemoryFault, HEAP_BASE};
+use mips_sim::processor::Processor;
+
+#[test]
+fn test_memory_sparse_regions() {
+    let mut memory = Memory::new();
+    memory.write_word(TEXT_BASE, 0x3402_0004);
+    memory.write_word(DATA_BASE, 0xDEAD_BEEF);
+    memory.write_word(STACK_POINTER, 0x1234_5678);
+    assert_eq!(memory.read_word(TEXT_BASE), 0x3402_0004);
+    assert_eq!(memory.read_word(DATA_BASE), 0xDEAD_BEEF);
+    assert_eq!(memory.read_word(STACK_POINTER), 0x1234_5678);
+    assert_eq!(memory.read_word(0x2000_0000), 0);
+}
+
+#[test]
+fn test_memory_page_boundary() {
+    let mut memory = Memory::new();
+    let address = DATA_BASE + PAGE_SIZE - 2;
+    memory.write_word(address, 0xAABB_CCDD);
+    assert_eq!(memory.read_word(address), 0xAABB_CCDD);
+    assert_eq!(memory.read_halfword(address), 0xCCDD);
+    assert_eq!(memory.read_halfword(address + 2), 0xAABB);
+    memory.write_cstring(address, "mips");
+    assert_eq!(memory.read_cstring(address), "mips");
+}
+
+#[test]
+fn test_memory_segment_permissions() {
+    let mut memory = Memory::new();
+    memory.load_program(vec![0x3402_0004]);
+    assert_eq!(memory.fetch(TEXT_BASE), Ok(0x3402_0004));
+    assert_eq!(memory.load(TEXT_BASE, Size::Word), Ok(0x3402_0004));
+    assert_eq!(
+        memory.store(TEXT_BASE, Size::Word, 0),
+        Err(MemoryFault::Permission {
+            address: TEXT_BASE,
+            access: Access::Write,
+            segment: "text"
+        })
+    );
+    assert_eq!(
+        memory.fetch(DATA_BASE),
+        Err(MemoryFault::Permission {
+            address: DATA_BASE,
+            access: Access::Execute,
+            segment: "data"
+        })
+    );
+    assert_eq!(
+        memory.load(0x10, Size::Byte),
+        Err(MemoryFault::Unmapped {
+            address: 0x10,
+            access: Access::Read
+        })
+    );
+    assert_eq!(memory.store(STACK_POINTER, Size::Word, 7), Ok(()));
+    assert_eq!(memory.read_word(STACK_POINTER), 7);
+}
+
+#[test]
+fn test_processor_fetch_fault() {
+    let mut processor = Processor::new();
+    processor.set_entry_point(DATA_BASE);
+    processor.cycle();
+    assert_eq!(
+        processor.get_fault(),
+        Some(MemoryFault::Permission {
+            address: DATA_BASE,
+            access: Access::Execute,
+            segment: "data"
+        })
+    );
+}
+
+#[test]
+fn test_memory_sbrk() {
+    let mut memory = Memory::new();
+    assert!(memory.store(HEAP_BASE, Size::Word, 1).is_err());
+    assert_eq!(memory.sbrk(6), Ok(HEAP_BASE));
+    assert_eq!(memory.get_program_break(), HEAP_BASE + 8);
+    assert_eq!(memory.store(HEAP_BASE + 4, Size::Word, 1), Ok(()));
+    assert!(memory.store(HEAP_BASE + 8, Size::Word, 1).is_err());
+    assert_eq!(memory.sbrk(-8), Ok(HEAP_BASE + 8));
+    assert!(memory.store(HEAP_BASE, Size::Word, 1).is_err());
+    assert!(matches!(memory.sbrk(-4), Err(SbrkError::BelowHeap { .. })));
+    assert!(matches!(
+        memory.sbrk(i32::MAX),
+        Err(SbrkError::StackCollision { .. })
+    ));
+    assert_eq!(memory.get_program_break(), HEAP_BASE);
+}
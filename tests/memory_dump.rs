@@ -0,0 +1,61 @@
+use mips_sim::processor::memory::{Memory, Size, DATA_BASE, TEXT_BASE};
+use mips_sim::processor::memory_dump::MemoryDump;
+use mips_sim::processor::Processor;
+
+#[test]
+fn test_memory_dump_groupings() {
+    let mut memory = Memory::new();
+    memory.write_cstring(DATA_BASE, "Hello, world!\n");
+
+    let words = MemoryDump::new(&memory, DATA_BASE, 16, Size::Word).to_string();
+    assert_eq!(
+        words,
+        "0x10010000: 6c6c6548 77202c6f 646c726f 00000a21  Hello, world!...\n"
+    );
+
+    let bytes = MemoryDump::new(&memory, DATA_BASE, 20, Size::Byte).to_string();
+    let lines: Vec<&str> = bytes.lines().collect();
+    assert_eq!(
+        lines[0],
+        "0x10010000: 48 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21 0a 00 00  Hello, world!..."
+    );
+    // The short last row is padded so the ASCII column lines up
+    assert_eq!(lines[1], format!("0x10010010: 00 00 00 00{:36}  ....", ""));
+
+    // Lengths are rounded up to a whole group
+    let halves = MemoryDump::new(&memory, DATA_BASE, 3, Size::Halfword).to_string();
+    assert_eq!(halves, format!("0x10010000: 6548 6c6c{:30}  Hell\n", ""));
+}
+
+#[test]
+fn test_memory_dump_symbols_and_text() {
+    let mut processor = Processor::new();
+    processor.load_program(vec![
+        // lui $t1, 0x1001
+        0x3C09_1001,
+        // ori $t0, $zero, 5
+        0x3408_0005
+    ]);
+    processor.load_data(vec![0x6968_6968, 0x0000_0021]);
+    processor.add_symbol("main", TEXT_BASE);
+    processor.add_symbol("greeting", DATA_BASE);
+    processor.add_symbol("bang", DATA_BASE + 4);
+
+    let text = processor.memory_dump(TEXT_BASE, 8, Size::Word).to_string();
+    assert_eq!(
+        text,
+        "<main>:\n\
+         0x00400000: 3c091001  lui $t1, 0x1001\n\
+         0x00400004: 34080005  ori $t0, $zero, 0x5\n"
+    );
+
+    // Rows break at every label
+    let data = processor.memory_dump(DATA_BASE, 8, Size::Word).to_string();
+    let lines: Vec<&str> = data.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], "<greeting>:");
+    assert!(lines[1].starts_with("0x10010000: 69686968 "));
+    assert!(lines[1].ends_with("  hihi"));
+    assert_eq!(lines[2], "<bang>:");
+    assert!(lines[3].ends_with("  !..."));
+}
@@ -0,0 +1,127 @@
+use mips_sim::processor::console::ScriptedConsole;
+use mips_sim::processor::memory::{Memory, Size};
+use mips_sim::processor::mmio::{
+    KeyboardDisplay,
+    DISPLAY_INTERRUPT,
+    INTERRUPT_ENABLE,
+    KEYBOARD_INTERRUPT,
+    MMIO_BASE,
+    READY,
+    RECEIVER_CONTROL,
+    RECEIVER_DATA,
+    TRANSMITTER_CONTROL,
+    TRANSMITTER_DATA
+};
+use mips_sim::processor::registers::Register;
+use mips_sim::processor::{Processor, Status};
+
+fn memory(console: &ScriptedConsole, delay: u32) -> Memory {
+    let mut memory = Memory::new();
+    let device = KeyboardDisplay::new_with_delay(Box::new(console.clone()), delay);
+    memory.map_device(MMIO_BASE, Box::new(device)).unwrap();
+    memory
+}
+
+fn load(memory: &mut Memory, register: u32) -> u64 {
+    memory.load(MMIO_BASE + register, Size::Word).unwrap()
+}
+
+fn store(memory: &mut Memory, register: u32, value: u64) {
+    memory.store(MMIO_BASE + register, Size::Word, value).unwrap();
+}
+
+#[test]
+fn test_keyboard_receiver() {
+    let console = ScriptedConsole::new("ab");
+    let mut memory = memory(&console, 0);
+    // Keys only arrive as cycles pass
+    assert_eq!(load(&mut memory, RECEIVER_CONTROL), 0);
+    memory.tick_devices();
+    assert_eq!(load(&mut memory, RECEIVER_CONTROL), READY as u64);
+    assert_eq!(load(&mut memory, RECEIVER_DATA), 'a' as u64);
+    assert_eq!(load(&mut memory, RECEIVER_CONTROL), 0);
+
+    memory.tick_devices();
+    assert_eq!(memory.load(MMIO_BASE + RECEIVER_CONTROL, Size::Byte), Ok(READY as u64));
+    assert_eq!(memory.load(MMIO_BASE + RECEIVER_DATA, Size::Byte), Ok('b' as u64));
+    memory.tick_devices();
+    assert_eq!(load(&mut memory, RECEIVER_CONTROL), 0);
+    // The control register's ready bit is read only
+    store(&mut memory, RECEIVER_CONTROL, READY as u64);
+    assert_eq!(load(&mut memory, RECEIVER_CONTROL), 0);
+}
+
+#[test]
+fn test_display_transmitter() {
+    let console = ScriptedConsole::new("");
+    let mut memory = memory(&console, 2);
+    assert_eq!(load(&mut memory, TRANSMITTER_CONTROL), READY as u64);
+    memory
+        .store(MMIO_BASE + TRANSMITTER_DATA, Size::Byte, 'h' as u64)
+        .unwrap();
+    assert_eq!(console.output(), "h");
+    assert_eq!(load(&mut memory, TRANSMITTER_CONTROL), 0);
+    // Lost while the display is busy
+    store(&mut memory, TRANSMITTER_DATA, 'x' as u64);
+    memory.tick_devices();
+    assert_eq!(load(&mut memory, TRANSMITTER_CONTROL), 0);
+    memory.tick_devices();
+    assert_eq!(load(&mut memory, TRANSMITTER_CONTROL), READY as u64);
+    store(&mut memory, TRANSMITTER_DATA, 'i' as u64);
+    assert_eq!(console.output(), "hi");
+    // Nothing lands in the memory underneath
+    assert_eq!(memory.read_word(MMIO_BASE + TRANSMITTER_DATA), 0);
+}
+
+#[test]
+fn test_mmio_interrupts() {
+    let console = ScriptedConsole::new("");
+    let mut memory = memory(&console, 1);
+    store(&mut memory, RECEIVER_CONTROL, INTERRUPT_ENABLE as u64);
+    assert_eq!(load(&mut memory, RECEIVER_CONTROL), INTERRUPT_ENABLE as u64);
+    assert_eq!(memory.get_devices().interrupts(), 0);
+    console.push_input("k");
+    memory.tick_devices();
+    assert_eq!(memory.get_devices().interrupts(), 1 << KEYBOARD_INTERRUPT);
+    load(&mut memory, RECEIVER_DATA);
+    assert_eq!(memory.get_devices().interrupts(), 0);
+
+    store(&mut memory, TRANSMITTER_CONTROL, INTERRUPT_ENABLE as u64);
+    assert_eq!(memory.get_devices().interrupts(), 1 << DISPLAY_INTERRUPT);
+    store(&mut memory, TRANSMITTER_DATA, 'o' as u64);
+    assert_eq!(memory.get_devices().interrupts(), 0);
+    memory.tick_devices();
+    assert_eq!(memory.get_devices().interrupts(), 1 << DISPLAY_INTERRUPT);
+}
+
+#[test]
+fn test_mmio_echo() {
+    let console = ScriptedConsole::new("x");
+    let device = KeyboardDisplay::new(Box::new(console.clone()));
+    let mut processor =
+        Processor::new_with_devices(Box::new(console.clone()), vec![(MMIO_BASE, Box::new(device))])
+            .unwrap();
+    processor.load_program(vec![
+        // lui $t0, 0xffff
+        0x3C08_FFFF,
+        0x0000_0000,
+        0x0000_0000,
+        // lw $t1, 4($t0)
+        0x8D09_0004,
+        // lw $t2, 8($t0)
+        0x8D0A_0008,
+        0x0000_0000,
+        // sw $t1, 12($t0)
+        0xAD09_000C,
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    assert_eq!(processor.run(100), Status::Exited(0));
+    assert_eq!(console.output(), "x");
+    assert_eq!(processor.get_register(Register::T1), 'x' as u32);
+    assert_eq!(processor.get_register(Register::T2), READY);
+}
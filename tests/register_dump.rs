@@ -0,0 +1,58 @@
+use mips_sim::processor::register_dump::{RegisterDump, RegisterState, RegisterView};
+use mips_sim::processor::registers::Register;
+use mips_sim::processor::Processor;
+
+#[test]
+fn test_register_dump() {
+    let mut registers = [0; 32];
+    registers[8] = 0xFFFF_FFFF;
+    registers[29] = 0x7FFF_EFFC;
+    let state = RegisterState {
+        registers,
+        hi: 1,
+        lo: 2,
+        pc: 0x0040_0000
+    };
+
+    let hex = RegisterDump::new(state, RegisterView::Hex).to_string();
+    let lines: Vec<&str> = hex.lines().collect();
+    assert_eq!(lines.len(), 9);
+    // Columns are filled downwards, $t0 is the first row of the second column
+    assert!(lines[0].starts_with("$zero/0 0x00000000  $t0/8   0xffffffff"));
+    assert!(lines[5].contains("$sp/29  0x7fffeffc"));
+    assert_eq!(lines[8], "hi      0x00000001  lo      0x00000002  pc      0x00400000");
+
+    let signed = RegisterDump::new(state, RegisterView::Signed).to_string();
+    assert!(signed.contains("$t0/8            -1"));
+    let unsigned = RegisterDump::new(state, RegisterView::Unsigned).to_string();
+    assert!(unsigned.contains("$t0/8   4294967295"));
+    let all = RegisterDump::new(state, RegisterView::All).to_string();
+    assert_eq!(all.lines().count(), 17);
+    assert!(all.contains("$t0/8   0xffffffff          -1 4294967295"));
+}
+
+#[test]
+fn test_register_diff() {
+    let mut processor = Processor::new();
+    processor.load_program(vec![
+        // ori $t0, $zero, 5
+        0x3408_0005
+    ]);
+    for _ in 0..3 {
+        processor.cycle();
+    }
+    let unchanged = processor.register_diff(RegisterView::Hex).to_string();
+    assert!(unchanged.starts_with("No registers changed\n"));
+
+    processor.cycle();
+    assert_eq!(processor.get_register(Register::T0), 5);
+    let diff = processor.register_diff(RegisterView::Signed).to_string();
+    let lines: Vec<&str> = diff.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], "$t0/8             0 ->           5");
+    assert!(lines[1].starts_with("pc      "));
+
+    let previous = processor.get_previous_register_state();
+    assert_eq!(previous.registers[8], 0);
+    assert_eq!(processor.get_register_state().registers[8], 5);
+}
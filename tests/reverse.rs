@@ -0,0 +1,164 @@
+use mips_sim::debugger::Debugger;
+use mips_sim::processor::cache::{Cache, CacheConfig, CacheStats, LineState, LoggedAccess};
+use mips_sim::processor::cache_hierarchy::CacheLevel;
+use mips_sim::processor::console::ScriptedConsole;
+use mips_sim::processor::memory::{DATA_BASE, TEXT_BASE};
+use mips_sim::processor::registers::Register;
+use mips_sim::processor::watchpoint::WatchKind;
+use mips_sim::processor::Processor;
+
+fn processor() -> Processor {
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.load_program(vec![
+        // lui $t1, 0x1001
+        0x3C09_1001,
+        // ori $t0, $zero, 0x55
+        0x3408_0055,
+        0x0000_0000,
+        0x0000_0000,
+        // sw $t0, 8($t1)
+        0xAD28_0008,
+        // lw $t2, 8($t1)
+        0x8D2A_0008,
+        // ori $t0, $zero, 0x66
+        0x3408_0066,
+        0x0000_0000,
+        0x0000_0000,
+        // sw $t0, 8($t1)
+        0xAD28_0008
+    ]);
+    processor
+}
+
+fn state(processor: &Processor) -> (u32, u64, u32, u32, u32, u32) {
+    (
+        processor.get_program_counter(),
+        processor.get_cycle_count(),
+        processor.get_register(Register::T0),
+        processor.get_register(Register::T1),
+        processor.get_register(Register::T2),
+        processor.get_memory().read_word(DATA_BASE + 8)
+    )
+}
+
+#[test]
+fn test_step_back_restores_every_cycle() {
+    let mut processor = processor();
+    processor.enable_history(100);
+    let mut states = vec![state(&processor)];
+    for _ in 0..20 {
+        processor.cycle();
+        states.push(state(&processor));
+    }
+    assert_eq!(processor.get_memory().read_word(DATA_BASE + 8), 0x66);
+    assert_eq!(processor.get_history_len(), 20);
+    while let Some(expected) = states.pop() {
+        assert_eq!(state(&processor), expected);
+        if states.is_empty() {
+            assert!(processor.step_back().is_none());
+        } else {
+            assert!(processor.step_back().is_some());
+        }
+    }
+    // Running forward again gives the same results
+    processor.run(20);
+    assert_eq!(processor.get_register(Register::T2), 0x55);
+    assert_eq!(processor.get_memory().read_word(DATA_BASE + 8), 0x66);
+}
+
+type CacheState = (CacheStats, Vec<LoggedAccess>, Option<(usize, usize)>, Vec<LineState>);
+
+fn cache_state(processor: &Processor) -> Vec<CacheState> {
+    CacheLevel::ALL
+        .iter()
+        .filter_map(|level| processor.get_caches().get(*level))
+        .map(|cache| {
+            let ways = cache.get_config().associativity as usize;
+            let lines = (0..cache.get_config().sets() as usize)
+                .flat_map(|set| (0..ways).map(move |way| cache.get_line(set, way)))
+                .collect();
+            (
+                *cache.get_stats(),
+                cache.get_access_log().copied().collect(),
+                cache.get_touched(),
+                lines
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_step_back_restores_caches() {
+    let mut processor = processor();
+    processor.set_instruction_cache(Cache::new(CacheConfig::new(32, 16, 1)).unwrap());
+    processor.set_data_cache(Cache::new(CacheConfig::new(32, 16, 1)).unwrap());
+    let mut l2 = CacheConfig::new(256, 32, 2);
+    l2.latency = 2;
+    processor.set_l2_cache(Cache::new(l2).unwrap());
+    processor.set_memory_latency(3);
+    processor.enable_history(100);
+    let mut states = vec![(state(&processor), cache_state(&processor))];
+    for _ in 0..40 {
+        processor.cycle();
+        states.push((state(&processor), cache_state(&processor)));
+    }
+    let stats = *processor.get_pipeline_stats();
+    assert!(stats.instruction_cache_stalls > 0);
+    assert!(processor.get_l2_cache().unwrap().get_stats().read_misses > 0);
+    while let Some(expected) = states.pop() {
+        assert_eq!((state(&processor), cache_state(&processor)), expected);
+        processor.step_back();
+    }
+    // Replaying sees the same hits and misses, so stalls the same cycles
+    processor.run(40);
+    assert_eq!(*processor.get_pipeline_stats(), stats);
+}
+
+#[test]
+fn test_history_limit() {
+    let mut processor = processor();
+    processor.enable_history(4);
+    processor.run(10);
+    assert_eq!(processor.get_history_len(), 4);
+    while processor.step_back().is_some() {}
+    assert_eq!(processor.get_cycle_count(), 6);
+}
+
+#[test]
+fn test_reverse_continue() {
+    let mut debugger = Debugger::new(processor());
+    debugger.execute("step 20").unwrap();
+    debugger
+        .get_processor_mut()
+        .get_memory_mut()
+        .add_watchpoint(DATA_BASE + 8, 4, WatchKind::Write);
+    let output = debugger.execute("reverse-continue").unwrap();
+    assert!(output.contains("Watchpoint hit"), "{}", output);
+    assert_eq!(debugger.get_processor().get_memory().read_word(DATA_BASE + 8), 0x55);
+    let output = debugger.execute("rc").unwrap();
+    assert!(output.contains("Watchpoint hit"), "{}", output);
+    assert_eq!(debugger.get_processor().get_memory().read_word(DATA_BASE + 8), 0);
+
+    debugger.execute("unwatch 0x10010008").unwrap();
+    debugger.add_breakpoint(TEXT_BASE + 4);
+    debugger.execute("step 10").unwrap();
+    let output = debugger.execute("rc").unwrap();
+    assert!(output.contains("Breakpoint 1 reached"), "{}", output);
+    assert_eq!(debugger.get_processor().get_program_counter(), TEXT_BASE + 4);
+    let output = debugger.execute("rc").unwrap();
+    assert!(output.contains("start of the recorded history"), "{}", output);
+}
+
+#[test]
+fn test_reverse_stepi() {
+    let mut debugger = Debugger::new(processor());
+    debugger.execute("stepi 3").unwrap();
+    let commit = debugger.get_processor().get_last_commit().unwrap();
+    assert_eq!(commit.address, TEXT_BASE + 8);
+    debugger.execute("rsi").unwrap();
+    let commit = debugger.get_processor().get_last_commit().unwrap();
+    assert_eq!(commit.address, TEXT_BASE + 4);
+    assert_eq!(debugger.get_processor().get_register(Register::T0), 0x55);
+    debugger.execute("rsi").unwrap();
+    assert_eq!(debugger.get_processor().get_register(Register::T0), 0);
+}
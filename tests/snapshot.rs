@@ -0,0 +1,212 @@
+use mips_sim::processor::console::ScriptedConsole;
+use mips_sim::processor::cp0::{STATUS, STATUS_UM};
+use mips_sim::processor::exception::ExceptionHandler;
+use mips_sim::processor::memory::{Size, DATA_BASE};
+use mips_sim::processor::mmio::{
+    KeyboardDisplay,
+    INTERRUPT_ENABLE,
+    MMIO_BASE,
+    READY,
+    RECEIVER_CONTROL,
+    RECEIVER_DATA,
+    TRANSMITTER_CONTROL,
+    TRANSMITTER_DATA
+};
+use mips_sim::processor::registers::Register;
+use mips_sim::processor::snapshot::{SnapshotError, SNAPSHOT_VERSION};
+use mips_sim::processor::{Processor, Status};
+
+fn processor() -> Processor {
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.load_program(vec![
+        // lui $t1, 0x1001
+        0x3C09_1001,
+        // ori $t0, $zero, 0x55
+        0x3408_0055,
+        0x0000_0000,
+        0x0000_0000,
+        // sw $t0, 8($t1)
+        0xAD28_0008,
+        // lw $t2, 8($t1)
+        0x8D2A_0008,
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    processor
+}
+
+#[test]
+fn test_snapshot_resume() {
+    let mut original = processor();
+    original.run(6);
+    let mut snapshot = Vec::new();
+    original.save_snapshot(&mut snapshot).unwrap();
+    assert_eq!(original.run(100), Status::Exited(0));
+
+    // Restoring over a processor with a different program replaces all of it
+    let mut resumed = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    resumed.load_program(vec![0x3408_0099; 8]);
+    resumed.run(3);
+    resumed.restore_snapshot(snapshot.as_slice()).unwrap();
+    assert_eq!(resumed.get_cycle_count(), 6);
+    assert_eq!(resumed.run(100), Status::Exited(0));
+    assert_eq!(resumed.get_cycle_count(), original.get_cycle_count());
+    for register in [Register::T0, Register::T1, Register::T2, Register::V0, Register::Sp] {
+        assert_eq!(resumed.get_register(register), original.get_register(register));
+    }
+    assert_eq!(resumed.get_memory().read_word(DATA_BASE + 8), 0x55);
+}
+
+#[test]
+fn test_snapshot_file() {
+    let path = std::env::temp_dir().join(format!("mips-sim-snapshot-{}", std::process::id()));
+    let mut original = processor();
+    original.run(100);
+    original.save_snapshot_file(&path).unwrap();
+    let mut restored = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    restored.restore_snapshot_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(restored.get_status(), Status::Exited(0));
+    assert_eq!(restored.get_register(Register::T2), 0x55);
+}
+
+#[test]
+fn test_snapshot_rejects_bad_input() {
+    let mut processor = processor();
+    assert!(matches!(
+        processor.restore_snapshot(&b"not a snapshot"[..]),
+        Err(SnapshotError::NotASnapshot)
+    ));
+    let mut snapshot = Vec::new();
+    processor.save_snapshot(&mut snapshot).unwrap();
+    snapshot[8..12].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+    assert!(matches!(
+        processor.restore_snapshot(snapshot.as_slice()),
+        Err(SnapshotError::UnsupportedVersion(_))
+    ));
+    // Truncated files are rejected without touching the processor
+    processor.run(3);
+    let mut snapshot = Vec::new();
+    processor.save_snapshot(&mut snapshot).unwrap();
+    processor.run(2);
+    snapshot.truncate(snapshot.len() - 1);
+    assert!(matches!(
+        processor.restore_snapshot(snapshot.as_slice()),
+        Err(SnapshotError::Io(_))
+    ));
+    assert_eq!(processor.get_cycle_count(), 5);
+}
+
+#[test]
+fn test_snapshot_exception_handler() {
+    let mut original = processor();
+    original.set_exception_handler(ExceptionHandler::Builtin);
+    let status = original.get_cp0().get(STATUS);
+    original.get_cp0_mut().set(STATUS, status | STATUS_UM);
+    original.run(2);
+    assert!(original.get_memory().is_user_mode());
+    let mut snapshot = Vec::new();
+    original.save_snapshot(&mut snapshot).unwrap();
+
+    let mut restored = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    restored.restore_snapshot(snapshot.as_slice()).unwrap();
+    assert_eq!(restored.get_exception_handler(), ExceptionHandler::Builtin);
+    assert!(restored.get_memory().is_user_mode());
+}
+
+#[test]
+fn test_snapshot_version_1() {
+    let mut original = processor();
+    let status = original.get_cp0().get(STATUS);
+    original.get_cp0_mut().set(STATUS, status | STATUS_UM);
+    original.run(6);
+    let mut snapshot = Vec::new();
+    original.save_snapshot(&mut snapshot).unwrap();
+    // Version 1 ends the header at the program break, right after the one
+    // byte of a running status, without the mode bytes, TLB flag, machine,
+    // interrupt lines and devices, and ends EX/MEM and MEM/WB at their PC
+    snapshot[8..12].copy_from_slice(&1u32.to_le_bytes());
+    snapshot.drain(500..504);
+    snapshot.drain(483..487);
+    snapshot.drain(422..436);
+
+    let mut restored = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    restored.set_exception_handler(ExceptionHandler::Builtin);
+    restored.restore_snapshot(snapshot.as_slice()).unwrap();
+    assert_eq!(restored.get_cycle_count(), 6);
+    assert_eq!(restored.get_exception_handler(), ExceptionHandler::Builtin);
+    // The mode comes from Status
+    assert!(restored.get_memory().is_user_mode());
+    assert_eq!(restored.run(100), Status::Exited(0));
+    assert_eq!(restored.get_register(Register::T2), 0x55);
+
+    // Tags added after version 1 are not accepted in it
+    snapshot[417] = 4;
+    assert!(matches!(
+        restored.restore_snapshot(snapshot.as_slice()),
+        Err(SnapshotError::Corrupt("status"))
+    ));
+}
+
+/// The test program with the keyboard and display mapped, reading `input`
+fn mmio_processor(input: &str) -> Processor {
+    let mut processor = processor();
+    let device = KeyboardDisplay::new(Box::new(ScriptedConsole::new(input)));
+    processor
+        .get_memory_mut()
+        .map_device(MMIO_BASE, Box::new(device))
+        .unwrap();
+    processor
+}
+
+#[test]
+fn test_snapshot_devices() {
+    let mut original = mmio_processor("k");
+    let memory = original.get_memory_mut();
+    memory.tick_devices();
+    memory
+        .store(MMIO_BASE + RECEIVER_CONTROL, Size::Word, INTERRUPT_ENABLE as u64)
+        .unwrap();
+    memory
+        .store(MMIO_BASE + TRANSMITTER_DATA, Size::Byte, 'x' as u64)
+        .unwrap();
+    original.raise_interrupt(3);
+    let mut snapshot = Vec::new();
+    original.save_snapshot(&mut snapshot).unwrap();
+
+    let mut restored = mmio_processor("");
+    restored.restore_snapshot(snapshot.as_slice()).unwrap();
+    assert_eq!(restored.get_interrupt_controller().get_external(), 1 << 3);
+    let memory = restored.get_memory_mut();
+    let control = memory.load(MMIO_BASE + RECEIVER_CONTROL, Size::Word).unwrap();
+    assert_eq!(control, (READY | INTERRUPT_ENABLE) as u64);
+    assert_eq!(memory.load(MMIO_BASE + RECEIVER_DATA, Size::Word), Ok('k' as u64));
+    // The character sent before the snapshot still keeps the display busy
+    assert_eq!(memory.load(MMIO_BASE + TRANSMITTER_CONTROL, Size::Word), Ok(0));
+
+    // The device state has nowhere to go without the device
+    assert!(matches!(
+        processor().restore_snapshot(snapshot.as_slice()),
+        Err(SnapshotError::WrongMachine)
+    ));
+}
+
+#[test]
+fn test_snapshot_version_5_with_devices() {
+    let mut snapshot = Vec::new();
+    processor().save_snapshot(&mut snapshot).unwrap();
+    // Version 5 ends the header at the machine, before the interrupt lines
+    // and devices
+    snapshot[8..12].copy_from_slice(&5u32.to_le_bytes());
+    snapshot.drain(430..436);
+
+    assert!(matches!(
+        mmio_processor("").restore_snapshot(snapshot.as_slice()),
+        Err(SnapshotError::NoDeviceState)
+    ));
+    processor().restore_snapshot(snapshot.as_slice()).unwrap();
+}
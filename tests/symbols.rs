@@ -0,0 +1,36 @@
+use mips_sim::processor::symbols::SymbolTable;
+
+#[test]
+fn test_symbol_lookup() {
+    let mut symbols = SymbolTable::new();
+    symbols.insert("main", 0x0040_0000);
+    symbols.insert("loop", 0x0040_0010);
+    assert_eq!(symbols.address("loop"), Some(0x0040_0010));
+    assert_eq!(symbols.name(0x0040_0000), Some("main"));
+    assert_eq!(symbols.nearest(0x0040_0018), Some(("loop", 8)));
+    assert_eq!(symbols.nearest(0x0030_0000), None);
+
+    // Moving a label frees its old address
+    symbols.insert("loop", 0x0040_0020);
+    assert_eq!(symbols.name(0x0040_0010), None);
+    assert_eq!(symbols.address("loop"), Some(0x0040_0020));
+}
+
+#[test]
+fn test_symbol_replaced_at_address() {
+    let mut symbols = SymbolTable::new();
+    symbols.insert("start", 0x0040_0000);
+    symbols.insert("main", 0x0040_0000);
+    assert_eq!(symbols.name(0x0040_0000), Some("main"));
+    assert_eq!(symbols.address("start"), None);
+
+    // Adding the replaced name again leaves the other label alone
+    symbols.insert("start", 0x0040_0008);
+    assert_eq!(symbols.name(0x0040_0000), Some("main"));
+    assert_eq!(symbols.address("main"), Some(0x0040_0000));
+    assert_eq!(symbols.address("start"), Some(0x0040_0008));
+    assert_eq!(symbols.iter().count(), 2);
+
+    symbols.insert("main", 0x0040_0000);
+    assert_eq!(symbols.iter().collect::<Vec<_>>(), [(0x0040_0000, "main"), (0x0040_0008, "start")]);
+}
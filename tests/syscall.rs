@@ -0,0 +1,308 @@
+use std::path::{Path, PathBuf};
+use mips_sim::processor::console::ScriptedConsole;
+use mips_sim::processor::exception::ExceptionHandler;
+use mips_sim::processor::instruction::Instruction;
+use mips_sim::processor::memory::{DATA_BASE, TEXT_BASE};
+use mips_sim::processor::registers::Register;
+use mips_sim::processor::segment::{Access, MemoryFault, HEAP_BASE};
+use mips_sim::processor::watchpoint::{WatchKind, WatchTarget, WatchpointHit};
+use mips_sim::processor::{Processor, Status};
+
+const NOP: u32 = 0;
+const SYSCALL: u32 = 0x0000_000C;
+const V0: u32 = 2;
+const A0: u32 = 4;
+const A1: u32 = 5;
+const A2: u32 = 6;
+const S0: u32 = 16;
+const S1: u32 = 17;
+const S2: u32 = 18;
+const SP: u32 = 29;
+
+fn ori(rt: u32, rs: u32, imm: u32) -> u32 {
+    (0x0D << 26) | (rs << 21) | (rt << 16) | imm
+}
+
+fn lui(rt: u32, imm: u32) -> u32 {
+    (0x0F << 26) | (rt << 16) | imm
+}
+
+fn or(rd: u32, rs: u32, rt: u32) -> u32 {
+    (rs << 21) | (rt << 16) | (rd << 11) | 0x25
+}
+
+/// Sets `$v0` and leaves enough nops for it to be written back before the syscall decodes
+fn syscall(code: u32) -> Vec<u32> {
+    vec![ori(V0, 0, code), NOP, NOP, SYSCALL]
+}
+
+/// An empty directory of its own under the system temp directory
+fn scratch(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("mips-sim-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+/// Opens `path` with `flags` through syscall 13 and returns the descriptor
+fn open(sandbox: &Path, path: &str, flags: u32) -> u32 {
+    let mut program = vec![lui(A0, DATA_BASE >> 16), ori(A1, 0, flags)];
+    program.extend(syscall(13));
+    program.extend([or(S0, V0, 0)]);
+    program.extend(syscall(10));
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.set_sandbox(sandbox);
+    processor.load_program(program);
+    processor.get_memory_mut().write_cstring(DATA_BASE, path);
+    assert_eq!(processor.run(1000), Status::Exited(0));
+    processor.get_register(Register::S0)
+}
+
+fn run(program: Vec<u32>, input: &str) -> (Status, String) {
+    let console = ScriptedConsole::new(input);
+    let mut processor = Processor::new_with_console(Box::new(console.clone()));
+    processor.load_data(vec![u32::from_le_bytes(*b"hi\n\0")]);
+    processor.load_program(program);
+    let status = processor.run(1000);
+    (status, console.output())
+}
+
+#[test]
+fn test_syscall_scripted_io() {
+    let mut program = Vec::new();
+    program.extend(syscall(5));
+    program.extend([or(A0, V0, 0), NOP, NOP]);
+    program.extend(syscall(1));
+    program.extend([ori(A0, 0, '!' as u32)]);
+    program.extend(syscall(11));
+    program.extend([lui(A0, DATA_BASE >> 16)]);
+    program.extend(syscall(4));
+    program.extend([ori(A0, 0, 3)]);
+    program.extend(syscall(17));
+    let (status, output) = run(program, "-42\n");
+    assert_eq!(status, Status::Exited(3));
+    assert_eq!(output, "-42!hi\n");
+}
+
+#[test]
+fn test_syscall_read_string() {
+    let mut program = vec![lui(A0, DATA_BASE >> 16), ori(A1, 0, 4)];
+    program.extend(syscall(8));
+    program.extend(syscall(4));
+    program.extend(syscall(10));
+    let (status, output) = run(program, "mips sim\n");
+    assert_eq!(status, Status::Exited(0));
+    assert_eq!(output, "mip");
+}
+
+#[test]
+fn test_syscall_print_formats() {
+    let mut program = vec![ori(A0, 0, 0xBEEF)];
+    program.extend(syscall(34));
+    program.extend(syscall(36));
+    program.extend(syscall(10));
+    let (status, output) = run(program, "");
+    assert_eq!(status, Status::Exited(0));
+    assert_eq!(output, "0x0000beef48879");
+}
+
+#[test]
+fn test_syscall_sbrk() {
+    let mut program = vec![ori(A0, 0, 16)];
+    program.extend(syscall(9));
+    program.extend([or(S0, V0, 0)]);
+    // A stack pointer just above the new break stops the heap short of it
+    program.extend([lui(SP, HEAP_BASE >> 16), NOP, NOP, ori(SP, SP, 0x20), ori(A0, 0, 0x100)]);
+    program.extend(syscall(9));
+    program.extend([or(S1, V0, 0)]);
+    program.extend(syscall(10));
+    let console = ScriptedConsole::new("");
+    let mut processor = Processor::new_with_console(Box::new(console));
+    processor.load_program(program);
+    assert_eq!(processor.run(1000), Status::Exited(0));
+    assert_eq!(processor.get_register(Register::S0), HEAP_BASE);
+    assert_eq!(processor.get_register(Register::S1), u32::MAX);
+    assert_eq!(processor.get_memory().get_program_break(), HEAP_BASE + 16);
+}
+
+#[test]
+fn test_syscall_files() {
+    let sandbox = scratch("files");
+    let mut program = vec![lui(A0, DATA_BASE >> 16), ori(A1, 0, 1)];
+    program.extend(syscall(13));
+    // write(fd, "hello", 5)
+    program.extend([or(S0, V0, 0), lui(A1, DATA_BASE >> 16), NOP, NOP]);
+    program.extend([or(A0, S0, 0), ori(A1, A1, 8), ori(A2, 0, 5)]);
+    program.extend(syscall(15));
+    program.extend([or(S1, V0, 0)]);
+    program.extend(syscall(16));
+    // read(fd, buffer, 64) after opening it again for reading
+    program.extend([lui(A0, DATA_BASE >> 16), ori(A1, 0, 0)]);
+    program.extend(syscall(13));
+    program.extend([or(S0, V0, 0), lui(A1, DATA_BASE >> 16), NOP, NOP]);
+    program.extend([or(A0, S0, 0), ori(A1, A1, 0x20), ori(A2, 0, 64)]);
+    program.extend(syscall(14));
+    program.extend([or(S2, V0, 0)]);
+    program.extend(syscall(16));
+    program.extend(syscall(10));
+
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.set_sandbox(&sandbox);
+    processor.load_program(program);
+    processor.get_memory_mut().write_cstring(DATA_BASE, "out.txt");
+    processor.get_memory_mut().write_cstring(DATA_BASE + 8, "hello");
+    assert_eq!(processor.run(1000), Status::Exited(0));
+    assert_eq!(processor.get_register(Register::S1), 5);
+    assert_eq!(processor.get_register(Register::S2), 5);
+    assert_eq!(processor.get_memory().read_cstring(DATA_BASE + 0x20), "hello");
+    assert_eq!(std::fs::read_to_string(sandbox.join("out.txt")).unwrap(), "hello");
+    std::fs::remove_dir_all(sandbox).unwrap();
+}
+
+#[test]
+fn test_syscall_sandbox() {
+    let sandbox = scratch("sandbox");
+    let outside = scratch("outside");
+    std::fs::write(outside.join("secret.txt"), "secret").unwrap();
+    std::fs::write(sandbox.join("inside.txt"), "inside").unwrap();
+    assert_eq!(open(&sandbox, "inside.txt", 0), 3);
+    assert_eq!(open(&sandbox, "./inside.txt", 0), 3);
+    assert_eq!(open(&sandbox, "missing.txt", 0), u32::MAX);
+    assert_eq!(open(&sandbox, "/etc/passwd", 0), u32::MAX);
+    assert_eq!(open(&sandbox, "../inside.txt", 0), u32::MAX);
+    assert_eq!(open(&sandbox, "", 0), u32::MAX);
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(outside.join("secret.txt"), sandbox.join("link")).unwrap();
+        std::os::unix::fs::symlink(&outside, sandbox.join("dir")).unwrap();
+        std::os::unix::fs::symlink(outside.join("new.txt"), sandbox.join("dangling")).unwrap();
+        assert_eq!(open(&sandbox, "link", 0), u32::MAX);
+        assert_eq!(open(&sandbox, "dir/secret.txt", 0), u32::MAX);
+        assert_eq!(open(&sandbox, "dir/created.txt", 1), u32::MAX);
+        assert_eq!(open(&sandbox, "dangling", 1), u32::MAX);
+        assert!(!outside.join("created.txt").exists());
+        assert!(!outside.join("new.txt").exists());
+    }
+    std::fs::remove_dir_all(sandbox).unwrap();
+    std::fs::remove_dir_all(outside).unwrap();
+}
+
+#[test]
+fn test_syscall_read_is_capped() {
+    // A length of 4 GiB only fills what is left of the data segment
+    let mut program = vec![ori(A0, 0, 0), lui(A1, 0x1003), NOP, NOP, ori(A1, A1, 0xFFFC)];
+    program.extend([lui(A2, 0xFFFF)]);
+    program.extend(syscall(14));
+    program.extend([or(S0, V0, 0)]);
+    program.extend(syscall(10));
+    let console = ScriptedConsole::new("abcdef\n");
+    let mut processor = Processor::new_with_console(Box::new(console));
+    processor.load_program(program);
+    assert_eq!(processor.run(1000), Status::Exited(0));
+    assert_eq!(processor.get_register(Register::S0), 4);
+    assert_eq!(processor.get_memory().read_word(0x1003_FFFC), u32::from_le_bytes(*b"abcd"));
+    assert_eq!(processor.get_memory().read_byte(0x1004_0000), 0);
+}
+
+#[test]
+fn test_syscall_bad_buffer() {
+    // read_string into the text segment
+    let mut program = vec![lui(A0, TEXT_BASE >> 16), ori(A1, 0, 4)];
+    program.extend(syscall(8));
+    program.extend(syscall(10));
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("mips\n")));
+    processor.load_program(program.clone());
+    let status = processor.run(1000);
+    let fault = match status {
+        Status::Faulted(fault) => fault,
+        _ => panic!("{:?}", status)
+    };
+    assert!(matches!(fault, MemoryFault::Permission { .. }));
+    assert_eq!((fault.address(), fault.access()), (TEXT_BASE, Access::Write));
+    assert_eq!(processor.get_memory().read_word(TEXT_BASE), program[0]);
+
+    // The builtin handler reports it like SPIM and carries on
+    let console = ScriptedConsole::new("mips\n");
+    let mut processor = Processor::new_with_console(Box::new(console.clone()));
+    processor.set_exception_handler(ExceptionHandler::Builtin);
+    processor.load_program(program);
+    assert_eq!(processor.run(1000), Status::Exited(0));
+    assert!(console.output().starts_with(&format!(
+        "Exception occurred at PC={:#010x}\n  Bad address: {:#010x}\n",
+        TEXT_BASE + 20,
+        TEXT_BASE
+    )));
+}
+
+#[test]
+fn test_syscall_buffer_watchpoint() {
+    let mut program = vec![lui(A0, DATA_BASE >> 16), ori(A1, 0, 8)];
+    program.extend(syscall(8));
+    program.extend(syscall(10));
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("mips\n")));
+    processor.load_program(program);
+    processor
+        .get_memory_mut()
+        .add_watchpoint(DATA_BASE + 2, 1, WatchKind::Write);
+    assert_eq!(
+        processor.run(1000),
+        Status::Watchpoint(WatchpointHit {
+            pc: TEXT_BASE + 20,
+            instruction: Instruction::load(SYSCALL),
+            target: WatchTarget::Memory(DATA_BASE + 2),
+            access: WatchKind::Write,
+            value: b'p' as u32
+        })
+    );
+}
+
+#[test]
+fn test_syscall_float_moves() {
+    // mtc1 $t0, $f12
+    const MTC1: u32 = 0x4488_6000;
+    // mfc1 $s0, $f0
+    const MFC1: u32 = 0x4410_0000;
+    let mut program = vec![lui(8, 0x3FC0), NOP, NOP, MTC1];
+    program.extend(syscall(2));
+    program.extend(syscall(6));
+    program.extend([MFC1]);
+    program.extend(syscall(10));
+    let console = ScriptedConsole::new("2.25\n");
+    let mut processor = Processor::new_with_console(Box::new(console.clone()));
+    processor.load_program(program);
+    assert_eq!(processor.run(1000), Status::Exited(0));
+    assert_eq!(console.output(), "1.5");
+    assert_eq!(processor.get_register(Register::S0), 2.25f32.to_bits());
+    assert_eq!(Instruction::load(MTC1).disassemble(), "mtc1 $t0, $f12");
+    assert_eq!(Instruction::load(MFC1).disassemble(), "mfc1 $s0, $f0");
+}
+
+/// Seeds generator 1 with `seed` and draws an int, one below 10 and a float
+fn random(seed: u32) -> (u32, u32, u32) {
+    let mut program = vec![ori(A0, 0, 1), ori(A1, 0, seed)];
+    program.extend(syscall(40));
+    program.extend(syscall(41));
+    program.extend([or(S0, A0, 0), ori(A0, 0, 1), ori(A1, 0, 10)]);
+    program.extend(syscall(42));
+    program.extend([or(S1, A0, 0), ori(A0, 0, 1)]);
+    program.extend(syscall(43));
+    program.extend(syscall(10));
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.load_program(program);
+    assert_eq!(processor.run(1000), Status::Exited(0));
+    (
+        processor.get_register(Register::S0),
+        processor.get_register(Register::S1),
+        processor.get_float_registers().get(0)
+    )
+}
+
+#[test]
+fn test_syscall_random() {
+    let first = random(42);
+    assert_eq!(random(42), first);
+    assert_ne!(random(7), first);
+    assert!(first.1 < 10);
+    let value = f32::from_bits(first.2);
+    assert!((0.0..1.0).contains(&value), "{}", value);
+}
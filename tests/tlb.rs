@@ -0,0 +1,335 @@
+use mips_sim::processor::cache::{Cache, CacheConfig};
+use mips_sim::processor::console::ScriptedConsole;
+use mips_sim::processor::cp0::{CAUSE, STATUS, STATUS_UM};
+use mips_sim::processor::exception::ExceptionHandler;
+use mips_sim::processor::instruction::Instruction;
+use mips_sim::processor::memory::{Memory, Size};
+use mips_sim::processor::registers::Register;
+use mips_sim::processor::segment::{Access, MemoryFault};
+use mips_sim::processor::tlb::{
+    Tlb,
+    TlbEntry,
+    ENTRY_LO_D,
+    ENTRY_LO_G,
+    ENTRY_LO_N,
+    ENTRY_LO_V,
+    TLB_WIRED
+};
+use mips_sim::processor::{Processor, Status};
+
+const TLBR: u32 = 0x4200_0001;
+const TLBWI: u32 = 0x4200_0002;
+const TLBWR: u32 = 0x4200_0006;
+const TLBP: u32 = 0x4200_0008;
+const ERET: u32 = 0x4200_0018;
+
+fn write_words(processor: &mut Processor, physical: u32, words: &[u32]) {
+    for (index, word) in words.iter().enumerate() {
+        processor
+            .get_memory_mut()
+            .write_word(physical + index as u32 * 4, *word);
+    }
+}
+
+#[test]
+fn test_tlb_disassembly() {
+    assert_eq!(Instruction::load(TLBR).disassemble(), "tlbr");
+    assert_eq!(Instruction::load(TLBWI).disassemble(), "tlbwi");
+    assert_eq!(Instruction::load(TLBWR).disassemble(), "tlbwr");
+    assert_eq!(Instruction::load(TLBP).disassemble(), "tlbp");
+    assert_eq!(Instruction::load(TLBP).destination(), None);
+}
+
+#[test]
+fn test_translation() {
+    let mut memory = Memory::new();
+    memory.set_tlb(Tlb::new());
+    let tlb = memory.get_tlb_mut().unwrap();
+    tlb.set(0, TlbEntry::new(0x0040_0000, 0x0010_0000 | ENTRY_LO_V));
+    tlb.set(1, TlbEntry::new(0x0060_0000, 0x0010_0000));
+    // Only mapped for ASID 1
+    tlb.set(2, TlbEntry::new(0x0070_0000 | 1 << 6, 0x0010_0000 | ENTRY_LO_V));
+    tlb.set(3, TlbEntry::new(0x0080_0000 | 1 << 6, 0x0010_0000 | ENTRY_LO_V | ENTRY_LO_G));
+    memory.write_word(0x0010_0010, 7);
+    memory.set_user_mode(true);
+
+    assert_eq!(memory.load(0x0040_0010, Size::Word), Ok(7));
+    assert_eq!(
+        memory.store(0x0040_0010, Size::Word, 1),
+        Err(MemoryFault::TlbModified { address: 0x0040_0010 })
+    );
+    assert_eq!(
+        memory.load(0x0050_0000, Size::Word),
+        Err(MemoryFault::TlbMiss {
+            address: 0x0050_0000,
+            access: Access::Read
+        })
+    );
+    assert_eq!(
+        memory.fetch(0x0060_0000),
+        Err(MemoryFault::TlbInvalid {
+            address: 0x0060_0000,
+            access: Access::Execute
+        })
+    );
+    assert!(memory.load(0x0070_0010, Size::Word).is_err());
+    assert_eq!(memory.load(0x0080_0010, Size::Word), Ok(7));
+    memory.get_tlb_mut().unwrap().set_asid(1 << 6);
+    assert_eq!(memory.load(0x0070_0010, Size::Word), Ok(7));
+    assert_eq!(
+        memory.load(0x8010_0010, Size::Word),
+        Err(MemoryFault::Privileged {
+            address: 0x8010_0010,
+            access: Access::Read
+        })
+    );
+
+    // kseg0 and kseg1 both reach physical memory directly
+    memory.set_user_mode(false);
+    assert_eq!(memory.load(0x8010_0010, Size::Word), Ok(7));
+    memory.store(0xA010_0010, Size::Word, 9).unwrap();
+    assert_eq!(memory.read_word(0x0010_0010), 9);
+}
+
+#[test]
+fn test_tlb_instructions() {
+    let mut processor = Processor::new();
+    processor.get_memory_mut().set_tlb(Tlb::new());
+    write_words(
+        &mut processor,
+        0x1000,
+        &[
+            // lui $k0, 0x0040
+            0x3C1A_0040,
+            // lui $k1, 0x0010
+            0x3C1B_0010,
+            // ori $t0, $zero, 0x500, entry 5
+            0x3408_0500,
+            0x0000_0000,
+            // ori $k1, $k1, 0x600
+            0x377B_0600,
+            0x0000_0000,
+            0x0000_0000,
+            // mtc0 $k0, $10
+            0x409A_5000,
+            // mtc0 $k1, $2
+            0x409B_1000,
+            // mtc0 $t0, $0
+            0x4088_0000,
+            TLBWI,
+            // mtc0 $zero, $2
+            0x4080_1000,
+            TLBP,
+            TLBR,
+            // mfc0 $s0, $0
+            0x4010_0000,
+            // mfc0 $s1, $2
+            0x4011_1000
+        ]
+    );
+    // Running from kseg0 in kernel mode
+    let status = processor.get_cp0().get(STATUS);
+    processor
+        .get_cp0_mut()
+        .set(STATUS, status & !STATUS_UM);
+    processor.set_program_counter(0x8000_1000);
+    assert_eq!(processor.run(30), Status::Running);
+
+    let entry = processor.get_memory().get_tlb().unwrap().get(5);
+    assert_eq!(entry, TlbEntry::new(0x0040_0000, 0x0010_0600));
+    assert_eq!(processor.get_register(Register::S0), 5 << 8);
+    assert_eq!(processor.get_register(Register::S1), 0x0010_0600);
+    assert_eq!(
+        processor.get_memory().get_tlb().unwrap().to_string(),
+        " 5: 0x00400000 -> 0x00100000 asid  0 -DV-\n"
+    );
+}
+
+#[test]
+fn test_tlb_instructions_need_a_tlb() {
+    let mut processor = Processor::new();
+    processor.set_program_counter(0x8000_0200);
+    processor.get_memory_mut().write_word(0x8000_0200, TLBWR);
+    let status = processor.get_cp0().get(STATUS);
+    processor
+        .get_cp0_mut()
+        .set(STATUS, status & !STATUS_UM);
+    match processor.run(10) {
+        Status::Exception(exception) => assert_eq!(exception.code as u8, 10),
+        status => panic!("{:?}", status)
+    }
+}
+
+/// A program storing to an unmapped page under a handler that identity maps
+/// whatever missed
+fn refill_processor() -> Processor {
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.get_memory_mut().set_tlb(Tlb::new());
+    processor.set_exception_handler(ExceptionHandler::Vector);
+    // Identity maps the page that missed, at the refill vector in kseg0
+    write_words(
+        &mut processor,
+        0,
+        &[
+            // mfc0 $k0, $10
+            0x401A_5000,
+            // addiu $s1, $s1, 1
+            0x2631_0001,
+            0x0000_0000,
+            // ori $k0, $k0, 0x600
+            0x375A_0600,
+            0x0000_0000,
+            0x0000_0000,
+            // mtc0 $k0, $2
+            0x409A_1000,
+            TLBWR,
+            ERET
+        ]
+    );
+    processor.load_program(vec![
+        // lui $t0, 0x1001
+        0x3C08_1001,
+        // ori $t1, $zero, 0x55
+        0x3409_0055,
+        0x0000_0000,
+        0x0000_0000,
+        // sw $t1, 0($t0)
+        0xAD09_0000,
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    processor
+}
+
+#[test]
+fn test_refill_handler() {
+    let mut processor = refill_processor();
+    assert_eq!(processor.run(200), Status::Exited(0));
+    // One refill for the text page, one for the store
+    assert_eq!(processor.get_register(Register::S1), 2);
+    assert_eq!(processor.get_memory().read_word(0x1001_0000), 0x55);
+    assert_eq!(processor.get_cp0().get(CAUSE) & 0x7C, 3 << 2);
+    let tlb = processor.get_memory().get_tlb().unwrap();
+    let valid: Vec<usize> = (0..64).filter(|index| tlb.get(*index).is_valid()).collect();
+    assert_eq!(valid.len(), 2);
+    assert!(valid.iter().all(|index| *index >= TLB_WIRED));
+    let data = TlbEntry::new(0x1001_0000, 0x1001_0000 | ENTRY_LO_D | ENTRY_LO_V);
+    assert!(tlb.get_entries().contains(&data));
+}
+
+#[test]
+fn test_refill_step_back() {
+    let mut processor = refill_processor();
+    processor.enable_history(1000);
+    assert_eq!(processor.run(200), Status::Exited(0));
+    let entries = processor.get_memory().get_tlb().unwrap().get_entries().to_vec();
+    while processor.step_back().is_some() {}
+    assert_eq!(processor.get_cycle_count(), 0);
+    let tlb = processor.get_memory().get_tlb().unwrap();
+    assert!(tlb.get_entries().iter().all(|entry| !entry.is_valid()));
+
+    // Replaying refills the same entries
+    assert_eq!(processor.run(200), Status::Exited(0));
+    assert_eq!(processor.get_register(Register::S1), 2);
+    assert_eq!(processor.get_memory().get_tlb().unwrap().get_entries(), entries);
+}
+
+#[test]
+fn test_tlb_snapshot() {
+    let mut original = refill_processor();
+    original.run(200);
+    original.get_memory_mut().get_tlb_mut().unwrap().set_asid(3 << 6);
+    let mut snapshot = Vec::new();
+    original.save_snapshot(&mut snapshot).unwrap();
+
+    let mut restored = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    restored.restore_snapshot(snapshot.as_slice()).unwrap();
+    let tlb = restored.get_memory().get_tlb().unwrap();
+    let expected = original.get_memory().get_tlb().unwrap();
+    assert_eq!(tlb.get_entries(), expected.get_entries());
+    assert_eq!(tlb.get_asid(), 3 << 6);
+
+    // A snapshot without a TLB turns translation off again
+    let mut snapshot = Vec::new();
+    Processor::new().save_snapshot(&mut snapshot).unwrap();
+    restored.restore_snapshot(snapshot.as_slice()).unwrap();
+    assert!(restored.get_memory().get_tlb().is_none());
+}
+
+#[test]
+fn test_caches_see_physical_addresses() {
+    let mut processor = Processor::new();
+    processor.get_memory_mut().set_tlb(Tlb::new());
+    processor.set_data_cache(Cache::new(CacheConfig::new(256, 16, 1)).unwrap());
+    let tlb = processor.get_memory_mut().get_tlb_mut().unwrap();
+    tlb.set(0, TlbEntry::new(0x0040_0000, 0x0010_0000 | ENTRY_LO_V));
+    tlb.set(1, TlbEntry::new(0x0060_0000, 0x0010_0000 | ENTRY_LO_V | ENTRY_LO_N));
+    processor.get_memory_mut().write_word(0x0010_0010, 7);
+    // The same word through kseg0, kseg1, a cached and an uncached page
+    let mut program = Vec::new();
+    for (upper, register) in [(0x8010, 9), (0xA010, 10), (0x0040, 11), (0x0060, 12)] {
+        // lui $t0, upper
+        program.push(0x3C08_0000 | upper);
+        program.extend([0, 0]);
+        // lw $register, 0x10($t0)
+        program.push(0x8D00_0010 | register << 16);
+    }
+    write_words(&mut processor, 0x1000, &program);
+    let status = processor.get_cp0().get(STATUS);
+    processor
+        .get_cp0_mut()
+        .set(STATUS, status & !STATUS_UM);
+    processor.set_program_counter(0x8000_1000);
+    processor.run(30);
+
+    for register in [Register::T1, Register::T2, Register::T3, Register::T4] {
+        assert_eq!(processor.get_register(register), 7);
+    }
+    // kseg1 and the N page go around the cache, the mapped page hits the
+    // block kseg0 brought in
+    let stats = processor.get_data_cache().unwrap().get_stats();
+    assert_eq!(stats.reads, 2);
+    assert_eq!(stats.read_misses, 1);
+    assert!(processor.get_data_cache().unwrap().contains(0x0010_0010));
+}
+
+#[test]
+fn test_syscalls_see_virtual_addresses() {
+    let console = ScriptedConsole::new("");
+    let mut processor = Processor::new_with_console(Box::new(console.clone()));
+    processor.get_memory_mut().set_tlb(Tlb::new());
+    let tlb = processor.get_memory_mut().get_tlb_mut().unwrap();
+    tlb.set(0, TlbEntry::new(0x0040_0000, 0x0040_0000 | ENTRY_LO_V));
+    // The data page lives somewhere else entirely
+    tlb.set(1, TlbEntry::new(0x1001_0000, 0x0020_0000 | ENTRY_LO_V));
+    processor.get_memory_mut().write_cstring(0x0020_0000, "mapped");
+    processor.get_memory_mut().write_cstring(0x1001_0000, "physical");
+    processor.load_program(vec![
+        // lui $a0, 0x1001
+        0x3C04_1001,
+        // ori $v0, $zero, 4
+        0x3402_0004,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C,
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    assert_eq!(processor.run(100), Status::Exited(0));
+    assert_eq!(console.output(), "mapped");
+
+    let memory = processor.get_memory();
+    assert_eq!(memory.lookup(0x1001_0004), Some(0x0020_0004));
+    assert_eq!(memory.lookup(0x1002_0000), None);
+    assert_eq!(memory.peek_cstring(0x1001_0000), "mapped");
+    assert_eq!(memory.peek_word(0x1002_0000), 0);
+}
@@ -0,0 +1,128 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+use mips_sim::processor::console::ScriptedConsole;
+use mips_sim::processor::memory::{DATA_BASE, TEXT_BASE};
+use mips_sim::processor::{Processor, Status};
+use serde_json::Value;
+
+/// Collects trace output where the test can still read it
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buffer)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn trace() -> Vec<Value> {
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.load_program(vec![
+        // lui $t1, 0x1001
+        0x3C09_1001,
+        // ori $t0, $zero, 0x55
+        0x3408_0055,
+        0x0000_0000,
+        0x0000_0000,
+        // sw $t0, 8($t1)
+        0xAD28_0008,
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    let buffer = SharedBuffer::default();
+    processor.set_trace_writer(Box::new(buffer.clone()));
+    assert_eq!(processor.run(100), Status::Exited(0));
+    let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+    output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_trace_one_record_per_cycle() {
+    let records = trace();
+    for (i, record) in records.iter().enumerate() {
+        assert_eq!(record["cycle"], i as u64 + 1);
+        assert_eq!(record["stall"], false);
+        assert_eq!(record["flush"], false);
+    }
+    assert_eq!(records[0]["pc"], TEXT_BASE);
+    assert_eq!(records[0]["stages"]["if"]["pc"], TEXT_BASE);
+    assert_eq!(records[0]["stages"]["if"]["instruction"], "lui $t1, 0x1001");
+    assert_eq!(records[0]["stages"]["id"], Value::Null);
+    let last = records.last().unwrap();
+    assert_eq!(last["status"], "exited");
+    assert_eq!(last["exit_code"], 0);
+}
+
+#[test]
+fn test_trace_writes() {
+    let records = trace();
+    let store = records
+        .iter()
+        .find(|record| !record["memory"].as_array().unwrap().is_empty())
+        .unwrap();
+    assert_eq!(store["stages"]["mem"]["instruction"], "sw $t0, 8($t1)");
+    assert_eq!(store["memory"][0]["address"], DATA_BASE + 8);
+    assert_eq!(store["memory"][0]["size"], 4);
+    assert_eq!(store["memory"][0]["value"], 0x55);
+    let writeback = records
+        .iter()
+        .find(|record| record["registers"][0]["register"] == "t0")
+        .unwrap();
+    assert_eq!(writeback["registers"][0]["value"], 0x55);
+    assert_eq!(writeback["stages"]["wb"]["pc"], TEXT_BASE + 4);
+}
+
+#[test]
+fn test_trace_exception() {
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    // mfc0 $k0, $13 in user mode
+    processor.load_program(vec![0x401A_6800]);
+    let buffer = SharedBuffer::default();
+    processor.set_trace_writer(Box::new(buffer.clone()));
+    assert!(matches!(processor.run(10), Status::Exception(_)));
+    let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+    let last: Value = serde_json::from_str(output.lines().last().unwrap()).unwrap();
+    assert_eq!(last["status"], "exception");
+    assert_eq!(last["exception"], "exception 11 [Coprocessor unusable] at 0x00400000");
+}
+
+#[test]
+fn test_trace_branch_stall() {
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.load_program(vec![
+        // beq $t0, $t1, 1
+        0x1109_0001,
+        // ori $v0, $zero, 10
+        0x3402_000A,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    let buffer = SharedBuffer::default();
+    processor.set_trace_writer(Box::new(buffer.clone()));
+    assert_eq!(processor.run(100), Status::Exited(0));
+    let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+    let causes: Vec<Value> = output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["stall_cause"].clone())
+        .collect();
+    // Fetch waits while the branch decodes and for the bubble behind it
+    assert_eq!(causes[..4], [Value::Null, "control".into(), "control".into(), Value::Null]);
+    let stats = processor.get_pipeline_stats();
+    assert_eq!(stats.control_stalls, 2);
+    assert_eq!(stats.data_hazard_stalls, 0);
+    assert!(stats.to_string().contains("2 branch"));
+}
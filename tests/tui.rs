@@ -0,0 +1,102 @@
+use mips_sim::debugger::Debugger;
+use mips_sim::processor::console::ScriptedConsole;
+use mips_sim::processor::memory::TEXT_BASE;
+use mips_sim::processor::registers::Register;
+use mips_sim::processor::Processor;
+use mips_sim::tui::Tui;
+use ratatui::backend::TestBackend;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::Terminal;
+
+fn tui() -> Tui {
+    let console = ScriptedConsole::new("");
+    let mut processor = Processor::new_with_console(Box::new(console.clone()));
+    processor.load_program(vec![
+        // ori $a0, $zero, 42
+        0x3404_002A,
+        // ori $v0, $zero, 1
+        0x3402_0001,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    processor.add_symbol("main", TEXT_BASE);
+    Tui::new(Debugger::new(processor), console)
+}
+
+fn press(tui: &mut Tui, character: char) {
+    tui.handle_key(KeyEvent::new(KeyCode::Char(character), KeyModifiers::NONE));
+}
+
+fn screen(tui: &Tui) -> String {
+    let mut terminal = Terminal::new(TestBackend::new(120, 40)).unwrap();
+    terminal.draw(|frame| tui.draw(frame)).unwrap();
+    let buffer = terminal.backend().buffer();
+    let mut text = String::new();
+    for y in 0..buffer.area.height {
+        for x in 0..buffer.area.width {
+            text += buffer[(x, y)].symbol();
+        }
+        text += "\n";
+    }
+    text
+}
+
+#[test]
+fn test_tui_panes() {
+    let tui = tui();
+    let screen = screen(&tui);
+    for title in ["Pipeline", "Disassembly", "Registers", "Memory around $sp", "Console"] {
+        assert!(screen.contains(title), "missing {} in\n{}", title, screen);
+    }
+    assert!(screen.contains("main:"));
+    assert!(screen.contains("=> 0x00400000  ori $a0, $zero, 0x2a"));
+    assert!(screen.contains("$sp   7ffffffc"));
+}
+
+#[test]
+fn test_tui_stepping() {
+    let mut tui = tui();
+    press(&mut tui, 'n');
+    assert_eq!(tui.get_debugger().get_processor().get_register(Register::A0), 42);
+    assert!(screen(&tui).contains("WB  0x00400000  ori $a0, $zero, 0x2a"));
+    press(&mut tui, 'r');
+    assert_eq!(tui.get_debugger().get_processor().get_register(Register::A0), 0);
+    for _ in 0..8 {
+        press(&mut tui, 's');
+    }
+    // print_int output lands in the console pane
+    assert!(screen(&tui).contains("│42 "), "{}", screen(&tui));
+}
+
+#[test]
+fn test_tui_waits_for_input() {
+    let console = ScriptedConsole::new("");
+    let mut processor = Processor::new_with_console(Box::new(console.clone()));
+    processor.load_program(vec![
+        // ori $v0, $zero, 5
+        0x3402_0005,
+        0x0000_0000,
+        0x0000_0000,
+        // syscall
+        0x0000_000C
+    ]);
+    let mut tui = Tui::new(Debugger::new(processor), console);
+    for _ in 0..10 {
+        press(&mut tui, 'n');
+        if screen(&tui).contains("input>") {
+            break;
+        }
+    }
+    // The read_int has not run yet
+    let processor = tui.get_debugger().get_processor();
+    assert!(processor.wants_input());
+    assert_eq!(processor.get_register(Register::V0), 5);
+    let cycles = processor.get_cycle_count();
+    press(&mut tui, '7');
+    tui.handle_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+    assert_eq!(tui.get_debugger().get_processor().get_cycle_count(), cycles);
+    press(&mut tui, 's');
+    assert_eq!(tui.get_debugger().get_processor().get_register(Register::V0), 7);
+}
@@ -0,0 +1,75 @@
+use mips_sim::processor::console::ScriptedConsole;
+use mips_sim::processor::instruction::Instruction;
+use mips_sim::processor::memory::{DATA_BASE, TEXT_BASE};
+use mips_sim::processor::registers::Register;
+use mips_sim::processor::watchpoint::{WatchKind, WatchTarget, WatchpointHit};
+use mips_sim::processor::{Processor, Status};
+
+const STORE: u32 = 0xAD28_0008;
+
+fn processor() -> Processor {
+    let mut processor = Processor::new_with_console(Box::new(ScriptedConsole::new("")));
+    processor.load_program(vec![
+        // lui $t1, 0x1001
+        0x3C09_1001,
+        // ori $t0, $zero, 0x55
+        0x3408_0055,
+        0x0000_0000,
+        0x0000_0000,
+        // sw $t0, 8($t1)
+        STORE,
+        // lw $t2, 8($t1)
+        0x8D2A_0008
+    ]);
+    processor
+}
+
+#[test]
+fn test_memory_watchpoint() {
+    let mut processor = processor();
+    processor
+        .get_memory_mut()
+        .add_watchpoint(DATA_BASE + 8, 4, WatchKind::Write);
+    assert_eq!(
+        processor.run(100),
+        Status::Watchpoint(WatchpointHit {
+            pc: TEXT_BASE + 16,
+            instruction: Instruction::load(STORE),
+            target: WatchTarget::Memory(DATA_BASE + 8),
+            access: WatchKind::Write,
+            value: 0x55
+        })
+    );
+    assert_eq!(processor.get_memory().read_word(DATA_BASE + 8), 0x55);
+    // The load only reads so the write watchpoint stays quiet
+    let status = processor.run(20);
+    assert!(!matches!(status, Status::Watchpoint(_)));
+    assert_eq!(processor.get_register(Register::T2), 0x55);
+}
+
+#[test]
+fn test_register_watchpoint() {
+    let mut processor = processor();
+    processor
+        .get_registers_mut()
+        .add_watchpoint(Register::T0, WatchKind::Read);
+    match processor.run(100) {
+        Status::Watchpoint(hit) => {
+            assert_eq!(hit.pc, TEXT_BASE + 16);
+            assert_eq!(hit.target, WatchTarget::Register(Register::T0));
+            assert_eq!(hit.access, WatchKind::Read);
+            assert_eq!(hit.value, 0x55);
+        }
+        status => panic!("Expected a watchpoint hit, got {:?}", status)
+    }
+    processor
+        .get_registers_mut()
+        .add_watchpoint(Register::T2, WatchKind::Write);
+    match processor.run(100) {
+        Status::Watchpoint(hit) => {
+            assert_eq!(hit.pc, TEXT_BASE + 20);
+            assert_eq!(hit.access, WatchKind::Write);
+        }
+        status => panic!("Expected a watchpoint hit, got {:?}", status)
+    }
+}